@@ -1,4 +1,16 @@
 fn main() {
     prost_build::compile_protos(&["proto/state.proto"], &["proto/"])
         .expect("Failed to compile proto files");
+
+    // C ABI (src/ffi.rs) のヘッダはリポジトリの `include/wacret.h` が正.
+    // src/ffi.rs を変えたときだけ `--features header` で再生成する
+    #[cfg(feature = "header")]
+    {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        cbindgen::generate(&crate_dir)
+            .expect("Failed to generate include/wacret.h")
+            .write_to_file(format!("{}/include/wacret.h", crate_dir));
+    }
 }
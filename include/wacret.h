@@ -0,0 +1,183 @@
+#ifndef WACRET_H
+#define WACRET_H
+
+/* Generated by cbindgen from src/ffi.rs. Do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define WACRET_OP_OTHER 0
+
+#define WACRET_OP_LOCAL_GET 1
+
+#define WACRET_OP_I32_CONST 2
+
+#define WACRET_OP_F32_CONST 3
+
+#define WACRET_OP_I64_CONST 4
+
+#define WACRET_OP_F64_CONST 5
+
+#define WACRET_OP_CALL 6
+
+enum WasmType
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : uint8_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  WASM_TYPE_ANY = 0,
+  WASM_TYPE_U8 = 1,
+  WASM_TYPE_I32,
+  WASM_TYPE_F32,
+  WASM_TYPE_I64,
+  WASM_TYPE_F64,
+  WASM_TYPE_V128,
+  WASM_TYPE_FUNC_REF,
+  WASM_TYPE_EXTERN_REF,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum WasmType WasmType;
+#else
+typedef uint8_t WasmType;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+/**
+ * 読み込んだスタックテーブル (msgpack形式またはバイナリ形式)
+ */
+typedef struct WacretStackTable WacretStackTable;
+
+/**
+ * スタックの1要素. `kind` は `WACRET_OP_*`, `value` は kind ごとの即値
+ */
+typedef struct WacretStackEntry {
+  uint8_t kind;
+  /**
+   * WasmType
+   */
+  uint8_t ty;
+  uint64_t value;
+} WacretStackEntry;
+
+/**
+ * ラベルスタックの1要素. アドレスは関数本体の先頭から、スタックポインタと値の数はセル単位
+ */
+typedef struct WacretLabel {
+  uint32_t begin;
+  uint32_t target;
+  uint32_t stack_pointer;
+  uint32_t cell_num;
+} WacretLabel;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * msgpack形式 (`create --v2`) またはバイナリ形式 (`create --v2 --binary`) の
+ * スタックテーブルを読み込む. `data` はコピーされる. 失敗時は NULL
+ *
+ * # Safety
+ * `data` は `len` バイト読める領域を指すこと
+ */
+struct WacretStackTable *wacret_stack_table_load(const uint8_t *data, size_t len);
+
+/**
+ * ファイルからスタックテーブルを読み込む. 失敗時は NULL
+ *
+ * # Safety
+ * `path` はNUL終端された文字列であること
+ */
+struct WacretStackTable *wacret_stack_table_open(const char *path);
+
+/**
+ * # Safety
+ * `table` は `wacret_stack_table_load/open` が返したポインタか NULL であること
+ */
+void wacret_stack_table_free(struct WacretStackTable *table);
+
+/**
+ * 関数の数 (importを含む). 失敗時は -1
+ *
+ * # Safety
+ * `table` は有効なスタックテーブルであること
+ */
+int64_t wacret_stack_table_func_count(const struct WacretStackTable *table);
+
+/**
+ * テーブルが `wasm` から生成されたものなら 0, そうでなければ -1
+ *
+ * # Safety
+ * `table` は有効なスタックテーブル, `wasm` は `len` バイト読める領域であること
+ */
+int32_t wacret_stack_table_check_wasm(const struct WacretStackTable *table,
+                                      const uint8_t *wasm,
+                                      size_t len);
+
+/**
+ * 関数 `fidx` のローカル (引数を含む) の型を `types` に書き込み、ローカルの数を返す.
+ * 失敗時は -1
+ *
+ * # Safety
+ * `table` は有効なスタックテーブル, `types` は `cap` 要素書き込める領域か NULL (cap = 0) であること
+ */
+int64_t wacret_get_locals(const struct WacretStackTable *table,
+                          uint32_t fidx,
+                          uint8_t *types,
+                          size_t cap);
+
+/**
+ * 関数 `fidx` のコードオフセット `offset` におけるオペランドスタック (底から) を
+ * `entries` に書き込み、要素数を返す. 失敗時は -1
+ *
+ * # Safety
+ * `table` は有効なスタックテーブル, `entries` は `cap` 要素書き込める領域か NULL (cap = 0) であること
+ */
+int64_t wacret_get_stack(const struct WacretStackTable *table,
+                         uint32_t fidx,
+                         uint32_t offset,
+                         struct WacretStackEntry *entries,
+                         size_t cap);
+
+/**
+ * 関数 `fidx` のコードオフセット `offset` におけるラベルスタック (関数フレームから) を
+ * `labels` に書き込み、要素数を返す. 失敗時は -1
+ *
+ * # Safety
+ * `table` は有効なスタックテーブル, `labels` は `cap` 要素書き込める領域か NULL (cap = 0) であること
+ */
+int64_t wacret_get_labels(const struct WacretStackTable *table,
+                          uint32_t fidx,
+                          uint32_t offset,
+                          struct WacretLabel *labels,
+                          size_t cap);
+
+/**
+ * 関数 `fidx` のコードオフセット `offset` で各ローカル、各スタックの値 (底から) が生きていれば 1,
+ * 死んでいれば 0 を、ローカル、スタックの順に `live` に書き込み、要素数を返す.
+ * 死んでいる値は保存せず、リストア時は0で埋めてよい. 失敗時は -1
+ *
+ * # Safety
+ * `table` は有効なスタックテーブル, `live` は `cap` 要素書き込める領域か NULL (cap = 0) であること
+ */
+int64_t wacret_get_live(const struct WacretStackTable *table,
+                        uint32_t fidx,
+                        uint32_t offset,
+                        uint8_t *live,
+                        size_t cap);
+
+/**
+ * 呼び出したスレッドで最後に起きたエラーのメッセージ. なければ NULL.
+ * 次にエラーが起きるまで有効
+ */
+const char *wacret_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* WACRET_H */
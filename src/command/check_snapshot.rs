@@ -0,0 +1,223 @@
+use crate::command::view::utils::state::{CallStack, CallStackEntry, TypedArray};
+use crate::core::function_v2::{is_call_site, Function};
+use crate::core::module;
+use crate::core::stack_table::{Offset, StackTables};
+use crate::core::val::WasmType;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use camino::Utf8PathBuf;
+use prost::Message;
+use wasmparser::Operator;
+
+use anyhow::{Context, Result};
+
+/// Check that every frame of a `CallStack` snapshot fits the wasm module it is restored into
+pub fn check_snapshot(wasm: Utf8PathBuf, snapshot: Utf8PathBuf) -> Result<()> {
+    let wasm_buf = std::fs::read(&wasm).with_context(|| format!("Failed to read {}", wasm))?;
+    let snapshot_buf = std::fs::read(&snapshot).with_context(|| format!("Failed to read {}", snapshot))?;
+    let call_stack = CallStack::decode(&snapshot_buf[..])
+        .with_context(|| format!("Failed to decode {} as a CallStack", snapshot))?;
+
+    let errors = check_call_stack(&wasm_buf, &call_stack)?;
+    for error in &errors {
+        println!("{}", error);
+    }
+
+    if !errors.is_empty() {
+        anyhow::bail!("{} problem(s) found in {} against {}", errors.len(), snapshot, wasm);
+    }
+    println!("{} frame(s) of {} fit {}", call_stack.entries.len(), snapshot, wasm);
+
+    Ok(())
+}
+
+/// 関数ごとの命令の境界と、呼び出し命令のオフセット → 直接呼び出す関数 (call_indirectなどは `None`)
+struct Code {
+    boundaries: BTreeSet<Offset>,
+    call_sites: BTreeMap<Offset, Option<u32>>,
+}
+
+/// Returns a description of every problem found, each naming the frame and the field.
+/// Frames are ordered from the outermost caller; every frame but the last must be inside a call
+pub fn check_call_stack(wasm: &[u8], call_stack: &CallStack) -> Result<Vec<String>> {
+    let buf = wasm.to_vec();
+    let m = module::new_module(&buf)?;
+    let tables = StackTables::from_wasm(wasm, false)?;
+
+    let mut codes = BTreeMap::new();
+    for f in m.new_function_v2()? {
+        let f = match f {
+            Function::BytecodeFunction(f) => f,
+            Function::ImportFunction(_) => continue,
+        };
+        let mut code = Code { boundaries: BTreeSet::new(), call_sites: BTreeMap::new() };
+        for (offset, op) in f.operators()? {
+            code.boundaries.insert(offset);
+            if is_call_site(&op) {
+                let callee = match op {
+                    Operator::Call { function_index } | Operator::ReturnCall { function_index } => Some(function_index),
+                    _ => None,
+                };
+                code.call_sites.insert(offset, callee);
+            }
+        }
+        codes.insert(f.fidx, code);
+    }
+
+    let mut errors = vec![];
+    let innermost = call_stack.entries.len().saturating_sub(1);
+    for (depth, entry) in call_stack.entries.iter().enumerate() {
+        let mut error = |field: &str, message: String| errors.push(format!("frame {}: {}: {}", depth, field, message));
+
+        let Some(pc) = &entry.pc else {
+            error("pc", "missing".to_string());
+            continue;
+        };
+        let fidx = pc.fidx;
+        if fidx as usize >= m.funcs.len() {
+            error("pc.fidx", format!("function {} is out of range ({} functions)", fidx, m.funcs.len()));
+            continue;
+        }
+        if m.is_imported_func(fidx) {
+            error("pc.fidx", format!("function {} is an import", fidx));
+            continue;
+        }
+
+        let code = &codes[&fidx];
+        let Ok(offset) = Offset::try_from(pc.offset) else {
+            error("pc.offset", format!("{} is out of range in function {}", pc.offset, fidx));
+            continue;
+        };
+        // 呼び出し中のコード位置は呼び出し命令のoffset + 1. 最も内側のフレームもimportを呼び出し中のことがある
+        let call = offset.checked_sub(1).and_then(|o| code.call_sites.get(&o));
+        if depth < innermost {
+            let Some(callee) = call else {
+                error("pc.offset", format!("{} in function {} is not inside a call (call instruction offset + 1)", offset, fidx));
+                continue;
+            };
+            // 直接呼び出しなら、次のフレームは呼び出し先の関数
+            let next = call_stack.entries[depth + 1].pc.as_ref().map(|pc| pc.fidx);
+            if let (Some(callee), Some(next)) = (callee, next) {
+                if *callee != next {
+                    error("pc.fidx", format!("the call at offset {} of function {} calls function {}, but frame {} is in function {}", offset - 1, fidx, callee, depth + 1, next));
+                }
+            }
+        }
+        let in_call = call.is_some();
+        if !in_call && !code.boundaries.contains(&offset) {
+            error("pc.offset", format!("{} is not an instruction boundary of function {}", offset, fidx));
+            continue;
+        }
+        let stack = match tables.get_stack(fidx as usize, offset) {
+            Ok(stack) => stack,
+            Err(_) => {
+                error("pc.offset", format!("{} of function {} is not in the stack table", offset, fidx));
+                continue;
+            }
+        };
+
+        let locals = tables.get_locals(fidx as usize)?;
+        if let Err(e) = check_values(entry.locals.as_ref(), locals) {
+            error("locals", e.to_string());
+        }
+        let stack_types = stack.iter().map(|(_, t)| *t).collect::<Vec<_>>();
+        if let Err(e) = check_values(entry.value_stack.as_ref(), &stack_types) {
+            error("value_stack", e.to_string());
+        }
+        check_labels(entry, &mut error);
+    }
+    Ok(errors)
+}
+
+/// 値のセル数 (と型があれば型) がスタックテーブルと一致するか
+fn check_values(array: Option<&TypedArray>, expected: &[WasmType]) -> Result<()> {
+    let cells = array.and_then(|a| a.values.as_ref()).map_or(0, |v| v.contents.len());
+    let expected_cells = expected.iter().map(|t| t.cell_num()).sum::<usize>();
+
+    if let Some(types) = array.and_then(|a| a.types.as_ref()).filter(|t| !t.contents.is_empty()) {
+        let expected_types = expected.iter().map(|t| *t as u8).collect::<Vec<_>>();
+        if types.contents != expected_types {
+            anyhow::bail!("types {:?} differ from {:?} in the stack table", types.contents, expected_types);
+        }
+    }
+    if cells != expected_cells {
+        anyhow::bail!("{} cells, but the stack table has {} values in {} cells", cells, expected.len(), expected_cells);
+    }
+    Ok(())
+}
+
+/// ラベルスタックの各列の長さが揃っているか
+fn check_labels(entry: &CallStackEntry, error: &mut impl FnMut(&str, String)) {
+    let Some(labels) = &entry.label_stack else { return };
+    let depth = labels.begins.len();
+    for (field, len) in [
+        ("label_stack.targets", labels.targets.len()),
+        ("label_stack.stack_pointers", labels.stack_pointers.len()),
+        ("label_stack.cell_nums", labels.cell_nums.len()),
+    ] {
+        // 古いスナップショットはbeginsしか持たない
+        if len != 0 && len != depth {
+            error(field, format!("{} entries, but label_stack.begins has {}", len, depth));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::view::utils::state::{Array32, CodePos};
+
+    fn frame(fidx: u32, offset: u64, locals: Vec<u32>, value_stack: Vec<u32>) -> CallStackEntry {
+        CallStackEntry {
+            pc: Some(CodePos { fidx, offset }),
+            locals: Some(TypedArray { types: None, values: Some(Array32 { contents: locals }) }),
+            value_stack: Some(TypedArray { types: None, values: Some(Array32 { contents: value_stack }) }),
+            label_stack: None,
+        }
+    }
+
+    #[test]
+    fn test_check_call_stack() -> Result<()> {
+        let wasm = wat::parse_str(r#"
+            (module
+              (import "env" "sleep" (func $sleep))
+              (func $callee (param i64) (result i32)
+                call $sleep
+                i32.const 1)
+              (func (param i32)
+                i32.const 2
+                i64.const 3
+                call $callee
+                drop
+                drop))
+        "#)?;
+        // 呼び出し元: i32.const (2) + i64.const (2) の後の call (offset 4) の呼び出し中
+        // 呼び出し先: importの sleep (offset 0) の呼び出し中
+        let good = CallStack { entries: vec![frame(2, 5, vec![7], vec![2]), frame(1, 1, vec![3, 0], vec![])] };
+        assert_eq!(check_call_stack(&wasm, &good)?, Vec::<String>::new());
+
+        let bad = CallStack {
+            entries: vec![
+                // 呼び出し中ではない
+                frame(2, 2, vec![7], vec![2]),
+                // importの関数
+                frame(0, 0, vec![], vec![]),
+                // ローカルのセル数が合わない
+                frame(1, 1, vec![3], vec![]),
+            ],
+        };
+        let errors = check_call_stack(&wasm, &bad)?;
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(errors[0].starts_with("frame 0: pc.offset:"), "{}", errors[0]);
+        assert!(errors[1].starts_with("frame 1: pc.fidx:"), "{}", errors[1]);
+        assert!(errors[2].starts_with("frame 2: locals:"), "{}", errors[2]);
+
+        // call $callee の呼び出し中なのに、次のフレームが別の関数
+        let wrong_callee = CallStack { entries: vec![frame(2, 5, vec![7], vec![2]), frame(2, 5, vec![7], vec![2])] };
+        let errors = check_call_stack(&wasm, &wrong_callee)?;
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].starts_with("frame 0: pc.fidx:"), "{}", errors[0]);
+        Ok(())
+    }
+}
@@ -1,34 +1,32 @@
-use crate::core::stack_table::StackTables;
-use crate::core::module;
+use crate::core::stack_table::{Header, StackTables};
+use crate::core::stack_table_bin;
+use crate::core::safepoint::SafepointPolicy;
+use crate::command::output::{Output, write_file};
 
 use camino::Utf8PathBuf;
 
-use std::fs::File;
-use std::io::Write;
 use anyhow::Result;
 
-pub fn create_table_v2(path: Utf8PathBuf, before_execution: bool) -> Result<()> {
-    let buf: Vec<u8> = std::fs::read(&path).unwrap();
-
-    // コードから各セクションの情報を抽出
-    let m = module::new_module(&buf)?;
-    log::debug!("function size is {}", m.funcs.len());
-
-    // 関数クラスを初期化
-    let funcs = m.new_function_v2()?;
+pub fn create_table_v2(path: Utf8PathBuf, output: Output, before_execution: bool, binary: bool, safepoints: Option<SafepointPolicy>) -> Result<()> {
+    let buf: Vec<u8> = std::fs::read(&path)?;
 
     // 型スタック・命令スタックテーブルを生成
-    let stack_tables = StackTables::from_func(funcs, before_execution)?;
-
-    // stack_tableをserialize
-    let buf = stack_tables.serialize();
-
-    // bufをファイルに書き込む
-    let mut f: File = File::create("stack-table.msgpack")?;
-    f.write(buf.as_slice())?;
-    log::debug!("write type_table");
-
-    println!("write stack table to stack-table.msgpack");
+    // セーフポイントが指定されていればその要素だけを残す
+    let stack_tables = match safepoints {
+        Some(policy) => StackTables::from_wasm_at_safepoints(&buf, before_execution, policy)?,
+        None => StackTables::from_wasm(&buf, before_execution)?,
+    };
+
+    // stack_tableを入力wasmの情報を含むヘッダと一緒にserialize
+    let header = Header::new(&buf, before_execution);
+    if binary {
+        // ランタイムから直接引けるバイナリ形式
+        let buf = stack_table_bin::encode(&stack_tables, &header);
+        write_file(&output, "stack-table.bin", &buf)?;
+    } else {
+        let buf = stack_tables.serialize(&header);
+        write_file(&output, "stack-table.msgpack", &buf)?;
+    }
 
     Ok(())
 }
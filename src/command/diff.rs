@@ -0,0 +1,231 @@
+use crate::command::view::utils::UnifiedFormat;
+use crate::command::view::view_protobuf::parse_protobuf;
+use crate::command::view::view_v1::parse_v1_format;
+
+use std::io::IsTerminal;
+
+use camino::Utf8PathBuf;
+use serde::Serialize;
+
+use anyhow::{Context, Result};
+
+/// Frames matched by `pc`, or present on one side only
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FrameDiff {
+    Changed {
+        left: usize,
+        right: usize,
+        pc: Option<(u32, u64)>,
+        locals: Vec<ValueDiff>,
+        value_stack: Vec<ValueDiff>,
+        label_stack: Option<LabelStackDiff>,
+    },
+    LeftOnly { left: usize, pc: Option<(u32, u64)> },
+    RightOnly { right: usize, pc: Option<(u32, u64)> },
+}
+
+/// A slot whose value differs. `None` means the slot does not exist on that side
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ValueDiff {
+    pub index: usize,
+    pub left: Option<i64>,
+    pub right: Option<i64>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct LabelStackDiff {
+    pub left: Option<Vec<u32>>,
+    pub right: Option<Vec<u32>>,
+}
+
+/// Compare two snapshots frame by frame and print what changed.
+/// Each side is a protobuf `CallStack`, or with `*_v1` a v1 file or a directory of v1 files (one per frame)
+pub fn diff(left: Utf8PathBuf, right: Utf8PathBuf, left_v1: bool, right_v1: bool, json: bool) -> Result<()> {
+    let left_frames = load(&left, left_v1).with_context(|| format!("Failed to load {}", left))?;
+    let right_frames = load(&right, right_v1).with_context(|| format!("Failed to load {}", right))?;
+
+    let diffs = diff_frames(&left_frames, &right_frames);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diffs)?);
+    } else {
+        print_text(&diffs, std::io::stdout().is_terminal());
+        if diffs.is_empty() {
+            println!("{} and {} have the same frames", left, right);
+        }
+    }
+    Ok(())
+}
+
+fn load(path: &Utf8PathBuf, v1: bool) -> Result<Vec<UnifiedFormat>> {
+    if !v1 {
+        return parse_protobuf(path, false);
+    }
+    if !path.is_dir() {
+        return Ok(vec![parse_v1_format(path)?]);
+    }
+    // ファイル名の数字の順をフレームの順とする (frame_10 が frame_2 より前にならないように)
+    let mut paths = path
+        .read_dir_utf8()?
+        .map(|e| Ok(e?.path().to_owned()))
+        .collect::<Result<Vec<_>>>()?;
+    paths.sort_by_key(|p| frame_order(p.file_name().unwrap_or_default()));
+    paths.iter().map(|p| parse_v1_format(p).with_context(|| format!("Failed to parse {}", p))).collect()
+}
+
+/// ファイル名の最後の数字の並び. 数字のないファイルは最後に名前順で並べる
+fn frame_order(name: &str) -> (u64, String) {
+    let number = name.split(|c: char| !c.is_ascii_digit()).rfind(|d| !d.is_empty());
+    (number.and_then(|d| d.parse().ok()).unwrap_or(u64::MAX), name.to_string())
+}
+
+/// フレームをpcの最長共通部分列で対応付け、違いのあるフレームだけを返す
+pub fn diff_frames(left: &[UnifiedFormat], right: &[UnifiedFormat]) -> Vec<FrameDiff> {
+    let (n, m) = (left.len(), right.len());
+    // lcs[i][j] = left[i..] と right[j..] の最長共通部分列の長さ
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if left[i].pc == right[j].pc {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diffs = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && left[i].pc == right[j].pc {
+            if let Some(diff) = diff_frame(i, j, &left[i], &right[j]) {
+                diffs.push(diff);
+            }
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diffs.push(FrameDiff::LeftOnly { left: i, pc: left[i].pc });
+            i += 1;
+        } else {
+            diffs.push(FrameDiff::RightOnly { right: j, pc: right[j].pc });
+            j += 1;
+        }
+    }
+    diffs
+}
+
+fn diff_frame(i: usize, j: usize, left: &UnifiedFormat, right: &UnifiedFormat) -> Option<FrameDiff> {
+    let locals = diff_values(left.locals.as_deref(), right.locals.as_deref());
+    let value_stack = diff_values(left.value_stack.as_deref(), right.value_stack.as_deref());
+    let label_stack = (left.label_stack != right.label_stack)
+        .then(|| LabelStackDiff { left: left.label_stack.clone(), right: right.label_stack.clone() });
+
+    if locals.is_empty() && value_stack.is_empty() && label_stack.is_none() {
+        return None;
+    }
+    Some(FrameDiff::Changed { left: i, right: j, pc: left.pc, locals, value_stack, label_stack })
+}
+
+fn diff_values(left: Option<&[i64]>, right: Option<&[i64]>) -> Vec<ValueDiff> {
+    let (left, right) = (left.unwrap_or(&[]), right.unwrap_or(&[]));
+    (0..left.len().max(right.len()))
+        .filter_map(|index| {
+            let (l, r) = (left.get(index).copied(), right.get(index).copied());
+            (l != r).then_some(ValueDiff { index, left: l, right: r })
+        })
+        .collect()
+}
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+fn print_text(diffs: &[FrameDiff], color: bool) {
+    let paint = |code: &str, text: String| if color { format!("{}{}{}", code, text, RESET) } else { text };
+    let pc = |pc: &Option<(u32, u64)>| pc.map_or("?".to_string(), |(fidx, offset)| format!("fidx {}, offset {}", fidx, offset));
+    let value = |v: Option<i64>| v.map_or("-".to_string(), |v| v.to_string());
+
+    for diff in diffs {
+        match diff {
+            FrameDiff::LeftOnly { left, pc: p } => println!("{}", paint(RED, format!("- frame {} ({}) only in left", left, pc(p)))),
+            FrameDiff::RightOnly { right, pc: p } => println!("{}", paint(GREEN, format!("+ frame {} ({}) only in right", right, pc(p)))),
+            FrameDiff::Changed { left, right, pc: p, locals, value_stack, label_stack } => {
+                println!("{}", paint(BOLD, format!("frame {} / {} ({})", left, right, pc(p))));
+                for (name, values) in [("locals", locals), ("value_stack", value_stack)] {
+                    for v in values {
+                        println!("  {}[{}]", name, v.index);
+                        println!("    {}", paint(RED, format!("- {}", value(v.left))));
+                        println!("    {}", paint(GREEN, format!("+ {}", value(v.right))));
+                    }
+                }
+                if let Some(labels) = label_stack {
+                    println!("  label_stack");
+                    println!("    {}", paint(RED, format!("- {:?}", labels.left)));
+                    println!("    {}", paint(GREEN, format!("+ {:?}", labels.right)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(pc: (u32, u64), locals: Vec<i64>, value_stack: Vec<i64>, label_stack: Vec<u32>) -> UnifiedFormat {
+        UnifiedFormat {
+            pc: Some(pc),
+            return_address: None,
+            locals: Some(locals),
+            value_stack: Some(value_stack),
+            label_stack: Some(label_stack),
+            type_stack: None,
+        }
+    }
+
+    #[test]
+    fn test_diff_frames() {
+        let left = vec![
+            frame((3, 10), vec![1, 2], vec![5], vec![0]),
+            frame((2, 7), vec![], vec![], vec![0]),
+            frame((1, 4), vec![9], vec![1, 2], vec![0, 3]),
+        ];
+        let right = vec![
+            frame((3, 10), vec![1, 4], vec![5], vec![0]),
+            frame((1, 4), vec![9], vec![1], vec![0]),
+            frame((0, 2), vec![], vec![], vec![0]),
+        ];
+
+        let diffs = diff_frames(&left, &right);
+        assert_eq!(diffs, vec![
+            FrameDiff::Changed {
+                left: 0,
+                right: 0,
+                pc: Some((3, 10)),
+                locals: vec![ValueDiff { index: 1, left: Some(2), right: Some(4) }],
+                value_stack: vec![],
+                label_stack: None,
+            },
+            FrameDiff::LeftOnly { left: 1, pc: Some((2, 7)) },
+            FrameDiff::Changed {
+                left: 2,
+                right: 1,
+                pc: Some((1, 4)),
+                locals: vec![],
+                value_stack: vec![ValueDiff { index: 1, left: Some(2), right: None }],
+                label_stack: Some(LabelStackDiff { left: Some(vec![0, 3]), right: Some(vec![0]) }),
+            },
+            FrameDiff::RightOnly { right: 2, pc: Some((0, 2)) },
+        ]);
+
+        assert_eq!(diff_frames(&left, &left), vec![]);
+    }
+
+    #[test]
+    fn test_frame_order() {
+        let mut names = vec!["frame_10.txt", "frame_2.txt", "notes", "frame_1.txt"];
+        names.sort_by_key(|n| frame_order(n));
+        assert_eq!(names, vec!["frame_1.txt", "frame_2.txt", "frame_10.txt", "notes"]);
+    }
+}
@@ -1,354 +1,191 @@
-use crate::core::function::{BytecodeFunction, Function, CodePos};
+use crate::core::function_v2::{Function, Stack};
 use crate::core::module;
-use crate::compile::compile::{WasmType, u8_to_wasmtype, CompiledBytecodeFunction, CompiledCodePos};
-
-use std::collections::HashMap;
-use std::io::{BufWriter, Write};
-use std::fs::File;
+use crate::command::output::{Output, write_file};
 
 use camino::Utf8PathBuf;
-use anyhow::Result;
+use clap::ValueEnum;
 use csv::Terminator;
+use serde::Serialize;
+use wasmparser::Operator;
+
+use anyhow::{Context, Result};
 
 // Byte order mark
 const BOM: &[u8; 3] = &[0xEF, 0xBB, 0xBF]; // UTF-8
 
-#[derive(Clone)]
-struct ExtCompiledCodePos<'a> {
-    codepos: CompiledCodePos<'a>,
-    position_stack: Vec<u32>,
-    type_stack: Vec<WasmType>,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Csv,
+    Markdown,
+    Json,
 }
 
-#[derive(Clone)]
-struct AbsCodePos<'a> {
-    // NOTE: vecで持ってるからややこしいが、あくまでこれは、あるoffsetについての等価なコード位置のもの
-    offset: u32,
-    standard_codepos: Vec<ExtCompiledCodePos<'a>>,
-    fast_codepos: Vec<ExtCompiledCodePos<'a>>,
-}
-
-
-impl<'a> AbsCodePos<'a> {
-    fn to_string_standard_code(&self) -> Vec<String> {
-        let codepos = &self.standard_codepos;
-
-        let mut result = vec![];
-        for code in codepos {
-            result.push(code.codepos.opcode.to_string());
-        }
-
-        return result;
-    }
-
-    fn to_string_fast_code(&self) -> Vec<String> {
-        let codepos = &self.fast_codepos;
-
-        let mut result = vec![];
-        for code in codepos {
-            result.push(code.codepos.opcode.to_string());
-        }
-
-        return result;
-    }
-
-    fn to_string_standard_position_stack(&self) -> Vec<String> {
-        let codepos = &self.standard_codepos;
-
-        let mut result = vec![];
-        for code in codepos {
-            let standard_stack = code.position_stack
-                                        .iter()
-                                        .map(|e| e.to_string())
-                                        .collect::<Vec<_>>()
-                                        .join(", ");
-            result.push(format!("[{}]", standard_stack));
-        }
-
-        return result;
-    }
-
-    fn to_string_standard_type_stack(&self) -> Vec<String> {
-        let codepos = &self.standard_codepos;
-
-        let mut result = vec![];
-        for code in codepos {
-            let standard_stack = code.type_stack
-                                        .iter()
-                                        .map(|e| e.to_string())
-                                        .collect::<Vec<_>>()
-                                        .join(", ");
-            result.push(format!("[{}]", standard_stack));
+impl Format {
+    fn default_name(&self) -> &'static str {
+        match self {
+            Format::Csv => "wasm.csv",
+            Format::Markdown => "wasm.md",
+            Format::Json => "wasm.json",
         }
-
-        return result;
-    }
-
-    fn to_string_fast_position_stack(&self) -> Vec<String> {
-        let fast_codepos = &self.fast_codepos;
-
-        let mut result = vec![];
-        for code in fast_codepos {
-            let fast_stack = code.position_stack
-                                        .iter()
-                                        .map(|e| e.to_string())
-                                        .collect::<Vec<_>>()
-                                        .join(", ");
-            result.push(format!("[{}]", fast_stack));
-        }
-        return result;
     }
+}
 
-    fn to_string_fast_type_stack(&self) -> Vec<String> {
-        let fast_codepos = &self.fast_codepos;
+/// One value on the operand stack: its type and the operator that pushed it
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StackValue {
+    pub ty: String,
+    pub op: String,
+}
 
-        let mut result = vec![];
-        for code in fast_codepos {
-            let fast_stack = code.type_stack
-                                        .iter()
-                                        .map(|e| e.to_string())
-                                        .collect::<Vec<_>>()
-                                        .join(", ");
-            result.push(format!("[{}]", fast_stack));
-        }
-        return result;
-    }
+/// One entry of the stack table, with the stack before and after the instruction
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Row {
+    pub fidx: u32,
+    pub offset: u32,
+    pub op: String,
+    /// The entry at `offset + 1` recorded while the callee runs (arguments popped)
+    pub call_site: bool,
+    pub before: Vec<StackValue>,
+    pub after: Vec<StackValue>,
 }
 
+/// Print every instruction of every function alongside its operand stacks
+pub fn display(path: Utf8PathBuf, format: Format, output: Output) -> Result<()> {
+    let buf = std::fs::read(&path).with_context(|| format!("Failed to read {}", path))?;
+    let rows = create_rows(&buf)?;
+
+    let out = match format {
+        Format::Csv => render_csv(&rows)?,
+        Format::Markdown => render_markdown(&rows).into_bytes(),
+        Format::Json => serde_json::to_vec_pretty(&rows)?,
+    };
+    write_file(&output, format.default_name(), &out)
+}
 
-pub fn main(path: Utf8PathBuf) -> Result<()> {
-    // pathからwasmコードを取得
-    let buf: Vec<u8> = std::fs::read(&path).unwrap();
+pub fn create_rows(buf: &[u8]) -> Result<Vec<Row>> {
+    let buf = buf.to_vec();
     let m = module::new_module(&buf)?;
-    log::debug!("function size is {}", m.funcs.len());
-
-    // wasmコードの型スタックを計算する
-    let funcs = m.parse()?;
+    let funcs = m.new_function_v2()?;
 
-    // TODO: import_func_lenを使うの実装が微妙なのでリファクタする
-    let mut import_func_len = 0;
-    let mut merged_funcs: Vec<Vec<AbsCodePos>> = Vec::new();
-    // 高速バイトコードを配列に詰める。このとき、高速バイトコードの各命令は、wasmコードの命令と等価な位置(index)へ格納する
-    for func in funcs {
-        match func {
-            Function::ImportFunction(_) => import_func_len += 1,
-            Function::BytecodeFunction(b)=> {
-                // wasmコードから高速バイトコードを生成する
-                // TODO: standard_funcかcore.funcの型スタックと同じかチェックしたい
-                let standard_func = CompiledBytecodeFunction::new_standard_code(&m, &b);
-                let fast_func = CompiledBytecodeFunction::new_fast_code(&m, &b);
-
-                // 型スタックを計算
-                let ext_standard_code = ext_compiled_bytecode(standard_func);
-                let ext_fast_code = ext_compiled_bytecode(fast_func);
+    let mut rows = vec![];
+    for f in &funcs {
+        let f = match f {
+            Function::ImportFunction(_) => continue,
+            Function::BytecodeFunction(f) => f,
+        };
 
-                // funcとcompiled_funcで同じオフセット同士を紐付ける
-                // NOTE: 一つのオフセットに対して複数の命令が入る場合がある
-                let all_codes: Vec<AbsCodePos> = merge_codes_v2(&ext_standard_code, &ext_fast_code);
-                merged_funcs.push(all_codes);
-            },
+        // 呼び出し命令は「呼び出し中」→「呼び出し後」の順に2つ記録されている
+        let mut before: Vec<StackValue> = vec![];
+        for codepos in f.create_stack_table(false)? {
+            let after = stack_values(&codepos.stack);
+            let call_site = codepos.call_site;
+
+            let row = Row {
+                fidx: f.fidx,
+                offset: codepos.offset,
+                op: op_name(&codepos.op),
+                call_site,
+                before: before.clone(),
+                after,
+            };
+            if !call_site {
+                before = row.after.clone();
+            }
+            rows.push(row);
         }
     }
-
-    // それぞれのバイトコードと型スタックをhuman-friendryな形式で表示する
-    print_csv(import_func_len, merged_funcs);
-
-    return Ok(());
+    Ok(rows)
 }
 
-fn merge_codes_v2<'a>(code1: &Vec<ExtCompiledCodePos<'a>>, code2: &Vec<ExtCompiledCodePos<'a>>) -> Vec<AbsCodePos<'a>> {
-    // codesの前処理
-    let mut m1: HashMap<u32, Vec<ExtCompiledCodePos>> = HashMap::new();
-    let mut v1 = Vec::new();
-    let mut now_offset = 0;
-    for ext in code1 {
-        if now_offset != ext.codepos.offset {
-            m1.insert(now_offset, v1);
-            v1 = Vec::new();
-            now_offset = ext.codepos.offset;
-        }
-        v1.push(ext.clone());
-    }
+fn stack_values(stack: &Stack) -> Vec<StackValue> {
+    stack.inner
+        .iter()
+        .map(|(op, ty)| StackValue { ty: ty.to_string().to_owned(), op: op_name(op) })
+        .collect()
+}
 
-    // compiled_codesの前処理
-    let mut m2: HashMap<u32, Vec<ExtCompiledCodePos>> = HashMap::new();
-    let mut v2: Vec<ExtCompiledCodePos> = Vec::new();
-    now_offset = 0;
-    for ext in code2 {
-        if now_offset != ext.codepos.offset {
-            m2.insert(now_offset, v2);
-            v2 = Vec::new();
-            now_offset = ext.codepos.offset;
-        }
-        v2.push(ext.clone());
-    }
+fn op_name(op: &Operator) -> String {
+    format!("{:?}", op)
+}
 
-    // Vec<AbsCodePos>の構築
-    let mut all_codes = Vec::new();
-    for codepos in code1 {
-        let offset = codepos.codepos.offset;
+fn stack_to_string(values: &[StackValue]) -> String {
+    let values = values
+        .iter()
+        .map(|v| format!("{}({})", v.ty, v.op))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("[{}]", values)
+}
 
-        // codepos取得
-        let codepos;
-        match m1.get(&offset) {
-            None => codepos = Vec::new(),
-            Some(content) => codepos = content.clone(),
-        }
+const HEADER: [&str; 6] = ["fidx", "offset", "op", "call site", "stack before", "stack after"];
+
+fn columns(row: &Row) -> [String; 6] {
+    [
+        row.fidx.to_string(),
+        row.offset.to_string(),
+        row.op.clone(),
+        if row.call_site { "yes".to_string() } else { String::new() },
+        stack_to_string(&row.before),
+        stack_to_string(&row.after),
+    ]
+}
 
-        // fast_codepos取得
-        let fast_codepos;
-        match m2.get(&offset) {
-            None => fast_codepos = Vec::new(),
-            Some(content) => fast_codepos = content.clone(),
+fn render_csv(rows: &[Row]) -> Result<Vec<u8>> {
+    let mut buf = BOM.to_vec();
+    {
+        let mut w = csv::WriterBuilder::new()
+            .terminator(Terminator::CRLF)
+            .from_writer(&mut buf);
+        w.write_record(HEADER)?;
+        for row in rows {
+            w.write_record(columns(row))?;
         }
-
-        let e = AbsCodePos{
-            offset: offset,
-            standard_codepos: codepos,
-            fast_codepos: fast_codepos,
-        };
-        all_codes.push(e);
+        w.flush()?;
     }
-
-   return all_codes; 
+    Ok(buf)
 }
 
-fn ext_compiled_bytecode(func: CompiledBytecodeFunction<'_>) -> Vec<ExtCompiledCodePos> {
-    let mut codes = Vec::new();
-
-    let mut type_stack = Vec::new();
-    let mut position_stack = Vec::new();
-    for codepos in func.codes {
-        let optype = &codepos.optype;
-        // pop
-        for _ in 0..optype.input.len() {
-            position_stack.pop().expect("Failed to pop position_stack");
-            type_stack.pop().expect("Failed to pop type_stack");
-        }
-        // push
-        for i in 0..optype.output.len() {
-            position_stack.push(codepos.offset);
-            type_stack.push(optype.output[i]);
-        }
-
-        codes.push(
-            ExtCompiledCodePos{
-                codepos: codepos, 
-                position_stack: position_stack.clone(),
-                type_stack: type_stack.clone(),
-        });
+fn render_markdown(rows: &[Row]) -> String {
+    let mut s = format!("| {} |\n", HEADER.join(" | "));
+    s += &format!("|{}\n", "---|".repeat(HEADER.len()));
+    for row in rows {
+        let cols = columns(row).map(|c| c.replace('|', "\\|"));
+        s += &format!("| {} |\n", cols.join(" | "));
     }
-    return codes;
+    s
 }
 
-// TODO: clone多用しているが、そのcloneが適切か考え直す
-// fn merge_codes<'a>(codes: &Vec<CodePos<'a>>, compiled_codes: &Vec<ExtCompiledCodePos<'a>>) -> Vec<AbsCodePos<'a>> {
-//     // codesの前処理
-//     let mut m1: HashMap<u32, Vec<CodePos>> = HashMap::new();
-//     let mut v1 = Vec::new();
-//     let mut now_offset = 0;
-//     for codepos in codes {
-//         if now_offset != codepos.offset {
-//             m1.insert(now_offset, v1);
-//             v1 = Vec::new();
-//             now_offset = codepos.offset;
-//         }
-//         v1.push(codepos.clone());
-//     }
-
-//     // compiled_codesの前処理
-//     let mut m2: HashMap<u32, Vec<ExtCompiledCodePos>> = HashMap::new();
-//     let mut v2: Vec<ExtCompiledCodePos> = Vec::new();
-//     now_offset = 0;
-//     for ext in compiled_codes {
-//         if now_offset != ext.codepos.offset {
-//             m2.insert(now_offset, v2);
-//             v2 = Vec::new();
-//             now_offset = ext.codepos.offset;
-//         }
-//         v2.push(ext.clone());
-//     }
-
-//     // Vec<AbsCodePos>の構築
-//     let mut all_codes = Vec::new();
-//     for codepos in codes {
-//         let offset = codepos.offset;
-
-//         // codepos取得
-//         let codepos;
-//         match m1.get(&offset) {
-//             None => codepos = Vec::new(),
-//             Some(content) => codepos = content.clone(),
-//         }
-
-//         // fast_codepos取得
-//         let fast_codepos;
-//         match m2.get(&offset) {
-//             None => fast_codepos = Vec::new(),
-//             Some(content) => fast_codepos = content.clone(),
-//         }
-
-//         let e = AbsCodePos{
-//             offset: offset,
-//             codepos: codepos,
-//             fast_codepos: fast_codepos,
-//         };
-//         all_codes.push(e);
-//     }
-
-//    return all_codes; 
-// }
-
-fn print_csv(import_func_len: u32, funcs: Vec<Vec<AbsCodePos>>) {
-    // create file
-    let file = File::create("wasm.csv").expect("Failed to create file");
-    let mut w = BufWriter::new(file);
-    let _ = w.write_all(BOM);
-
-    let mut w= csv::WriterBuilder::new()
-                                .terminator(Terminator::CRLF)
-                                .from_writer(w);
-    
-    // print header
-    // TODO:　ハードコードはバグの要因なのでリファクタする
-    let _ = w.write_record(["fidx", "offset", "standard code", "standard stack", "fast code", "fast stack"]);
-
-    // print content
-    for (fidx, func) in funcs.iter().enumerate() {
-        for code in func {
-            // TODO: vecの管理をどうにかする
-            let mut contents = vec![["", "", "", "", "", ""]; std::cmp::max(code.standard_codepos.len(), code.fast_codepos.len())];
-            if contents.len() == 0 {
-                continue;
-            }
-            let offset_str = &(code.offset.to_string());
-            let fidx = import_func_len + fidx as u32;
-            let fidx_str = fidx.to_string();
-            contents[0][0] = &fidx_str;
-            contents[0][1] = &offset_str;
-
-            // standard
-            let standard_codes = code.to_string_standard_code();
-            let standard_stacks = code.to_string_standard_position_stack();
-            for (i, code) in code.standard_codepos.iter().enumerate() {
-                contents[i][2] = standard_codes[i].as_str();
-                contents[i][3] = standard_stacks[i].as_str();
-            }
-
-            // fast
-            let fast_codes = code.to_string_fast_code();
-            let fast_stacks = code.to_string_fast_position_stack();
-            for (i, code) in code.fast_codepos.iter().enumerate() {
-                contents[i][4] = fast_codes[i].as_str();
-                contents[i][5] = fast_stacks[i].as_str();
-            }
-
-            // offsetを出力
-
-            // 
-            for c in contents {
-                let _ = w.write_record(c).expect("Failed to write csv");
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rows_with_call_site() -> Result<()> {
+        let wasm = wat::parse_str(r#"
+            (module
+              (func $callee (param i32) (result i64) i64.const 1)
+              (func
+                i32.const 7
+                call $callee
+                drop))
+        "#)?;
+        let rows = create_rows(&wasm)?;
+        let rows = rows.iter().filter(|r| r.fidx == 1).collect::<Vec<_>>();
+
+        // i32.const 7 (offset 0), call (呼び出し中: offset 3, 呼び出し後: offset 2), drop (offset 4)
+        let offsets = rows.iter().map(|r| (r.offset, r.call_site)).collect::<Vec<_>>();
+        assert_eq!(&offsets[..4], &[(0, false), (3, true), (2, false), (4, false)]);
+
+        let types = |v: &[StackValue]| v.iter().map(|v| v.ty.clone()).collect::<Vec<_>>();
+        assert_eq!(types(&rows[1].before), vec!["I32"]);
+        assert!(rows[1].after.is_empty());
+        assert_eq!(types(&rows[2].before), vec!["I32"]);
+        assert_eq!(types(&rows[2].after), vec!["I64"]);
+        assert!(rows[2].after[0].op.starts_with("Call"));
+        assert_eq!(types(&rows[3].before), vec!["I64"]);
+
+        let md = render_markdown(&[rows[0].clone()]);
+        assert!(md.lines().nth(2).unwrap().starts_with("| 1 | 0 | I32Const"), "{}", md);
+        Ok(())
     }
-}
\ No newline at end of file
+}
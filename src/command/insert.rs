@@ -1,78 +1,258 @@
 use anyhow::{anyhow, Result};
 use camino::Utf8PathBuf;
+use std::borrow::Cow;
 use std::fs;
-use walrus::{Module, ir::{Instr, InstrLocId}};
+use std::sync::{Arc, Mutex};
+use walrus::{CodeTransform, CustomSection, IdsToIndices, LocalFunction, Module, ModuleConfig, ir::{Instr, InstrLocId, InstrSeqId}};
+use wasmparser::Operator;
 
-/// Insert a NOP instruction at a specific offset within a specific function
+use crate::core::{function_v2, module};
+use crate::core::offset_map::OffsetMap;
+
+/// Insert a NOP instruction before the instruction at a byte offset within a specific function.
+/// The old→new offset map is written next to the output as JSON (or to `map_path`)
 pub fn insert_nop(
     input_path: Utf8PathBuf,
     output_path: Utf8PathBuf,
     function_index: u32,
     offset: u32,
+    map_path: Option<Utf8PathBuf>,
 ) -> Result<()> {
     // Read the original WASM file
     let wasm_bytes = fs::read(&input_path)
         .map_err(|e| anyhow!("Failed to read input file {}: {}", input_path, e))?;
 
     // Inject NOP into the WASM binary
-    let modified_bytes = inject_nop(&wasm_bytes, function_index, offset)?;
+    let (modified_bytes, offset_map) = inject_nop(&wasm_bytes, function_index, offset)?;
 
     // Write the modified WASM binary
     fs::write(&output_path, &modified_bytes)
         .map_err(|e| anyhow!("Failed to write output file {}: {}", output_path, e))?;
+    let map_path = map_path.unwrap_or_else(|| default_map_path(&output_path));
+    write_offset_map(&map_path, &offset_map)?;
 
     log::info!(
-        "Successfully inserted NOP at offset {} in function {} and wrote to {}",
+        "Successfully inserted NOP at offset {} in function {} and wrote to {} (offset map: {})",
         offset,
         function_index,
-        output_path
+        output_path,
+        map_path
     );
 
     Ok(())
 }
 
-/// Inject a NOP instruction at the specified offset within the specified function
-fn inject_nop(wasm_bytes: &[u8], func_index: u32, instr_offset: u32) -> Result<Vec<u8>> {
-    // Parse the WASM module using Walrus
-    let mut module = Module::from_buffer(wasm_bytes)
-        .map_err(|e| anyhow!("Failed to parse WASM module: {}", e))?;
+/// `out.wasm` → `out.wasm.offsets.json`
+pub fn default_map_path(output_path: &Utf8PathBuf) -> Utf8PathBuf {
+    Utf8PathBuf::from(format!("{}.offsets.json", output_path))
+}
+
+pub fn write_offset_map(path: &Utf8PathBuf, offset_map: &OffsetMap) -> Result<()> {
+    let json = serde_json::to_vec_pretty(offset_map)?;
+    fs::write(path, json).map_err(|e| anyhow!("Failed to write offset map {}: {}", path, e))
+}
+
+/// Inject a NOP instruction before the instruction at the specified byte offset within the specified function
+fn inject_nop(wasm_bytes: &[u8], func_index: u32, offset: u32) -> Result<(Vec<u8>, OffsetMap)> {
+    let mut module = parse_module(wasm_bytes)?;
+
+    insert_instrs(&mut module, wasm_bytes, func_index, offset, vec![Instr::Nop(walrus::ir::Nop {})])?;
+
+    emit_with_offset_map(module, wasm_bytes)
+}
+
+/// Parse a module for instrumentation, keeping track of where every instruction came from
+pub fn parse_module(wasm_bytes: &[u8]) -> Result<Module> {
+    ModuleConfig::new()
+        .preserve_code_transform(true)
+        .parse(wasm_bytes)
+        .map_err(|e| anyhow!("Failed to parse WASM module: {}", e))
+}
+
+/// Name of the placeholder custom section used to observe the code transform during emission
+const CAPTURE_SECTION: &str = "wacret.code_transform";
+
+/// Receives the code transform while walrus emits the module. It emits no data and is stripped afterwards
+#[derive(Debug)]
+struct TransformCapture(Arc<Mutex<Vec<(u32, u32)>>>);
+
+impl CustomSection for TransformCapture {
+    fn name(&self) -> &str {
+        CAPTURE_SECTION
+    }
+
+    fn data(&self, _ids_to_indices: &IdsToIndices) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&[])
+    }
+
+    fn apply_code_transform(&mut self, transform: &CodeTransform) {
+        let map = transform.instruction_map
+            .iter()
+            .map(|(loc, pos)| (loc.data(), *pos as u32))
+            .collect();
+        *self.0.lock().unwrap() = map;
+    }
+}
+
+/// Serialize a module parsed with `parse_module` and build the offset map from `wasm_bytes` to the result.
+/// walrus may reorder functions and renumber locals, which the map also records
+pub fn emit_with_offset_map(mut module: Module, wasm_bytes: &[u8]) -> Result<(Vec<u8>, OffsetMap)> {
+    let captured = Arc::new(Mutex::new(vec![]));
+    module.customs.add(TransformCapture(captured.clone()));
+    let mut modified_bytes = module.emit_wasm();
+
+    // 空のカスタムセクションは最後に出力される: id (0), サイズ, 名前の長さ, 名前
+    let mut section = vec![0, CAPTURE_SECTION.len() as u8 + 1, CAPTURE_SECTION.len() as u8];
+    section.extend_from_slice(CAPTURE_SECTION.as_bytes());
+    if !modified_bytes.ends_with(&section) {
+        return Err(anyhow!("The code transform section was not emitted at the end of the module"));
+    }
+    modified_bytes.truncate(modified_bytes.len() - section.len());
+
+    let instruction_map = captured.lock().unwrap().clone();
+    let offset_map = OffsetMap::from_binaries(wasm_bytes, &modified_bytes, &instruction_map)?;
+    Ok((modified_bytes, offset_map))
+}
+
+/// Where to insert, in terms of the original wasm binary
+enum Position {
+    /// Before the instruction at this position in the file
+    Before(u32),
+    /// At the end of a block body, i.e. before its `end` or `else`.
+    /// The block is given by the position of its block instruction (`None` for the function body)
+    EndOf(Option<u32>, Branch),
+}
+
+#[derive(Clone, Copy)]
+enum Branch {
+    Then,
+    Else,
+}
+
+/// Insert `instrs` before the instruction at `offset`, a function-relative byte offset
+/// as used by the stack tables (`CodePos.offset`). `wasm_bytes` must be the binary `module` was parsed from.
+pub fn insert_instrs(module: &mut Module, wasm_bytes: &[u8], func_index: u32, offset: u32, instrs: Vec<Instr>) -> Result<()> {
+    insert_all(module, wasm_bytes, vec![(func_index, offset, instrs)])
+}
+
+/// Apply several insertions `(function index, offset, instructions)`, all addressed by offsets in `wasm_bytes`.
+/// Insertions at the same offset keep their order
+pub fn insert_all(module: &mut Module, wasm_bytes: &[u8], insertions: Vec<(u32, u32, Vec<Instr>)>) -> Result<()> {
+    let buf = wasm_bytes.to_vec();
+    let m = module::new_module(&buf)?;
+    let funcs = m.new_function_v2()?;
 
-    // Get the function ID from the index
-    let func_id = module.funcs.iter().nth(func_index as usize)
-        .ok_or_else(|| anyhow!("Function with index {} not found", func_index))?.id();
+    for (func_index, offset, instrs) in insertions {
+        let f = match funcs.get(func_index as usize) {
+            Some(function_v2::Function::BytecodeFunction(f)) => f,
+            Some(function_v2::Function::ImportFunction(_)) => return Err(anyhow!("Function with index {} is not a local function", func_index)),
+            None => return Err(anyhow!("Function with index {} not found", func_index)),
+        };
+        let position = resolve_offset(f, offset)?;
 
-    // Get the mutable function body
-    let func = module.funcs.get_mut(func_id);
+        // Get the function ID from the index
+        let func_id = module.funcs.iter().nth(func_index as usize)
+            .ok_or_else(|| anyhow!("Function with index {} not found", func_index))?.id();
 
-    // Ensure the function is local
-    let body = match &mut func.kind {
-        walrus::FunctionKind::Local(local) => local,
-        _ => return Err(anyhow!("Function with index {} is not a local function", func_index)),
-    };
+        // Ensure the function is local
+        let body = match &mut module.funcs.get_mut(func_id).kind {
+            walrus::FunctionKind::Local(local) => local,
+            _ => return Err(anyhow!("Function with index {} is not a local function", func_index)),
+        };
 
-    // Get the entry block ID
-    let entry_block_id = body.entry_block();
+        let (seq_id, index) = find_position(body, body.entry_block(), &position)?
+            .ok_or_else(|| anyhow!("Offset {} of function {} was not found in the parsed function body", offset, func_index))?;
 
-    // Get a mutable reference to the instruction sequence
-    let instr_seq = body.block_mut(entry_block_id);
+        let instr_seq = body.block_mut(seq_id);
+        for (i, instr) in instrs.into_iter().enumerate() {
+            instr_seq.instrs.insert(index + i, (instr, InstrLocId::default()));
+        }
+        log::debug!("Instruction sequence after insertion: {:?}", instr_seq.instrs);
+    }
 
-    // Insert a NOP-equivalent instruction (e.g., Drop) at the specified offset
-    let nop_instr = Instr::Nop(walrus::ir::Nop {});
-    let nop_loc_id = InstrLocId::new(instr_seq.instrs.len() as u32);
-    instr_seq.instrs.insert(instr_offset as usize, (nop_instr, nop_loc_id));
+    Ok(())
+}
 
-    // Log the instruction sequence after insertion
-    log::debug!("Instruction sequence after insertion: {:?}", instr_seq.instrs);
+/// Map a function-relative byte offset to a position in the original binary
+fn resolve_offset(f: &function_v2::BytecodeFunction, offset: u32) -> Result<Position> {
+    let base = f.code_base()?;
+    // 開いているブロック: (ブロック命令の位置, elseに入ったか)
+    let mut blocks: Vec<(u32, bool)> = vec![];
+    for (op_offset, op) in f.operators()? {
+        let pos = base + op_offset;
+        if op_offset == offset {
+            let position = match op {
+                Operator::End => match blocks.last() {
+                    Some((begin, in_else)) => Position::EndOf(Some(*begin), if *in_else { Branch::Else } else { Branch::Then }),
+                    None => Position::EndOf(None, Branch::Then),
+                },
+                Operator::Else => Position::EndOf(blocks.last().map(|(begin, _)| *begin), Branch::Then),
+                _ => Position::Before(pos),
+            };
+            return Ok(position);
+        }
+        if op_offset > offset {
+            break;
+        }
 
-    // Serialize the modified module back to bytes
-    let modified_bytes = module.emit_wasm();
+        match op {
+            Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } => blocks.push((pos, false)),
+            Operator::Else => {
+                if let Some(block) = blocks.last_mut() {
+                    block.1 = true;
+                }
+            }
+            Operator::End => { blocks.pop(); }
+            _ => {}
+        }
+    }
+    Err(anyhow!("Offset {} is not an instruction boundary in function {}", offset, f.fidx))
+}
+
+/// Walk the nested instruction sequences to find where `position` is
+fn find_position(body: &LocalFunction, seq_id: InstrSeqId, position: &Position) -> Result<Option<(InstrSeqId, usize)>> {
+    let seq = body.block(seq_id);
+    if let Position::EndOf(None, _) = position {
+        return Ok(Some((seq_id, seq.instrs.len())));
+    }
 
-    Ok(modified_bytes)
+    for (i, (instr, loc)) in seq.instrs.iter().enumerate() {
+        let pos = if loc.is_default() { None } else { Some(loc.data()) };
+        match position {
+            Position::Before(target) if pos == Some(*target) => return Ok(Some((seq_id, i))),
+            Position::EndOf(Some(target), branch) if pos == Some(*target) => {
+                let inner = match (instr, branch) {
+                    (Instr::Block(b), _) => b.seq,
+                    (Instr::Loop(l), _) => l.seq,
+                    (Instr::IfElse(e), Branch::Then) => e.consequent,
+                    (Instr::IfElse(e), Branch::Else) => e.alternative,
+                    _ => return Err(anyhow!("Instruction at {} is not a block", target)),
+                };
+                return Ok(Some((inner, body.block(inner).instrs.len())));
+            }
+            _ => {}
+        }
+
+        let children = match instr {
+            Instr::Block(b) => vec![b.seq],
+            Instr::Loop(l) => vec![l.seq],
+            Instr::IfElse(e) => vec![e.consequent, e.alternative],
+            _ => vec![],
+        };
+        for child in children {
+            if let Some(found) = find_position(body, child, position)? {
+                return Ok(Some(found));
+            }
+        }
+    }
+    Ok(None)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::stack_table::StackTables;
+    use crate::core::val::WasmType;
 
     #[test]
     fn test_insert_nop_file_not_found() {
@@ -81,6 +261,7 @@ mod tests {
             "output.wasm".into(),
             0,
             0,
+            None,
         );
         assert!(result.is_err());
     }
@@ -96,4 +277,116 @@ mod tests {
             assert!(e.to_string().contains("Function with index 999 not found"));
         }
     }
+
+    /// Collect the instructions of a function in code order, with the sequence nesting flattened
+    fn flatten(wasm: &[u8], func_index: usize) -> Vec<String> {
+        fn walk(body: &LocalFunction, seq: InstrSeqId, out: &mut Vec<String>) {
+            for (instr, _) in &body.block(seq).instrs {
+                let name = format!("{:?}", instr);
+                out.push(name.split(|c: char| !c.is_alphanumeric()).next().unwrap().to_string());
+                match instr {
+                    Instr::Block(b) => walk(body, b.seq, out),
+                    Instr::Loop(l) => walk(body, l.seq, out),
+                    Instr::IfElse(e) => {
+                        walk(body, e.consequent, out);
+                        out.push("Else".to_string());
+                        walk(body, e.alternative, out);
+                    }
+                    _ => {}
+                }
+                if let Instr::Block(_) | Instr::Loop(_) | Instr::IfElse(_) = instr {
+                    out.push("End".to_string());
+                }
+            }
+        }
+        let module = Module::from_buffer(wasm).unwrap();
+        let func = module.funcs.iter().nth(func_index).unwrap();
+        let body = match &func.kind {
+            walrus::FunctionKind::Local(local) => local,
+            _ => panic!("expected a local function"),
+        };
+        let mut out = vec![];
+        walk(body, body.entry_block(), &mut out);
+        out
+    }
+
+    #[test]
+    fn test_inject_nop_at_nested_byte_offsets() -> Result<()> {
+        let wasm = wat::parse_str(r#"
+            (module
+              (func (param i32)
+                block
+                  local.get 0
+                  if
+                    i32.const 1
+                    drop
+                  else
+                    nop
+                  end
+                end))
+        "#)?;
+        // block (0), local.get 0 (2), if (4), i32.const 1 (6), drop (8), else (9), nop (10), end (11), end (12), end (13)
+        let (modified, _) = inject_nop(&wasm, 0, 6)?;
+        assert_eq!(flatten(&modified, 0), vec!["Block", "LocalGet", "IfElse", "Nop", "Const", "Drop", "Else", "Nop", "End", "End"]);
+
+        // else の前 (then節の末尾) と if の end の前 (else節の末尾)
+        let (modified, _) = inject_nop(&wasm, 0, 9)?;
+        assert_eq!(flatten(&modified, 0), vec!["Block", "LocalGet", "IfElse", "Const", "Drop", "Nop", "Else", "Nop", "End", "End"]);
+        let (modified, _) = inject_nop(&wasm, 0, 11)?;
+        assert_eq!(flatten(&modified, 0), vec!["Block", "LocalGet", "IfElse", "Const", "Drop", "Else", "Nop", "Nop", "End", "End"]);
+
+        // 関数本体の end の前
+        let (modified, _) = inject_nop(&wasm, 0, 13)?;
+        assert_eq!(flatten(&modified, 0).last().unwrap(), "Nop");
+
+        // 命令の途中はエラー
+        let err = inject_nop(&wasm, 0, 7).unwrap_err();
+        assert!(err.to_string().contains("not an instruction boundary"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_offset_map_after_reordering() -> Result<()> {
+        let wasm = wat::parse_str(r#"
+            (module
+              (func $small (param i32) (result i32) local.get 0)
+              (func (param i32) (local i64 i32)
+                i64.const 1
+                local.set 1
+                local.get 0
+                if
+                  i32.const 2
+                  local.set 2
+                end
+                local.get 2
+                call $small
+                drop))
+        "#)?;
+        // i64.const (0), local.set 1 (2), local.get 0 (4), if (6), i32.const (8), local.set 2 (10),
+        // end (12), local.get 2 (13), call (15), drop (17), end (18)
+        let (modified, map) = inject_nop(&wasm, 1, 4)?;
+        wasmparser::validate(&modified)?;
+
+        // walrus は大きい関数から出力するので関数番号が入れ替わる
+        assert_eq!(map.func(0)?.fidx, 1);
+        let f = map.func(1)?;
+        assert_eq!(f.fidx, 0);
+        // nop (4) の後ろは1バイトずれ、elseのないifには `else` が補われる
+        let offsets = f.offsets.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+        assert_eq!(offsets, vec![(0, 0), (2, 2), (4, 5), (6, 7), (8, 9), (10, 11), (12, 14), (13, 15), (15, 17), (17, 19), (18, 20)]);
+        assert_eq!(f.offset(16)?, 18);
+        assert!(f.offset(3).is_err());
+        // ローカルは型ごとにまとめ直される
+        assert_eq!(f.locals, vec![Some(0), Some(2), Some(1)]);
+
+        let tables = StackTables::from_wasm(&wasm, false)?;
+        let remapped = map.apply_to_stack_tables(tables)?;
+        assert_eq!(remapped.get_locals(0)?, &vec![WasmType::I32, WasmType::I32, WasmType::I64]);
+        assert_eq!(remapped.get_stack(0, 18)?, &vec![]);
+        let labels = remapped.get_labels(0, 9)?;
+        assert_eq!((labels[1].begin, labels[1].target), (7, 14));
+        let expected = StackTables::from_wasm(&modified, false)?;
+        assert_eq!(remapped.get_stack(0, 15)?, expected.get_stack(0, 15)?);
+        Ok(())
+    }
 }
@@ -0,0 +1,137 @@
+use crate::command::insert::{emit_with_offset_map, insert_all, parse_module};
+use crate::command::output::{Output, write_files};
+use crate::core::function_v2::{BytecodeFunction, Function};
+use crate::core::module;
+use crate::core::offset_map::OffsetMap;
+use crate::core::safepoint::{safepoints, SafepointKind, SafepointPolicy};
+use crate::core::stack_table::{Header, StackTables};
+
+use camino::Utf8PathBuf;
+use walrus::ir::{Call, Const, Instr, Value};
+use walrus::{FunctionId, ValType};
+
+use anyhow::{Context, Result};
+
+/// Import module and name of the function the instrumented code calls at every safepoint
+pub const POLL_MODULE: &str = "wacret";
+pub const POLL_NAME: &str = "checkpoint_poll";
+/// Offset the poll at function entry passes. Function entry has no stack table entry,
+/// so it must not be mistaken for a loop header at offset 0
+pub const ENTRY_OFFSET: u32 = u32::MAX;
+
+/// Rewrite a module to call the imported `wacret.checkpoint_poll(fidx, offset)` at its safepoints,
+/// and write the instrumented module, its stack tables and the offset map into `output`
+pub fn instrument(path: Utf8PathBuf, policy: SafepointPolicy, output: Output) -> Result<()> {
+    let buf = std::fs::read(&path).with_context(|| format!("Failed to read {}", path))?;
+    let (instrumented, offset_map) = instrument_polls(&buf, policy)?;
+
+    // ランタイムはpollの呼び出し中にチェックポイントを取るので、計装後のコードのスタックテーブルを出力する
+    let header = Header::new(&instrumented, false);
+    let tables = StackTables::from_wasm(&instrumented, false)?.serialize(&header);
+    let map = serde_json::to_vec_pretty(&offset_map)?;
+
+    write_files(&output, &[
+        ("instrumented.wasm", &instrumented),
+        ("stack-table.msgpack", &tables),
+        ("offsets.json", &map),
+    ])
+}
+
+/// Insert `i32.const fidx; i32.const offset; call $checkpoint_poll` at every safepoint.
+/// `fidx` and `offset` identify the safepoint in the original binary (the offset is the stack table key,
+/// or `ENTRY_OFFSET` at function entry). Loop headers poll at the start of the loop body, call sites right after the call returns
+pub fn instrument_polls(buf: &[u8], policy: SafepointPolicy) -> Result<(Vec<u8>, OffsetMap)> {
+    let mut module = parse_module(buf)?;
+    if module.imports.find(POLL_MODULE, POLL_NAME).is_some() {
+        anyhow::bail!("The module already imports {}.{}", POLL_MODULE, POLL_NAME);
+    }
+    let ty = module.types.add(&[ValType::I32, ValType::I32], &[]);
+    let (poll, _) = module.add_import_func(POLL_MODULE, POLL_NAME, ty);
+
+    let wasm = buf.to_vec();
+    let m = module::new_module(&wasm)?;
+    let mut insertions = vec![];
+    for f in m.new_function_v2()? {
+        let f = match f {
+            Function::BytecodeFunction(f) => f,
+            Function::ImportFunction(_) => continue,
+        };
+        let next = next_offsets(&f)?;
+        for point in safepoints(&f, policy)? {
+            // pollを挿入する位置: 関数の先頭、loopの本体の先頭、呼び出しの次の命令の前
+            let (at, id) = match point.kind {
+                SafepointKind::FunctionEntry => (0, ENTRY_OFFSET),
+                SafepointKind::LoopHeader => (next(point.offset)?, point.offset),
+                SafepointKind::CallSite => (next(point.offset - 1)?, point.offset),
+            };
+            insertions.push((f.fidx, at, poll_instrs(poll, f.fidx, id)));
+        }
+    }
+    insert_all(&mut module, buf, insertions)?;
+
+    emit_with_offset_map(module, buf)
+}
+
+fn poll_instrs(poll: FunctionId, fidx: u32, offset: u32) -> Vec<Instr> {
+    vec![
+        Instr::Const(Const { value: Value::I32(fidx as i32) }),
+        Instr::Const(Const { value: Value::I32(offset as i32) }),
+        Instr::Call(Call { func: poll }),
+    ]
+}
+
+/// 命令のオフセット → 次の命令のオフセット
+fn next_offsets(f: &BytecodeFunction) -> Result<impl Fn(u32) -> Result<u32>> {
+    let offsets = f.operators()?.into_iter().map(|(offset, _)| offset).collect::<Vec<_>>();
+    let fidx = f.fidx;
+    Ok(move |offset: u32| {
+        let i = offsets.binary_search(&offset)
+            .map_err(|_| anyhow::anyhow!("Offset {} is not an instruction boundary in function {}", offset, fidx))?;
+        offsets.get(i + 1).copied()
+            .ok_or_else(|| anyhow::anyhow!("No instruction after offset {} in function {}", offset, fidx))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmparser::{Operator, Parser, Payload};
+
+    #[test]
+    fn test_instrument_polls_at_safepoints() -> Result<()> {
+        let wasm = wat::parse_str(r#"
+            (module
+              (func $f)
+              (func
+                loop
+                  call $f
+                  br 0
+                end))
+        "#)?;
+        // loop (0), call (2), br (4), end (6), end (7)
+        let (instrumented, map) = instrument_polls(&wasm, SafepointPolicy::All)?;
+        wasmparser::validate(&instrumented)?;
+
+        // pollの引数 (fidx, offset) を集める. pollはimportなので関数番号0
+        let mut polls = vec![];
+        for payload in Parser::new(0).parse_all(&instrumented) {
+            if let Payload::CodeSectionEntry(body) = payload? {
+                let ops = body.get_operators_reader()?.into_iter().collect::<Result<Vec<_>, _>>()?;
+                for w in ops.windows(3) {
+                    if let [Operator::I32Const { value: fidx }, Operator::I32Const { value: offset }, Operator::Call { function_index: 0 }] = w {
+                        polls.push((*fidx, *offset));
+                    }
+                }
+            }
+        }
+        polls.sort();
+        // 関数の入口と、関数の先頭にあるloopは別のoffsetで区別できる
+        assert_eq!(polls, vec![(0, ENTRY_OFFSET as i32), (1, ENTRY_OFFSET as i32), (1, 0), (1, 3)]);
+
+        // 元の関数は1つずつ後ろにずれる
+        assert_eq!(map.new_func_count, 3);
+        assert!(map.funcs.values().all(|f| f.fidx >= 1));
+        StackTables::from_wasm(&instrumented, false)?;
+        Ok(())
+    }
+}
@@ -1,5 +1,14 @@
 pub mod create_table;
 pub mod create_table_v2;
+pub mod output;
 pub mod view;
 pub mod insert;
-// pub mod display;
\ No newline at end of file
+pub mod verify;
+pub mod restore_prep;
+pub mod translate;
+pub mod stats;
+pub mod display;pub mod remap;
+pub mod instrument;
+pub mod transform;
+pub mod check_snapshot;
+pub mod diff;
@@ -0,0 +1,53 @@
+use camino::{Utf8Path, Utf8PathBuf};
+
+use std::io::Write;
+use anyhow::{Context, Result};
+
+/// `-o/--output` で指定された出力先
+pub enum Output {
+    Stdout,
+    Path(Utf8PathBuf),
+}
+
+impl Output {
+    /// `-` は標準出力、未指定ならカレントディレクトリ
+    pub fn new(output: Option<Utf8PathBuf>) -> Self {
+        match output {
+            Some(path) if path == "-" => Output::Stdout,
+            Some(path) => Output::Path(path),
+            None => Output::Path(Utf8PathBuf::from(".")),
+        }
+    }
+}
+
+/// 1ファイルを書き出す. 出力先がディレクトリなら `default_name` で作成する
+pub fn write_file(output: &Output, default_name: &str, buf: &[u8]) -> Result<()> {
+    match output {
+        Output::Stdout => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(buf)?;
+            stdout.flush()?;
+        }
+        Output::Path(path) => {
+            let path = if path.is_dir() { path.join(default_name) } else { path.clone() };
+            std::fs::write(&path, buf).with_context(|| format!("Failed to write {}", path))?;
+            log::info!("write {} bytes to {}", buf.len(), path);
+        }
+    }
+    Ok(())
+}
+
+/// 複数ファイルをディレクトリに書き出す (存在しなければ作成する)
+pub fn write_files(output: &Output, files: &[(&str, &[u8])]) -> Result<()> {
+    let dir: &Utf8Path = match output {
+        Output::Stdout => anyhow::bail!("Cannot write {} files to stdout, specify a directory", files.len()),
+        Output::Path(path) => path,
+    };
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create directory {}", dir))?;
+    for (name, buf) in files {
+        let path = dir.join(name);
+        std::fs::write(&path, buf).with_context(|| format!("Failed to write {}", path))?;
+        log::info!("write {} bytes to {}", buf.len(), path);
+    }
+    Ok(())
+}
@@ -0,0 +1,160 @@
+use crate::command::output::{Output, write_file};
+use crate::command::view::utils::state::{Array32, Array8, CallStack, CallStackEntry, CodePos, LabelStack, TypedArray};
+use crate::core::offset_map::{FuncMap, OffsetMap};
+use crate::core::stack_table::{Header, StackTables, MAGIC};
+
+use camino::Utf8PathBuf;
+use prost::Message;
+
+use anyhow::{Context, Result};
+
+/// Rewrite a `StackTables` file or a `CallStack` snapshot taken on the original binary
+/// so that it refers to the instrumented binary, using the offset map written by `insert`
+pub fn remap(map: Utf8PathBuf, input: Utf8PathBuf, wasm: Utf8PathBuf, output: Output) -> Result<()> {
+    let map_buf = std::fs::read(&map).with_context(|| format!("Failed to read {}", map))?;
+    let input_buf = std::fs::read(&input).with_context(|| format!("Failed to read {}", input))?;
+    let wasm_buf = std::fs::read(&wasm).with_context(|| format!("Failed to read {}", wasm))?;
+    let offset_map: OffsetMap = serde_json::from_slice(&map_buf)
+        .with_context(|| format!("Failed to parse the offset map {}", map))?;
+
+    if input_buf.starts_with(&MAGIC) {
+        let (header, tables) = StackTables::deserialize(&input_buf)?;
+        let tables = offset_map.apply_to_stack_tables(tables)?;
+        let header = Header::new(&wasm_buf, header.before_execution);
+        write_file(&output, "remapped-stack-table.msgpack", &tables.serialize(&header))?;
+    } else {
+        let call_stack = CallStack::decode(&input_buf[..])
+            .with_context(|| format!("Failed to decode {} as a CallStack", input))?;
+        let call_stack = remap_call_stack(&offset_map, &call_stack)?;
+        write_file(&output, "remapped-snapshot.pb", &call_stack.encode_to_vec())?;
+    }
+    Ok(())
+}
+
+pub fn remap_call_stack(offset_map: &OffsetMap, call_stack: &CallStack) -> Result<CallStack> {
+    let entries = call_stack
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| remap_frame(offset_map, entry).with_context(|| format!("Failed to remap frame {}", i)))
+        .collect::<Result<_>>()?;
+    Ok(CallStack { entries })
+}
+
+fn remap_frame(offset_map: &OffsetMap, entry: &CallStackEntry) -> Result<CallStackEntry> {
+    let pc = entry.pc.as_ref().ok_or_else(|| anyhow::anyhow!("The frame has no pc"))?;
+    let map = offset_map.func(pc.fidx)?;
+    let pc = CodePos { fidx: map.fidx, offset: map.offset(pc.offset as u32)? as u64 };
+
+    let locals = entry.locals.as_ref().map(|locals| remap_locals(map, locals)).transpose()?;
+    let label_stack = entry.label_stack.as_ref().map(|labels| remap_labels(map, labels)).transpose()?;
+
+    Ok(CallStackEntry {
+        pc: Some(pc),
+        locals,
+        value_stack: entry.value_stack.clone(),
+        label_stack,
+    })
+}
+
+/// ローカルのセルを計装後の番号順に並べ替える. 使われなくなったローカルは捨てる
+fn remap_locals(map: &FuncMap, locals: &TypedArray) -> Result<TypedArray> {
+    let cells = locals.values.as_ref().map_or(&[][..], |v| &v.contents[..]);
+    let expected = map.local_types.iter().map(|t| t.cell_num()).sum::<usize>();
+    if cells.len() != expected {
+        anyhow::bail!("The locals have {} cells, but the function needs {}", cells.len(), expected);
+    }
+
+    let values = reorder_cells(map, cells);
+
+    // 型はローカルごとに1つ (restore-prep / check-values と同じ)
+    let types = locals
+        .types
+        .as_ref()
+        .map(|types| {
+            let types = &types.contents;
+            if types.len() != map.local_types.len() {
+                anyhow::bail!("The locals have {} types, but the function has {} locals", types.len(), map.local_types.len());
+            }
+            Ok(Array8 { contents: map.reorder(types, 0) })
+        })
+        .transpose()?;
+
+    Ok(TypedArray { types, values: Some(Array32 { contents: values }) })
+}
+
+/// セル単位の列をローカルごとに区切って並べ替える
+fn reorder_cells(map: &FuncMap, cells: &[u32]) -> Vec<u32> {
+    let mut rest = cells;
+    let mut per_local = vec![];
+    for ty in &map.local_types {
+        let (head, tail) = rest.split_at(ty.cell_num());
+        per_local.push(head.to_vec());
+        rest = tail;
+    }
+    map.reorder(&per_local, vec![]).concat()
+}
+
+fn remap_labels(map: &FuncMap, labels: &LabelStack) -> Result<LabelStack> {
+    let mut begins = Vec::with_capacity(labels.begins.len());
+    let mut targets = Vec::with_capacity(labels.targets.len());
+    for (depth, (begin, target)) in labels.begins.iter().zip(&labels.targets).enumerate() {
+        begins.push(if depth == 0 { *begin } else { map.offset(*begin)? });
+        targets.push(map.offset(*target)?);
+    }
+    Ok(LabelStack { begins, targets, ..labels.clone() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::insert::{emit_with_offset_map, insert_instrs, parse_module};
+    use crate::core::val::WasmType;
+    use walrus::ir::{Instr, Nop};
+
+    #[test]
+    fn test_remap_snapshot() -> Result<()> {
+        let wasm = wat::parse_str(r#"
+            (module
+              (func $small (param i32) (result i32) local.get 0)
+              (func (param i32) (local i64 f32 i32)
+                local.get 0
+                local.set 3
+                i64.const 1
+                local.set 1
+                local.get 3
+                call $small
+                drop))
+        "#)?;
+        // local.get 0 (0), local.set 3 (2), i64.const (4), local.set 1 (6), local.get 3 (8), call (10), drop (12), end (13)
+        let mut module = parse_module(&wasm)?;
+        insert_instrs(&mut module, &wasm, 1, 4, vec![Instr::Nop(Nop {})])?;
+        let (_, map) = emit_with_offset_map(module, &wasm)?;
+
+        let entry = CallStackEntry {
+            pc: Some(CodePos { fidx: 1, offset: 11 }),
+            // 引数 (7), i64 (2 cells), f32 (使われない), i32 (7)
+            locals: Some(TypedArray {
+                types: Some(Array8 { contents: vec![WasmType::I32 as u8, WasmType::I64 as u8, WasmType::F32 as u8, WasmType::I32 as u8] }),
+                values: Some(Array32 { contents: vec![7, 1, 0, 9, 7] }),
+            }),
+            value_stack: None,
+            label_stack: Some(LabelStack { begins: vec![0], targets: vec![13], stack_pointers: vec![0], cell_nums: vec![0] }),
+        };
+        let remapped = remap_call_stack(&map, &CallStack { entries: vec![entry.clone()] })?;
+        let remapped = &remapped.entries[0];
+
+        assert_eq!(remapped.pc, Some(CodePos { fidx: 0, offset: 12 }));
+        // f32 は取り除かれ、i32 → i64 の順になる
+        let locals = remapped.locals.as_ref().unwrap();
+        assert_eq!(locals.values.as_ref().unwrap().contents, vec![7, 7, 1, 0]);
+        assert_eq!(locals.types.as_ref().unwrap().contents, vec![WasmType::I32 as u8, WasmType::I32 as u8, WasmType::I64 as u8]);
+        assert_eq!(remapped.label_stack.as_ref().unwrap().targets, vec![14]);
+
+        // セルごとの型は受け付けない
+        let mut per_cell = entry;
+        per_cell.locals.as_mut().unwrap().types = Some(Array8 { contents: vec![WasmType::I32 as u8; 5] });
+        assert!(remap_call_stack(&map, &CallStack { entries: vec![per_cell] }).is_err());
+        Ok(())
+    }
+}
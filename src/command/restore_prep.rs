@@ -0,0 +1,146 @@
+use crate::core::stack_table::StackTables;
+use crate::core::val::WasmType;
+use crate::command::output::{Output, write_file};
+use crate::command::verify::to_label_stack;
+use crate::command::view::utils::state::{Array8, CallStack, TypedArray};
+
+use camino::Utf8PathBuf;
+use prost::Message;
+
+use anyhow::{Context, Result};
+
+/// Fill in the types of a raw `CallStack` snapshot from the stack tables so it can be restored
+pub fn restore_prep(wasm: Utf8PathBuf, table: Utf8PathBuf, snapshot: Utf8PathBuf, output: Output) -> Result<()> {
+    let wasm_buf = std::fs::read(&wasm).with_context(|| format!("Failed to read {}", wasm))?;
+    let table_buf = std::fs::read(&table).with_context(|| format!("Failed to read {}", table))?;
+    let snapshot_buf = std::fs::read(&snapshot).with_context(|| format!("Failed to read {}", snapshot))?;
+
+    // テーブルが別のwasmから生成されていたら型付けできない
+    let (_, tables) = StackTables::deserialize_for(&table_buf, &wasm_buf)?;
+    let mut call_stack = CallStack::decode(&snapshot_buf[..])
+        .with_context(|| format!("Failed to decode {} as a CallStack", snapshot))?;
+
+    type_call_stack(&tables, &mut call_stack)?;
+
+    write_file(&output, "typed-snapshot.pb", &call_stack.encode_to_vec())?;
+    Ok(())
+}
+
+/// 各フレームのローカルと値スタックに型を付ける.
+///
+/// 値は32bitセルの列として保存されているので、型ごとのセル数で区切れることを検査する.
+/// 呼び出し元フレームのpcは呼び出し中のコード位置 (Call命令のoffset + 1) を指す.
+/// ラベルスタックがダンプされていないフレームにはスタックテーブルのラベルスタックを補う.
+pub fn type_call_stack(tables: &StackTables, call_stack: &mut CallStack) -> Result<()> {
+    for (depth, entry) in call_stack.entries.iter_mut().enumerate() {
+        let pc = entry.pc.as_ref().ok_or_else(|| anyhow::anyhow!("Frame {} has no pc", depth))?;
+        let (fidx, offset) = (pc.fidx, pc.offset as u32);
+        if fidx as usize >= tables.0.len() {
+            anyhow::bail!("Frame {}: function {} is out of range ({} functions)", depth, fidx, tables.0.len());
+        }
+
+        let locals = tables.get_locals(fidx as usize)?;
+        let stack = tables
+            .get_stack(fidx as usize, offset)
+            .with_context(|| format!("Frame {}: no stack table entry at (fidx {}, offset {})", depth, fidx, offset))?;
+        let stack_types = stack.iter().map(|(_, t)| *t).collect::<Vec<_>>();
+
+        let what = format!("frame {} (fidx {}, offset {})", depth, fidx, offset);
+        set_types(entry.locals.get_or_insert_with(Default::default), locals, &format!("locals of {}", what))?;
+        set_types(entry.value_stack.get_or_insert_with(Default::default), &stack_types, &format!("value stack of {}", what))?;
+        if entry.label_stack.is_none() {
+            entry.label_stack = Some(to_label_stack(tables.get_labels(fidx as usize, offset)?));
+        }
+    }
+    Ok(())
+}
+
+fn set_types(array: &mut TypedArray, types: &[WasmType], what: &str) -> Result<()> {
+    let cells = array.values.as_ref().map_or(0, |v| v.contents.len());
+    let mut expected = 0;
+    for t in types {
+        if *t == WasmType::Any {
+            anyhow::bail!("The type of a value in the {} is unknown", what);
+        }
+        expected += t.cell_num();
+    }
+    if cells != expected {
+        anyhow::bail!("The {} has {} cells, but its types {:?} need {}", what, cells, types, expected);
+    }
+
+    let contents = types.iter().map(|t| *t as u8).collect::<Vec<_>>();
+    if let Some(old) = &array.types {
+        if !old.contents.is_empty() && old.contents != contents {
+            log::warn!("Overwriting the types of the {}: {:?} -> {:?}", what, old.contents, contents);
+        }
+    }
+    array.types = Some(Array8 { contents });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::view::utils::state::{Array32, CallStackEntry, CodePos};
+
+    fn typed_array(contents: Vec<u32>) -> Option<TypedArray> {
+        Some(TypedArray { types: None, values: Some(Array32 { contents }) })
+    }
+
+    fn types(array: &Option<TypedArray>) -> Vec<u8> {
+        array.as_ref().unwrap().types.as_ref().unwrap().contents.clone()
+    }
+
+    #[test]
+    fn test_type_call_stack() -> Result<()> {
+        let wasm = wat::parse_str(r#"
+            (module
+              (func $callee (param f64) (result i32)
+                (local i64)
+                i32.const 1
+                i64.const 2
+                unreachable)
+              (func (param i32)
+                i64.const 3
+                f64.const 4
+                call $callee
+                unreachable))
+        "#)?;
+        let tables = StackTables::from_wasm(&wasm, false)?;
+        let mut call_stack = CallStack {
+            entries: vec![
+                // 呼び出し元: i64.const (2 bytes) + f64.const (9 bytes) の後の call の呼び出し中
+                CallStackEntry {
+                    pc: Some(CodePos { fidx: 1, offset: 12 }),
+                    locals: typed_array(vec![7]),
+                    value_stack: typed_array(vec![3, 0]),
+                    label_stack: None,
+                },
+                // 呼び出し先: i64.const 2 (offset 2) の実行後
+                CallStackEntry {
+                    pc: Some(CodePos { fidx: 0, offset: 2 }),
+                    locals: typed_array(vec![0, 0x40100000, 0, 0]),
+                    value_stack: typed_array(vec![1, 2, 0]),
+                    label_stack: None,
+                },
+            ],
+        };
+        type_call_stack(&tables, &mut call_stack)?;
+
+        use WasmType::*;
+        let entries = &call_stack.entries;
+        assert_eq!(types(&entries[0].locals), vec![I32 as u8]);
+        assert_eq!(types(&entries[0].value_stack), vec![I64 as u8]);
+        assert_eq!(types(&entries[1].locals), vec![F64 as u8, I64 as u8]);
+        assert_eq!(types(&entries[1].value_stack), vec![I32 as u8, I64 as u8]);
+        // ラベルスタックは関数フレームだけ
+        let labels = entries[1].label_stack.as_ref().unwrap();
+        assert_eq!((labels.begins.clone(), labels.stack_pointers.clone(), labels.cell_nums.clone()), (vec![0], vec![0], vec![1]));
+
+        // セル数が型と合わなければエラー
+        call_stack.entries[1].value_stack = typed_array(vec![1, 2]);
+        let err = type_call_stack(&tables, &mut call_stack).unwrap_err();
+        assert!(err.to_string().contains("has 2 cells"), "{}", err);
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use crate::core::stack_table::{StackTable, StackTables};
+
+use camino::Utf8PathBuf;
+use serde::Serialize;
+
+use anyhow::{Context, Result};
+
+/// Bytes a checkpoint saves per code position, and how many of them could be skipped
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct FuncStats {
+    pub fidx: usize,
+    /// Number of code positions in the stack table
+    pub positions: usize,
+    /// Bytes of all locals and stack values, summed over the positions
+    pub total_bytes: usize,
+    /// Bytes of dead locals and stack values
+    pub dead_bytes: usize,
+    /// Bytes of live stack values that can be recomputed instead of saved
+    pub recomputable_bytes: usize,
+}
+
+impl FuncStats {
+    pub fn skippable_bytes(&self) -> usize {
+        self.dead_bytes + self.recomputable_bytes
+    }
+}
+
+/// Report how many bytes per checkpoint liveness and rematerialization could save
+pub fn stats(path: Utf8PathBuf, json: bool) -> Result<()> {
+    let buf = std::fs::read(&path).with_context(|| format!("Failed to read {}", path))?;
+    let tables = StackTables::from_wasm(&buf, false)?;
+
+    let funcs = tables
+        .iter()
+        .enumerate()
+        .filter(|(_, table)| !table.inner().is_empty())
+        .map(|(fidx, table)| func_stats(fidx, table))
+        .collect::<Result<Vec<_>>>()?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&funcs)?);
+        return Ok(());
+    }
+
+    println!("{:>6} {:>9} {:>12} {:>12} {:>12} {:>8}", "fidx", "positions", "avg bytes", "avg dead", "avg remat", "skip %");
+    let mut total = FuncStats::default();
+    for f in &funcs {
+        print_row(&f.fidx.to_string(), f);
+        total.positions += f.positions;
+        total.total_bytes += f.total_bytes;
+        total.dead_bytes += f.dead_bytes;
+        total.recomputable_bytes += f.recomputable_bytes;
+    }
+    print_row("total", &total);
+    Ok(())
+}
+
+fn print_row(name: &str, f: &FuncStats) {
+    let avg = |bytes: usize| bytes as f64 / f.positions.max(1) as f64;
+    let percent = 100.0 * f.skippable_bytes() as f64 / f.total_bytes.max(1) as f64;
+    println!(
+        "{:>6} {:>9} {:>12.1} {:>12.1} {:>12.1} {:>7.1}%",
+        name, f.positions, avg(f.total_bytes), avg(f.dead_bytes), avg(f.recomputable_bytes), percent
+    );
+}
+
+pub fn func_stats(fidx: usize, table: &StackTable) -> Result<FuncStats> {
+    let locals = table.locals();
+    let mut stats = FuncStats { fidx, ..Default::default() };
+    for (offset, stack) in table.inner() {
+        let live = table.live().get(offset).ok_or_else(|| anyhow::anyhow!("Live mask not found for offset {}", offset))?;
+        let recomputable = table.recomputable().get(offset).ok_or_else(|| anyhow::anyhow!("Recomputable flags not found for offset {}", offset))?;
+
+        stats.positions += 1;
+        for (ty, live) in locals.iter().zip(&live.locals) {
+            stats.total_bytes += ty.size() as usize;
+            if !live {
+                stats.dead_bytes += ty.size() as usize;
+            }
+        }
+        for (((_, ty), live), recomputable) in stack.iter().zip(&live.stack).zip(recomputable) {
+            stats.total_bytes += ty.size() as usize;
+            if !live {
+                stats.dead_bytes += ty.size() as usize;
+            } else if *recomputable {
+                stats.recomputable_bytes += ty.size() as usize;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_func_stats() -> Result<()> {
+        let wasm = wat::parse_str(r#"
+            (module
+              (func (param i64) (result i64)
+                i32.const 1
+                drop
+                local.get 0
+                local.get 0
+                i64.add))
+        "#)?;
+        let tables = StackTables::from_wasm(&wasm, false)?;
+        let stats = func_stats(0, &tables.0[0])?;
+        // i32.const 1 / drop / local.get 0 / local.get 0 / i64.add / end の6位置、ローカルは毎回8バイト
+        assert_eq!(stats.positions, 6);
+        assert_eq!(stats.total_bytes, 6 * 8 + 4 + 8 + 2 * 8 + 8 + 8);
+        // 2つ目の local.get 0 以降はローカルが死んでいる. 1 はdropされる
+        assert_eq!(stats.dead_bytes, 3 * 8 + 4);
+        // 1つ目の local.get 0 のコピーだけ再計算できる. 2つ目の後はローカルが保存されないので
+        // コピーは保存する (ローカルとコピーを両方省いて二重に数えない)
+        assert_eq!(stats.recomputable_bytes, 8);
+        Ok(())
+    }
+}
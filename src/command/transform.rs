@@ -0,0 +1,801 @@
+use crate::command::output::{Output, write_file};
+use crate::core::function_v2::{BytecodeFunction, Function};
+use crate::core::module;
+use crate::core::stack_table::{StackTable, StackTables};
+use crate::core::val::WasmType;
+
+use std::collections::HashMap;
+
+use camino::Utf8PathBuf;
+use walrus::ir::{
+    BinaryOp, Binop, Const, Drop, GlobalGet, GlobalSet, IfElse, Instr, InstrSeqId, InstrSeqType, Load, LoadKind,
+    LocalGet, LocalSet, MemArg, Return, Store, StoreKind, Unreachable, Value,
+};
+use walrus::{
+    FunctionBuilder, GlobalId, InitExpr, InstrLocId, LocalFunction, LocalId, MemoryId, Module, ModuleLocals, ModuleTypes, ValType,
+};
+use wasmparser::Operator;
+
+use anyhow::{Context, Result};
+
+/// `wacret_get_state` の値
+pub const STATE_NORMAL: i32 = 0;
+pub const STATE_UNWINDING: i32 = 1;
+pub const STATE_REWINDING: i32 = 2;
+
+/// Size of one saved value (a local or an operand stack slot) in linear memory
+const SLOT: u32 = 8;
+
+/// Rewrite a module so it can unwind its call stack into linear memory and rewind it on restore,
+/// in the style of Binaryen's Asyncify
+pub fn transform(path: Utf8PathBuf, output: Output) -> Result<()> {
+    let buf = std::fs::read(&path).with_context(|| format!("Failed to read {}", path))?;
+    let transformed = transform_module(&buf)?;
+    write_file(&output, "transformed.wasm", &transformed)
+}
+
+/// Instrument every function that makes calls and export the control functions:
+///
+/// - `wacret_start_unwind(ptr, end)`: 呼び出し元へ戻りながら各フレームを `[ptr, end)` に保存する
+/// - `wacret_stop_unwind() -> ptr`: 保存を終え、保存したデータの末尾を返す
+/// - `wacret_start_rewind(ptr)`: 次に呼ばれたエクスポート関数から保存したフレームを復元する
+/// - `wacret_stop_rewind()`: 復元を終える (最も内側のimportの中で呼ぶ)
+/// - `wacret_get_state() -> state`
+///
+/// 各フレームはスタックテーブルの型を使って、呼び出し前のオペランドスタック (引数を含む) とローカルを
+/// 固定長のレコード `[スタック (SLOT * 最大の高さ)][ローカル (SLOT * ローカル数)][呼び出し番号]` に保存する.
+/// 呼び出しを含むブロックの外側にオペランドスタックの値が残っている関数や、
+/// 末尾呼び出し・関数参照の呼び出し・例外を使う関数は扱えない
+pub fn transform_module(buf: &[u8]) -> Result<Vec<u8>> {
+    // 命令の位置 (ファイル先頭から) を関数内のオフセットに直すため、各関数の命令列の先頭を求める.
+    // walrusが読めない命令もあるので、扱えない命令の検査もここで先に行う
+    let wasm = buf.to_vec();
+    let m = module::new_module(&wasm)?;
+    let bases = m
+        .new_function_v2()?
+        .iter()
+        .map(|f| match f {
+            Function::BytecodeFunction(f) => {
+                check_supported(f)?;
+                Ok(Some(f.code_base()?))
+            }
+            Function::ImportFunction(_) => Ok(None),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut module = Module::from_buffer(buf).map_err(|e| anyhow::anyhow!("Failed to parse WASM module: {}", e))?;
+    let tables = StackTables::from_wasm(buf, false)?;
+    let memory = module.get_memory_id().map_err(|e| anyhow::anyhow!("The module needs exactly one memory: {}", e))?;
+
+    let global = |module: &mut Module| module.globals.add_local(ValType::I32, true, InitExpr::Value(Value::I32(0)));
+    let rt = Runtime { state: global(&mut module), ptr: global(&mut module), end: global(&mut module), memory };
+
+    let ids = module.funcs.iter().map(|f| f.id()).collect::<Vec<_>>();
+    for (fidx, (id, base)) in ids.into_iter().zip(bases).enumerate() {
+        let Some(base) = base else { continue };
+        let results = module.types.get(module.funcs.get(id).ty()).results().to_vec();
+        let idx = module.locals.add(ValType::I32);
+        let tmps = [ValType::I32, ValType::I64, ValType::F32, ValType::F64]
+            .into_iter()
+            .map(|ty| (ty, module.locals.add(ty)))
+            .collect();
+        let func = match &mut module.funcs.get_mut(id).kind {
+            walrus::FunctionKind::Local(func) => func,
+            _ => continue,
+        };
+        let locals = used_locals(func)
+            .into_iter()
+            .map(|l| (l, module.locals.get(l).ty()))
+            .collect();
+
+        let table = &tables.0[fidx];
+        let height = table.inner().values().map(|s| s.len()).max().unwrap_or(0) as u32;
+        let mut cx = FuncContext {
+            rt: &rt,
+            table,
+            fidx,
+            base,
+            height,
+            locals,
+            idx,
+            tmps,
+            spills: HashMap::new(),
+            module_locals: &mut module.locals,
+            results,
+            next_call: 0,
+        };
+        cx.transform_function(func, &mut module.types)
+            .with_context(|| format!("Failed to transform function {}", fidx))?;
+    }
+
+    add_exports(&mut module, &rt);
+    Ok(module.emit_wasm())
+}
+
+/// 退避・復元できない命令を使っていないか.
+/// 末尾呼び出しは呼び出し元のフレームを先に捨て、関数参照の呼び出しや例外は呼び出し番号で復元先を表せない
+fn check_supported(f: &BytecodeFunction) -> Result<()> {
+    for (offset, op) in f.operators()? {
+        let name = match op {
+            Operator::ReturnCall { .. } => "return_call",
+            Operator::ReturnCallIndirect { .. } => "return_call_indirect",
+            Operator::CallRef { .. } => "call_ref",
+            Operator::ReturnCallRef { .. } => "return_call_ref",
+            Operator::Try { .. } => "try",
+            Operator::TryTable { .. } => "try_table",
+            Operator::Throw { .. } => "throw",
+            Operator::ThrowRef => "throw_ref",
+            Operator::Rethrow { .. } => "rethrow",
+            Operator::Delegate { .. } => "delegate",
+            _ => continue,
+        };
+        anyhow::bail!("Function {} uses {} at offset {}, which cannot be unwound", f.fidx, name, offset);
+    }
+    Ok(())
+}
+
+struct Runtime {
+    state: GlobalId,
+    ptr: GlobalId,
+    end: GlobalId,
+    memory: MemoryId,
+}
+
+fn add_exports(module: &mut Module, rt: &Runtime) {
+    let i32 = ValType::I32;
+    let (ptr, end) = (module.locals.add(i32), module.locals.add(i32));
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &[i32, i32], &[]);
+    builder.func_body()
+        .i32_const(STATE_UNWINDING).global_set(rt.state)
+        .local_get(ptr).global_set(rt.ptr)
+        .local_get(end).global_set(rt.end);
+    let f = builder.finish(vec![ptr, end], &mut module.funcs);
+    module.exports.add("wacret_start_unwind", f);
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[i32]);
+    builder.func_body().i32_const(STATE_NORMAL).global_set(rt.state).global_get(rt.ptr);
+    let f = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("wacret_stop_unwind", f);
+
+    let ptr = module.locals.add(i32);
+    let mut builder = FunctionBuilder::new(&mut module.types, &[i32], &[]);
+    builder.func_body().i32_const(STATE_REWINDING).global_set(rt.state).local_get(ptr).global_set(rt.ptr);
+    let f = builder.finish(vec![ptr], &mut module.funcs);
+    module.exports.add("wacret_start_rewind", f);
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.func_body().i32_const(STATE_NORMAL).global_set(rt.state);
+    let f = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("wacret_stop_rewind", f);
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[i32]);
+    builder.func_body().global_get(rt.state);
+    let f = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("wacret_get_state", f);
+}
+
+struct FuncContext<'a> {
+    rt: &'a Runtime,
+    table: &'a StackTable,
+    fidx: usize,
+    /// 命令列の先頭のファイル先頭からの位置
+    base: u32,
+    /// オペランドスタックの最大の高さ
+    height: u32,
+    /// 保存するローカル (引数と使われているローカル)
+    locals: Vec<(LocalId, ValType)>,
+    /// 復元する呼び出しの番号
+    idx: LocalId,
+    /// スタックの値をメモリに書き出すための一時ローカル
+    tmps: HashMap<ValType, LocalId>,
+    /// 呼び出しの引数を退避するローカル. 型ごとに、1つの呼び出しで使う数だけ作る
+    spills: HashMap<ValType, Vec<LocalId>>,
+    module_locals: &'a mut ModuleLocals,
+    results: Vec<ValType>,
+    /// 次に割り当てる呼び出し番号 (コード順)
+    next_call: i32,
+}
+
+impl FuncContext<'_> {
+    fn record_size(&self) -> u32 {
+        SLOT * (self.height + self.locals.len() as u32 + 1)
+    }
+
+    fn idx_offset(&self) -> u32 {
+        SLOT * (self.height + self.locals.len() as u32)
+    }
+
+    fn transform_function(&mut self, func: &mut LocalFunction, types: &mut ModuleTypes) -> Result<()> {
+        let entry = func.entry_block();
+        if !seq_has_calls(func, entry) {
+            return Ok(());
+        }
+        self.transform_seq(func, types, entry)?;
+
+        // 復元: 関数の入口でレコードを取り出し、ローカルと呼び出し番号を読む
+        let mut restore = self.state_is(STATE_REWINDING);
+        let mut body = vec![
+            Instr::GlobalGet(GlobalGet { global: self.rt.ptr }),
+            i32_const(self.record_size() as i32),
+            binop(BinaryOp::I32Sub),
+            Instr::GlobalSet(GlobalSet { global: self.rt.ptr }),
+        ];
+        for (k, (local, ty)) in self.locals.iter().enumerate() {
+            body.push(Instr::GlobalGet(GlobalGet { global: self.rt.ptr }));
+            body.push(self.load(*ty, SLOT * (self.height + k as u32)));
+            body.push(Instr::LocalSet(LocalSet { local: *local }));
+        }
+        body.push(Instr::GlobalGet(GlobalGet { global: self.rt.ptr }));
+        body.push(self.load(ValType::I32, self.idx_offset()));
+        body.push(Instr::LocalSet(LocalSet { local: self.idx }));
+        restore.push(if_else(func, types, &[], &[], body, vec![]));
+
+        let instrs = &mut func.block_mut(entry).instrs;
+        let rest = std::mem::take(instrs);
+        *instrs = restore.into_iter().map(|i| (i, InstrLocId::default())).chain(rest).collect();
+        Ok(())
+    }
+
+    /// 呼び出しを含む命令列を書き換える. 呼び出しを含む命令列はオペランドスタックが空の状態から始まる
+    fn transform_seq(&mut self, func: &mut LocalFunction, types: &mut ModuleTypes, seq: InstrSeqId) -> Result<()> {
+        let instrs = std::mem::take(&mut func.block_mut(seq).instrs);
+        let seq_results = seq_results(types, func.block(seq).ty);
+
+        let mut out = vec![];
+        let mut before: Vec<ValType> = vec![];
+        // 呼び出しを含まない命令の並び. 復元中は実行せずに済ませる
+        let mut run: Vec<(Instr, InstrLocId)> = vec![];
+        let mut iter = instrs.into_iter();
+        while let Some((instr, loc)) = iter.next() {
+            if is_unconditional(&instr) {
+                // 以降は到達しないので、命令列の終わりまでまとめる
+                run.push((instr, loc));
+                run.extend(iter.by_ref());
+                let skip = self.state_is(STATE_REWINDING);
+                out.extend(self.skippable(func, types, skip, &before, &seq_results, std::mem::take(&mut run)));
+                before = seq_results.clone();
+                break;
+            }
+            if !contains_call(func, &instr) {
+                run.push((instr, loc));
+                continue;
+            }
+
+            if let Some((last, last_loc)) = run.last() {
+                let after = self.stack_after(func, last, *last_loc)?;
+                let skip = self.state_is(STATE_REWINDING);
+                out.extend(self.skippable(func, types, skip, &before, &after, std::mem::take(&mut run)));
+                before = after;
+            }
+
+            let after = self.stack_after(func, &instr, loc)?;
+            let offset = self.offset(loc);
+            let lo = self.next_call;
+            let element = match &instr {
+                Instr::Call(_) | Instr::CallIndirect(_) => {
+                    self.next_call += 1;
+                    let in_call = self.stack(offset + 1)?;
+                    if in_call.len() > before.len() || in_call[..] != before[..in_call.len()] {
+                        anyhow::bail!("The stack during the call at offset {} is not below its arguments", offset);
+                    }
+                    // 引数 (call_indirectならテーブルの添字も) を退避しておき、退避中はレコードに書き出す
+                    let args = self.spill_locals(&before[in_call.len()..]);
+                    let mut element: Vec<(Instr, InstrLocId)> = args
+                        .iter()
+                        .rev()
+                        .map(|(local, _)| Instr::LocalSet(LocalSet { local: *local }))
+                        .chain(args.iter().map(|(local, _)| Instr::LocalGet(LocalGet { local: *local })))
+                        .map(|i| (i, InstrLocId::default()))
+                        .collect();
+                    element.push((instr, loc));
+                    let skip = self.state_is_rewinding_elsewhere(lo, self.next_call);
+                    let mut element = self.skippable(func, types, skip, &before, &after, element);
+                    element.extend(self.unwind_check(func, types, lo, offset, &args, &after)?);
+                    element
+                }
+                Instr::Block(b) => {
+                    let inner = b.seq;
+                    self.check_empty_entry(func, types, inner, &before, 0, offset)?;
+                    self.transform_seq(func, types, inner)?;
+                    let skip = self.state_is_rewinding_elsewhere(lo, self.next_call);
+                    self.skippable(func, types, skip, &before, &after, vec![(instr, loc)])
+                }
+                Instr::Loop(l) => {
+                    let inner = l.seq;
+                    self.check_empty_entry(func, types, inner, &before, 0, offset)?;
+                    self.transform_seq(func, types, inner)?;
+                    let skip = self.state_is_rewinding_elsewhere(lo, self.next_call);
+                    self.skippable(func, types, skip, &before, &after, vec![(instr, loc)])
+                }
+                Instr::IfElse(e) => {
+                    let (consequent, alternative) = (e.consequent, e.alternative);
+                    self.check_empty_entry(func, types, consequent, &before, 1, offset)?;
+                    if seq_has_calls(func, consequent) {
+                        self.transform_seq(func, types, consequent)?;
+                    }
+                    let mid = self.next_call;
+                    if seq_has_calls(func, alternative) {
+                        self.transform_seq(func, types, alternative)?;
+                    }
+                    let skip = self.state_is_rewinding_elsewhere(lo, self.next_call);
+
+                    // 復元中は呼び出しのある方へ分岐させる
+                    let mut element = vec![];
+                    let mut then = vec![Instr::Drop(Drop {})];
+                    then.extend(self.idx_in(lo, mid));
+                    element.extend(self.state_is(STATE_REWINDING).into_iter().map(|i| (i, InstrLocId::default())));
+                    element.push((if_else(func, types, &[ValType::I32], &[ValType::I32], then, vec![]), InstrLocId::default()));
+                    element.push((instr, loc));
+                    self.skippable(func, types, skip, &before, &after, element)
+                }
+                _ => unreachable!("only calls and blocks contain calls"),
+            };
+            out.extend(element);
+            before = after;
+        }
+        if let Some((last, last_loc)) = run.last() {
+            let after = self.stack_after(func, last, *last_loc)?;
+            let skip = self.state_is(STATE_REWINDING);
+            out.extend(self.skippable(func, types, skip, &before, &after, std::mem::take(&mut run)));
+        }
+
+        func.block_mut(seq).instrs = out;
+        Ok(())
+    }
+
+    /// `skip` が真なら `element` を実行せず、入力を捨てて出力をレコードから読む
+    fn skippable(
+        &self,
+        func: &mut LocalFunction,
+        types: &mut ModuleTypes,
+        skip: Vec<Instr>,
+        before: &[ValType],
+        after: &[ValType],
+        element: Vec<(Instr, InstrLocId)>,
+    ) -> Vec<(Instr, InstrLocId)> {
+        let mut then = vec![Instr::Drop(Drop {}); before.len()];
+        for (j, ty) in after.iter().enumerate() {
+            then.push(Instr::GlobalGet(GlobalGet { global: self.rt.ptr }));
+            then.push(self.load(*ty, SLOT * j as u32));
+        }
+
+        let ty = InstrSeqType::new(types, before, after);
+        let consequent = func.builder_mut().dangling_instr_seq(ty).id();
+        func.block_mut(consequent).instrs = then.into_iter().map(|i| (i, InstrLocId::default())).collect();
+        let alternative = func.builder_mut().dangling_instr_seq(ty).id();
+        func.block_mut(alternative).instrs = element;
+
+        skip.into_iter()
+            .chain(std::iter::once(Instr::IfElse(IfElse { consequent, alternative })))
+            .map(|i| (i, InstrLocId::default()))
+            .collect()
+    }
+
+    /// 型ごとに `types` の値を退避するローカルを割り当てる
+    fn spill_locals(&mut self, types: &[ValType]) -> Vec<(LocalId, ValType)> {
+        let mut used: HashMap<ValType, usize> = HashMap::new();
+        types
+            .iter()
+            .map(|ty| {
+                let n = used.entry(*ty).or_default();
+                let spills = self.spills.entry(*ty).or_default();
+                if spills.len() == *n {
+                    spills.push(self.module_locals.add(*ty));
+                }
+                *n += 1;
+                (spills[*n - 1], *ty)
+            })
+            .collect()
+    }
+
+    /// 呼び出しから戻ったときに退避中なら、呼び出し前のスタックとローカルを保存して関数から戻る.
+    /// 復元時は呼び出しの直前までを飛ばし、レコードから読んだ引数で呼び出し直す
+    fn unwind_check(
+        &self,
+        func: &mut LocalFunction,
+        types: &mut ModuleTypes,
+        call: i32,
+        offset: u32,
+        args: &[(LocalId, ValType)],
+        after: &[ValType],
+    ) -> Result<Vec<(Instr, InstrLocId)>> {
+        let in_call = self.stack(offset + 1)?;
+        if in_call.len() > after.len() || in_call[..] != after[..in_call.len()] {
+            anyhow::bail!("The stack during the call at offset {} is not below its results", offset);
+        }
+
+        // レコードが入りきらなければ、何も書き込まずにトラップ
+        let mut save = vec![
+            Instr::GlobalGet(GlobalGet { global: self.rt.ptr }),
+            i32_const(self.record_size() as i32),
+            binop(BinaryOp::I32Add),
+            Instr::GlobalGet(GlobalGet { global: self.rt.end }),
+            binop(BinaryOp::I32GtU),
+        ];
+        save.push(if_else(func, types, &[], &[], vec![Instr::Unreachable(Unreachable {})], vec![]));
+
+        save.extend(vec![Instr::Drop(Drop {}); after.len() - in_call.len()]);
+        for (j, ty) in in_call.iter().enumerate().rev() {
+            let tmp = self.tmps[ty];
+            save.push(Instr::LocalSet(LocalSet { local: tmp }));
+            save.push(Instr::GlobalGet(GlobalGet { global: self.rt.ptr }));
+            save.push(Instr::LocalGet(LocalGet { local: tmp }));
+            save.push(self.store(*ty, SLOT * j as u32));
+        }
+        for (i, (local, ty)) in args.iter().enumerate() {
+            save.push(Instr::GlobalGet(GlobalGet { global: self.rt.ptr }));
+            save.push(Instr::LocalGet(LocalGet { local: *local }));
+            save.push(self.store(*ty, SLOT * (in_call.len() + i) as u32));
+        }
+
+        for (k, (local, ty)) in self.locals.iter().enumerate() {
+            save.push(Instr::GlobalGet(GlobalGet { global: self.rt.ptr }));
+            save.push(Instr::LocalGet(LocalGet { local: *local }));
+            save.push(self.store(*ty, SLOT * (self.height + k as u32)));
+        }
+        save.push(Instr::GlobalGet(GlobalGet { global: self.rt.ptr }));
+        save.push(i32_const(call));
+        save.push(self.store(ValType::I32, self.idx_offset()));
+        save.extend([
+            Instr::GlobalGet(GlobalGet { global: self.rt.ptr }),
+            i32_const(self.record_size() as i32),
+            binop(BinaryOp::I32Add),
+            Instr::GlobalSet(GlobalSet { global: self.rt.ptr }),
+        ]);
+        save.extend(self.results.iter().map(|ty| zero(*ty)));
+        save.push(Instr::Return(Return {}));
+
+        let mut check = self.state_is(STATE_UNWINDING);
+        check.push(if_else(func, types, after, after, save, vec![]));
+        Ok(check.into_iter().map(|i| (i, InstrLocId::default())).collect())
+    }
+
+    /// 呼び出しを含むブロックは、外側のオペランドスタックが空で引数もないときだけ扱える
+    fn check_empty_entry(&self, func: &LocalFunction, types: &ModuleTypes, seq: InstrSeqId, before: &[ValType], operands: usize, offset: u32) -> Result<()> {
+        let has_params = match func.block(seq).ty {
+            InstrSeqType::Simple(_) => false,
+            InstrSeqType::MultiValue(ty) => !types.get(ty).params().is_empty(),
+        };
+        if before.len() != operands || has_params {
+            anyhow::bail!(
+                "The block at offset {} of function {} contains calls but has values on the operand stack around it",
+                offset, self.fidx
+            );
+        }
+        Ok(())
+    }
+
+    fn state_is(&self, state: i32) -> Vec<Instr> {
+        vec![
+            Instr::GlobalGet(GlobalGet { global: self.rt.state }),
+            i32_const(state),
+            binop(BinaryOp::I32Eq),
+        ]
+    }
+
+    /// 復元中で、復元する呼び出しが `[lo, hi)` にない
+    fn state_is_rewinding_elsewhere(&self, lo: i32, hi: i32) -> Vec<Instr> {
+        let mut instrs = self.state_is(STATE_REWINDING);
+        instrs.extend(self.compare_idx(lo, hi, BinaryOp::I32GeU));
+        instrs.push(binop(BinaryOp::I32And));
+        instrs
+    }
+
+    /// 復元する呼び出しの番号が `[lo, hi)` にある
+    fn idx_in(&self, lo: i32, hi: i32) -> Vec<Instr> {
+        self.compare_idx(lo, hi, BinaryOp::I32LtU)
+    }
+
+    /// `idx - lo` (符号なし) と `hi - lo` の比較
+    fn compare_idx(&self, lo: i32, hi: i32, op: BinaryOp) -> Vec<Instr> {
+        vec![
+            Instr::LocalGet(LocalGet { local: self.idx }),
+            i32_const(lo),
+            binop(BinaryOp::I32Sub),
+            i32_const(hi - lo),
+            binop(op),
+        ]
+    }
+
+    fn offset(&self, loc: InstrLocId) -> u32 {
+        loc.data() - self.base
+    }
+
+    fn stack(&self, offset: u32) -> Result<Vec<ValType>> {
+        self.table.inner()
+            .get(&offset)
+            .ok_or_else(|| anyhow::anyhow!("No stack at (fidx {}, offset {})", self.fidx, offset))?
+            .iter()
+            .map(|(_, ty)| val_type(*ty))
+            .collect()
+    }
+
+    /// 命令 (ブロックなら `end`) を実行した後のオペランドスタック
+    fn stack_after(&self, func: &LocalFunction, instr: &Instr, loc: InstrLocId) -> Result<Vec<ValType>> {
+        let end = match instr {
+            Instr::Block(b) => func.block(b.seq).end,
+            Instr::Loop(l) => func.block(l.seq).end,
+            Instr::IfElse(e) if !func.block(e.alternative).end.is_default() => func.block(e.alternative).end,
+            Instr::IfElse(e) => func.block(e.consequent).end,
+            _ => loc,
+        };
+        self.stack(self.offset(end))
+    }
+
+    fn load(&self, ty: ValType, offset: u32) -> Instr {
+        let (kind, align) = match ty {
+            ValType::I32 => (LoadKind::I32 { atomic: false }, 4),
+            ValType::I64 => (LoadKind::I64 { atomic: false }, 8),
+            ValType::F32 => (LoadKind::F32, 4),
+            ValType::F64 => (LoadKind::F64, 8),
+            _ => unreachable!("unsupported types are rejected by val_type"),
+        };
+        Instr::Load(Load { memory: self.rt.memory, kind, arg: MemArg { align, offset } })
+    }
+
+    fn store(&self, ty: ValType, offset: u32) -> Instr {
+        let (kind, align) = match ty {
+            ValType::I32 => (StoreKind::I32 { atomic: false }, 4),
+            ValType::I64 => (StoreKind::I64 { atomic: false }, 8),
+            ValType::F32 => (StoreKind::F32, 4),
+            ValType::F64 => (StoreKind::F64, 8),
+            _ => unreachable!("unsupported types are rejected by val_type"),
+        };
+        Instr::Store(Store { memory: self.rt.memory, kind, arg: MemArg { align, offset } })
+    }
+}
+
+fn val_type(ty: WasmType) -> Result<ValType> {
+    match ty {
+        WasmType::I32 => Ok(ValType::I32),
+        WasmType::I64 => Ok(ValType::I64),
+        WasmType::F32 => Ok(ValType::F32),
+        WasmType::F64 => Ok(ValType::F64),
+        _ => anyhow::bail!("Values of type {} cannot be saved to linear memory", ty.to_string()),
+    }
+}
+
+fn i32_const(value: i32) -> Instr {
+    Instr::Const(Const { value: Value::I32(value) })
+}
+
+fn binop(op: BinaryOp) -> Instr {
+    Instr::Binop(Binop { op })
+}
+
+fn zero(ty: ValType) -> Instr {
+    let value = match ty {
+        ValType::I32 => Value::I32(0),
+        ValType::I64 => Value::I64(0),
+        ValType::F32 => Value::F32(0.0),
+        ValType::F64 => Value::F64(0.0),
+        _ => unreachable!("unsupported types are rejected by val_type"),
+    };
+    Instr::Const(Const { value })
+}
+
+fn if_else(func: &mut LocalFunction, types: &mut ModuleTypes, params: &[ValType], results: &[ValType], then: Vec<Instr>, otherwise: Vec<Instr>) -> Instr {
+    let ty = InstrSeqType::new(types, params, results);
+    let mut seq = |instrs: Vec<Instr>| {
+        let id = func.builder_mut().dangling_instr_seq(ty).id();
+        func.block_mut(id).instrs = instrs.into_iter().map(|i| (i, InstrLocId::default())).collect();
+        id
+    };
+    let consequent = seq(then);
+    let alternative = seq(otherwise);
+    Instr::IfElse(IfElse { consequent, alternative })
+}
+
+fn seq_results(types: &ModuleTypes, ty: InstrSeqType) -> Vec<ValType> {
+    match ty {
+        InstrSeqType::Simple(ty) => ty.into_iter().collect(),
+        InstrSeqType::MultiValue(ty) => types.get(ty).results().to_vec(),
+    }
+}
+
+/// この命令の後は同じ命令列の命令に到達しない
+fn is_unconditional(instr: &Instr) -> bool {
+    matches!(instr, Instr::Br(_) | Instr::BrTable(_) | Instr::Return(_) | Instr::Unreachable(_))
+}
+
+fn contains_call(func: &LocalFunction, instr: &Instr) -> bool {
+    match instr {
+        Instr::Call(_) | Instr::CallIndirect(_) => true,
+        Instr::Block(b) => seq_has_calls(func, b.seq),
+        Instr::Loop(l) => seq_has_calls(func, l.seq),
+        Instr::IfElse(e) => seq_has_calls(func, e.consequent) || seq_has_calls(func, e.alternative),
+        _ => false,
+    }
+}
+
+/// 到達できる範囲に呼び出しがあるか
+fn seq_has_calls(func: &LocalFunction, seq: InstrSeqId) -> bool {
+    for (instr, _) in &func.block(seq).instrs {
+        if is_unconditional(instr) {
+            return false;
+        }
+        if contains_call(func, instr) {
+            return true;
+        }
+    }
+    false
+}
+
+/// 引数と、本体で読み書きされるローカル
+fn used_locals(func: &LocalFunction) -> Vec<LocalId> {
+    fn walk(func: &LocalFunction, seq: InstrSeqId, out: &mut Vec<LocalId>) {
+        for (instr, _) in &func.block(seq).instrs {
+            match instr {
+                Instr::LocalGet(l) => out.push(l.local),
+                Instr::LocalSet(l) => out.push(l.local),
+                Instr::LocalTee(l) => out.push(l.local),
+                Instr::Block(b) => walk(func, b.seq, out),
+                Instr::Loop(l) => walk(func, l.seq, out),
+                Instr::IfElse(e) => {
+                    walk(func, e.consequent, out);
+                    walk(func, e.alternative, out);
+                }
+                _ => {}
+            }
+        }
+    }
+    let mut used = vec![];
+    walk(func, func.entry_block(), &mut used);
+    used.retain(|l| !func.args.contains(l));
+    used.sort();
+    used.dedup();
+    func.args.iter().copied().chain(used).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmparser::{Parser, Payload};
+    use wasmi::{Caller, Engine, Extern, Instance, Linker, Store, Value};
+
+    /// 呼び出しの下にi64が残り、importの結果が引数に依存するループ
+    const WORKLOAD: &str = r#"
+        (module
+          (import "env" "sleep" (func $sleep (param i32) (result i32)))
+          (memory (export "memory") 1)
+          (func $work (param $n i32) (result i32) (local $i i32) (local $sum i64) (local $unused f64)
+            loop
+              local.get $sum
+              local.get $i
+              call $sleep
+              i64.extend_i32_u
+              i64.add
+              local.set $sum
+              local.get $i i32.const 1 i32.add local.tee $i
+              local.get $n i32.lt_u
+              br_if 0
+            end
+            local.get $sum
+            i32.wrap_i64)
+          (func (export "run") (param i32) (result i32)
+            i32.const 1000
+            local.get 0
+            call $work
+            i32.add))
+    "#;
+
+    const BUF: i32 = 1024;
+
+    /// `unwind_at` 回目の `sleep` で `[BUF, end)` への退避を始める
+    struct Host {
+        calls: u32,
+        unwind_at: u32,
+        end: i32,
+    }
+
+    fn call(store: &mut Store<Host>, instance: &Instance, name: &str, args: &[Value], results: usize) -> Result<Vec<Value>> {
+        let f = instance.get_func(&*store, name).ok_or_else(|| anyhow::anyhow!("{} is not exported", name))?;
+        let mut out = vec![Value::I32(0); results];
+        f.call(&mut *store, args, &mut out)?;
+        Ok(out)
+    }
+
+    fn instantiate(engine: &Engine, module: &wasmi::Module, unwind_at: u32, end: i32) -> Result<(Store<Host>, Instance)> {
+        let mut store = Store::new(engine, Host { calls: 0, unwind_at, end });
+        let mut linker = <Linker<Host>>::new(engine);
+        linker.func_wrap("env", "sleep", |mut caller: Caller<'_, Host>, arg: i32| -> i32 {
+            let func = |caller: &Caller<'_, Host>, name: &str| caller.get_export(name).and_then(Extern::into_func).unwrap();
+            let mut state = [Value::I32(0)];
+            func(&caller, "wacret_get_state").call(&mut caller, &[], &mut state).unwrap();
+            if state[0].i32() == Some(STATE_REWINDING) {
+                func(&caller, "wacret_stop_rewind").call(&mut caller, &[], &mut []).unwrap();
+            } else {
+                caller.data_mut().calls += 1;
+                if caller.data().calls == caller.data().unwind_at {
+                    let args = [Value::I32(BUF), Value::I32(caller.data().end)];
+                    func(&caller, "wacret_start_unwind").call(&mut caller, &args, &mut []).unwrap();
+                }
+            }
+            arg * 3 + 1
+        })?;
+        let instance = linker.instantiate(&mut store, module)?.start(&mut store)?;
+        Ok((store, instance))
+    }
+
+    fn memory(store: &Store<Host>, instance: &Instance) -> Vec<u8> {
+        let memory = instance.get_export(store, "memory").and_then(Extern::into_memory).unwrap();
+        memory.data(store).to_vec()
+    }
+
+    #[test]
+    fn test_unwind_and_rewind() -> Result<()> {
+        let transformed = transform_module(&wat::parse_str(WORKLOAD)?)?;
+        wasmparser::validate(&transformed)?;
+        let engine = Engine::default();
+        let module = wasmi::Module::new(&engine, &transformed[..])?;
+
+        let (mut store, instance) = instantiate(&engine, &module, 0, 0)?;
+        let expected = call(&mut store, &instance, "run", &[Value::I32(5)], 1)?[0].i32();
+        assert_eq!(expected, Some(1000 + (0..5).map(|i| i * 3 + 1).sum::<i32>()));
+
+        for unwind_at in 1..=5 {
+            let (mut store, instance) = instantiate(&engine, &module, unwind_at, 8192)?;
+            call(&mut store, &instance, "run", &[Value::I32(5)], 1)?;
+            let end = call(&mut store, &instance, "wacret_stop_unwind", &[], 1)?[0].i32().unwrap();
+            assert!(end > BUF);
+            let saved = memory(&store, &instance);
+
+            // 別のインスタンスに退避したデータを写して復元する. 引数は復元したローカルで上書きされる
+            let (mut store, instance) = instantiate(&engine, &module, 0, 0)?;
+            let memory_export = instance.get_export(&store, "memory").and_then(Extern::into_memory).unwrap();
+            memory_export.data_mut(&mut store)[..end as usize].copy_from_slice(&saved[..end as usize]);
+            call(&mut store, &instance, "wacret_start_rewind", &[Value::I32(end)], 0)?;
+            let rewound = call(&mut store, &instance, "run", &[Value::I32(0)], 1)?[0].i32();
+            assert_eq!(rewound, expected, "unwound at sleep {}", unwind_at);
+        }
+
+        // 退避先が足りなければ、何も書き込まずにトラップする
+        let (mut store, instance) = instantiate(&engine, &module, 2, BUF + 8)?;
+        assert!(call(&mut store, &instance, "run", &[Value::I32(5)], 1).is_err());
+        assert!(memory(&store, &instance).iter().all(|b| *b == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_transform_module() -> Result<()> {
+        let transformed = transform_module(&wat::parse_str(WORKLOAD)?)?;
+        let mut exports = vec![];
+        for payload in Parser::new(0).parse_all(&transformed) {
+            if let Payload::ExportSection(reader) = payload? {
+                for export in reader {
+                    exports.push(export?.name.to_string());
+                }
+            }
+        }
+        for name in ["run", "wacret_start_unwind", "wacret_stop_unwind", "wacret_start_rewind", "wacret_stop_rewind", "wacret_get_state"] {
+            assert!(exports.iter().any(|e| e == name), "{} is not exported", name);
+        }
+
+        // 呼び出しを含むブロックの外側に値が残っている
+        let wasm = wat::parse_str(r#"
+            (module
+              (import "env" "sleep" (func $sleep))
+              (memory 1)
+              (func (result i32)
+                i32.const 1
+                block
+                  call $sleep
+                end))
+        "#)?;
+        let err = transform_module(&wasm).unwrap_err();
+        assert!(format!("{:#}", err).contains("has values on the operand stack"), "{:#}", err);
+
+        // 末尾呼び出しは退避できない
+        let wasm = wat::parse_str(r#"
+            (module
+              (import "env" "sleep" (func $sleep))
+              (memory 1)
+              (func $f call $sleep)
+              (func return_call $f))
+        "#)?;
+        let err = transform_module(&wasm).unwrap_err();
+        assert!(err.to_string().contains("Function 2 uses return_call at offset 0"), "{:#}", err);
+        Ok(())
+    }
+}
@@ -0,0 +1,22 @@
+use crate::command::output::{Output, write_file};
+use crate::command::view::utils::state::CallStack;
+use crate::compile::compile::{InterpMode, Translator};
+
+use camino::Utf8PathBuf;
+use prost::Message;
+
+use anyhow::{Context, Result};
+
+/// Translate a `CallStack` snapshot taken on one WAMR interpreter so it can be restored on the other
+pub fn translate(wasm: Utf8PathBuf, snapshot: Utf8PathBuf, from: InterpMode, to: InterpMode, output: Output) -> Result<()> {
+    let wasm_buf = std::fs::read(&wasm).with_context(|| format!("Failed to read {}", wasm))?;
+    let snapshot_buf = std::fs::read(&snapshot).with_context(|| format!("Failed to read {}", snapshot))?;
+
+    let call_stack = CallStack::decode(&snapshot_buf[..])
+        .with_context(|| format!("Failed to decode {} as a CallStack", snapshot))?;
+    let translator = Translator::from_wasm(&wasm_buf)?;
+    let translated = translator.translate_call_stack(&call_stack, from, to)?;
+
+    write_file(&output, "translated-snapshot.pb", &translated.encode_to_vec())?;
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use crate::core::verify;
+use crate::core::stack_table::{Label, StackTables};
+use crate::command::view::utils::state::{CallStack, LabelStack};
+
+use camino::Utf8PathBuf;
+use prost::Message;
+use anyhow::{Context, Result};
+
+/// Check the stack tables generated for a wasm module against the validator's operand stack
+pub fn verify(path: Utf8PathBuf, v1: bool) -> Result<()> {
+    let buf: Vec<u8> = std::fs::read(&path)
+        .map_err(|e| anyhow::anyhow!("Failed to read input file {}: {}", path, e))?;
+
+    let mismatches = if v1 {
+        verify::verify_stack_tables_v1(&buf)?
+    } else {
+        verify::verify_stack_tables(&buf)?
+    };
+
+    for mismatch in &mismatches {
+        println!("{}", mismatch);
+    }
+
+    if !mismatches.is_empty() {
+        anyhow::bail!("{} mismatch(es) found in the stack tables of {}", mismatches.len(), path);
+    }
+    println!("stack tables of {} match the validator", path);
+
+    Ok(())
+}
+
+/// Check the label stacks of a snapshot against the ones computed from the code positions
+pub fn verify_labels(wasm: Utf8PathBuf, table: Utf8PathBuf, snapshot: Utf8PathBuf) -> Result<()> {
+    let wasm_buf = std::fs::read(&wasm).with_context(|| format!("Failed to read {}", wasm))?;
+    let table_buf = std::fs::read(&table).with_context(|| format!("Failed to read {}", table))?;
+    let snapshot_buf = std::fs::read(&snapshot).with_context(|| format!("Failed to read {}", snapshot))?;
+
+    let (_, tables) = StackTables::deserialize_for(&table_buf, &wasm_buf)?;
+    let call_stack = CallStack::decode(&snapshot_buf[..])
+        .with_context(|| format!("Failed to decode {} as a CallStack", snapshot))?;
+
+    let mismatches = compare_label_stacks(&tables, &call_stack)?;
+    for mismatch in &mismatches {
+        println!("{}", mismatch);
+    }
+
+    if !mismatches.is_empty() {
+        anyhow::bail!("{} label stack(s) in {} differ from the stack tables", mismatches.len(), snapshot);
+    }
+    println!("label stacks of {} match the stack tables", snapshot);
+
+    Ok(())
+}
+
+/// Returns a description of every frame whose label stack differs from the computed one.
+/// Frames without a label stack are skipped.
+pub fn compare_label_stacks(tables: &StackTables, call_stack: &CallStack) -> Result<Vec<String>> {
+    let mut mismatches = vec![];
+    for (depth, entry) in call_stack.entries.iter().enumerate() {
+        let actual = match &entry.label_stack {
+            Some(labels) => labels,
+            None => continue,
+        };
+        let pc = entry.pc.as_ref().ok_or_else(|| anyhow::anyhow!("Frame {} has no pc", depth))?;
+        let (fidx, offset) = (pc.fidx as usize, pc.offset as u32);
+        if fidx >= tables.0.len() {
+            anyhow::bail!("Frame {}: function {} is out of range ({} functions)", depth, fidx, tables.0.len());
+        }
+
+        let expected = to_label_stack(tables.get_labels(fidx, offset)?);
+        if *actual != expected {
+            mismatches.push(format!(
+                "frame {} (fidx {}, offset {}): expected {:?}, but the snapshot has {:?}",
+                depth, fidx, offset, expected, actual
+            ));
+        }
+    }
+    Ok(mismatches)
+}
+
+/// スタックテーブルのラベルをスナップショットの形式に変換する
+pub fn to_label_stack(labels: &[Label]) -> LabelStack {
+    LabelStack {
+        begins: labels.iter().map(|l| l.begin).collect(),
+        targets: labels.iter().map(|l| l.target).collect(),
+        stack_pointers: labels.iter().map(|l| l.stack_pointer).collect(),
+        cell_nums: labels.iter().map(|l| l.cell_num).collect(),
+    }
+}
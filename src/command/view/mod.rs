@@ -1,6 +1,8 @@
 pub mod view_v1;
 pub mod view_protobuf;
+pub mod view_instance;
 pub mod utils;
 
 pub use view_v1::{view_v1_format, view_v1_format_multiple};
 pub use view_protobuf::view_protobuf;
+pub use view_instance::view_instance;
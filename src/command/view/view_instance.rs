@@ -0,0 +1,229 @@
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf;
+use prost::Message;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use wasmparser::{ExternalKind, KnownCustom, Name, Parser, Payload};
+
+use crate::command::view::utils::state::{Global, InstanceState};
+use crate::core::module;
+use crate::core::val::{valtype_to_wasmtype, WasmType};
+
+/// Size of a linear memory page
+pub const PAGE_SIZE: usize = 65536;
+/// Table element that holds a null reference
+pub const NULL_REF: u32 = 0xffffffff;
+
+#[derive(Debug, Serialize)]
+pub struct InstanceSummary {
+    pub memories: Vec<MemorySummary>,
+    pub globals: Vec<GlobalSummary>,
+    pub tables: Vec<TableSummary>,
+    pub dropped_data: Vec<u32>,
+    pub dropped_elems: Vec<u32>,
+    pub frames: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemorySummary {
+    pub index: u32,
+    pub pages: u32,
+    pub bytes: u64,
+    pub dirty_pages: Vec<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GlobalSummary {
+    pub index: u32,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub value: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TableSummary {
+    pub index: u32,
+    pub size: usize,
+    pub non_null: usize,
+}
+
+/// Print a summary of an `InstanceState` file in JSON format.
+/// Global names and types are taken from the module the state was taken on
+pub fn view_instance(path: Utf8PathBuf, wasm: Utf8PathBuf) -> Result<()> {
+    let data = fs::read(&path).with_context(|| format!("Failed to read {}", path))?;
+    let buf = fs::read(&wasm).with_context(|| format!("Failed to read {}", wasm))?;
+    let state = InstanceState::decode(&data[..])
+        .with_context(|| format!("Failed to decode {} as an InstanceState", path))?;
+
+    let summary = summarize_instance(&state, &buf)?;
+    println!("{}", serde_json::to_string_pretty(&summary)?);
+    Ok(())
+}
+
+pub fn summarize_instance(state: &InstanceState, buf: &[u8]) -> Result<InstanceSummary> {
+    let wasm = buf.to_vec();
+    let m = module::new_module(&wasm)?;
+    let names = global_names(buf)?;
+
+    let mut memories = vec![];
+    for memory in &state.memories {
+        let mut dirty_pages = vec![];
+        for page in &memory.pages {
+            if page.index >= memory.page_count {
+                anyhow::bail!("Memory {}: page {} is out of range ({} pages)", memory.index, page.index, memory.page_count);
+            }
+            if page.contents.len() != PAGE_SIZE {
+                anyhow::bail!("Memory {}: page {} has {} bytes", memory.index, page.index, page.contents.len());
+            }
+            dirty_pages.push(page.index);
+        }
+        dirty_pages.sort();
+        memories.push(MemorySummary {
+            index: memory.index,
+            pages: memory.page_count,
+            bytes: memory.page_count as u64 * PAGE_SIZE as u64,
+            dirty_pages,
+        });
+    }
+
+    let mut globals = vec![];
+    for global in &state.globals {
+        let ty = m.globals
+            .get(global.index as usize)
+            .ok_or_else(|| anyhow::anyhow!("Global {} is not defined in the module ({} globals)", global.index, m.globals.len()))?;
+        let wasm_type = valtype_to_wasmtype(&ty.content_type);
+        if global.value_type != wasm_type as u32 {
+            anyhow::bail!("Global {} is {} in the module, but the state has type {}", global.index, wasm_type.to_string(), global.value_type);
+        }
+        if !ty.mutable {
+            anyhow::bail!("Global {} is immutable and should not be in the state", global.index);
+        }
+        globals.push(GlobalSummary {
+            index: global.index,
+            name: names.get(&global.index).cloned().unwrap_or_else(|| format!("global{}", global.index)),
+            ty: wasm_type.to_string().to_owned(),
+            value: global_value(global, wasm_type)?,
+        });
+    }
+
+    let tables = state
+        .tables
+        .iter()
+        .map(|table| TableSummary {
+            index: table.index,
+            size: table.elements.len(),
+            non_null: table.elements.iter().filter(|e| **e != NULL_REF).count(),
+        })
+        .collect();
+
+    Ok(InstanceSummary {
+        memories,
+        globals,
+        tables,
+        dropped_data: state.dropped_data.clone(),
+        dropped_elems: state.dropped_elems.clone(),
+        frames: state.call_stack.as_ref().map_or(0, |c| c.entries.len()),
+    })
+}
+
+/// セルを型に合わせて1つの値にする. 64bitの値は下位のセルが先
+fn global_value(global: &Global, ty: WasmType) -> Result<serde_json::Value> {
+    let cells = &global.cells;
+    if cells.len() != ty.cell_num() {
+        anyhow::bail!("Global {} has {} cells, but {} needs {}", global.index, cells.len(), ty.to_string(), ty.cell_num());
+    }
+    let wide = || ((cells[1] as u64) << 32) | cells[0] as u64;
+    let value = match ty {
+        WasmType::I32 => (cells[0] as i32).into(),
+        WasmType::I64 => (wide() as i64).into(),
+        WasmType::F32 => (f32::from_bits(cells[0]) as f64).into(),
+        WasmType::F64 => f64::from_bits(wide()).into(),
+        WasmType::FuncRef | WasmType::ExternRef if cells[0] == NULL_REF => serde_json::Value::Null,
+        WasmType::FuncRef | WasmType::ExternRef => cells[0].into(),
+        _ => cells.iter().map(|c| format!("{:08x}", c)).collect::<Vec<_>>().join(" ").into(),
+    };
+    Ok(value)
+}
+
+/// グローバル変数の名前. name セクションの名前を優先し、なければexport名を使う
+fn global_names(buf: &[u8]) -> Result<HashMap<u32, String>> {
+    let mut exports = HashMap::new();
+    let mut names = HashMap::new();
+    for payload in Parser::new(0).parse_all(buf) {
+        match payload? {
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export?;
+                    if export.kind == ExternalKind::Global {
+                        exports.entry(export.index).or_insert_with(|| export.name.to_string());
+                    }
+                }
+            }
+            Payload::CustomSection(reader) => {
+                if let KnownCustom::Name(reader) = reader.as_known() {
+                    for name in reader {
+                        // 壊れたnameセクションは無視する
+                        let Ok(Name::Global(map)) = name else { continue };
+                        for naming in map.into_iter().flatten() {
+                            names.insert(naming.index, naming.name.to_string());
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    exports.extend(names);
+    Ok(exports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::view::utils::state::{CallStack, Memory, MemoryPage, Table};
+
+    #[test]
+    fn test_summarize_instance() -> Result<()> {
+        let wasm = wat::parse_str(r#"
+            (module
+              (import "env" "g" (global $imported (mut i32)))
+              (global $counter (mut i64) (i64.const 0))
+              (global (export "ratio") (mut f64) (f64.const 0))
+              (memory 2)
+              (table 3 funcref))
+        "#)?;
+        let state = InstanceState {
+            memories: vec![Memory {
+                index: 0,
+                page_count: 2,
+                pages: vec![MemoryPage { index: 1, contents: vec![1; PAGE_SIZE] }],
+            }],
+            globals: vec![
+                Global { index: 1, value_type: WasmType::I64 as u32, cells: vec![0xffffffff, 0x7fffffff] },
+                Global { index: 2, value_type: WasmType::F64 as u32, cells: vec![0, 0x3ff80000] },
+            ],
+            tables: vec![Table { index: 0, elements: vec![NULL_REF, 3, NULL_REF] }],
+            dropped_data: vec![],
+            dropped_elems: vec![0],
+            call_stack: Some(CallStack { entries: vec![] }),
+        };
+        let summary = summarize_instance(&state, &wasm)?;
+
+        assert_eq!(summary.memories[0].bytes, 2 * PAGE_SIZE as u64);
+        assert_eq!(summary.memories[0].dirty_pages, vec![1]);
+        assert_eq!(summary.globals[0].name, "counter");
+        assert_eq!(summary.globals[0].ty, "I64");
+        assert_eq!(summary.globals[0].value, serde_json::json!(0x7fffffff_ffffffffi64));
+        assert_eq!(summary.globals[1].name, "ratio");
+        assert_eq!(summary.globals[1].value, serde_json::json!(1.5));
+        assert_eq!(summary.tables[0].non_null, 1);
+
+        // 型が合わない
+        let mut wrong = state.clone();
+        wrong.globals[0].value_type = WasmType::I32 as u32;
+        assert!(summarize_instance(&wrong, &wasm).is_err());
+        Ok(())
+    }
+}
@@ -4,7 +4,8 @@ use prost::Message;
 use serde_json;
 use std::{fs};
 
-use crate::command::view::utils::state::CallStack;
+use crate::command::view::utils::state::{CallStack, TypedArray};
+use crate::core::val::WasmType;
 use crate::command::view::utils::UnifiedFormat;
 
 pub fn parse_protobuf(path: &Utf8PathBuf, merged_stack: bool) -> Result<Vec<UnifiedFormat>> {
@@ -14,13 +15,9 @@ pub fn parse_protobuf(path: &Utf8PathBuf, merged_stack: bool) -> Result<Vec<Unif
     // Try CallStack first (most likely to be the top-level message)
     if let Ok(call_stack) = CallStack::decode(&data[..]) {
         return Ok(call_stack.entries.iter().map(|entry| {
-            let locals = entry.locals.as_ref().and_then(|locals| 
-                locals.values.as_ref().map(|array| array.contents.iter().map(|&v| v as i64).collect())
-            ).unwrap_or_else(Vec::new);
+            let locals = entry.locals.as_ref().map(typed_values).unwrap_or_else(Vec::new);
             
-            let value_stack = entry.value_stack.as_ref().and_then(|stack| 
-                stack.values.as_ref().map(|array| array.contents.iter().map(|&v| v as i64).collect())
-            ).unwrap_or_else(Vec::new);
+            let value_stack = entry.value_stack.as_ref().map(typed_values).unwrap_or_else(Vec::new);
 
             if merged_stack {
                 let merged_values = locals.into_iter()
@@ -52,6 +49,31 @@ pub fn parse_protobuf(path: &Utf8PathBuf, merged_stack: bool) -> Result<Vec<Unif
     anyhow::bail!("Unable to decode protobuf file as any known message type");
 }
 
+/// Values of a TypedArray. When types are present (e.g. after `restore-prep`),
+/// the 32-bit cells are re-sliced so that a 64-bit value is shown as one value.
+fn typed_values(array: &TypedArray) -> Vec<i64> {
+    let cells = array.values.as_ref().map_or(&[][..], |v| &v.contents[..]);
+    let types = match &array.types {
+        Some(types) if !types.contents.is_empty() => &types.contents,
+        _ => return cells.iter().map(|&v| v as i64).collect(),
+    };
+
+    let mut values = vec![];
+    let mut rest = cells;
+    for t in types {
+        let n = WasmType::from_u8(*t).map_or(1, |t| t.cell_num()).min(rest.len());
+        let (cell, tail) = rest.split_at(n);
+        match cell {
+            [low, high] => values.push((((*high as u64) << 32) | *low as u64) as i64),
+            // v128 などはセルのまま表示する
+            _ => values.extend(cell.iter().map(|&v| v as i64)),
+        }
+        rest = tail;
+    }
+    values.extend(rest.iter().map(|&v| v as i64));
+    values
+}
+
 pub fn view_protobuf(path: Utf8PathBuf, merged_stack: bool) -> Result<()> {
     let unified_format = parse_protobuf(&path, merged_stack)?;
     let pretty_json = serde_json::to_string_pretty(&unified_format)?;
@@ -225,6 +247,17 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_typed_values_joins_64bit_cells() {
+        use crate::command::view::utils::state::Array8;
+
+        let array = TypedArray {
+            types: Some(Array8 { contents: vec![WasmType::I32 as u8, WasmType::I64 as u8, WasmType::F32 as u8] }),
+            values: Some(Array32 { contents: vec![1, 0xffffffff, 0x7fffffff, 3] }),
+        };
+        assert_eq!(typed_values(&array), vec![1, 0x7fffffff_ffffffff, 3]);
+    }
+
     #[test]
     fn test_parse_protobuf_invalid_data() {
         let mut temp_file = NamedTempFile::new().unwrap();
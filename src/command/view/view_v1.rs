@@ -6,7 +6,7 @@ use std::fs;
 use super::utils::{read_u32, read_u32_or_zero, read_u8, bytes_to_int, Label, UnifiedFormat};
 
 /// Parse a v1 format binary file and return the parsed data
-fn parse_v1_format(path: &Utf8PathBuf) -> Result<UnifiedFormat> {
+pub fn parse_v1_format(path: &Utf8PathBuf) -> Result<UnifiedFormat> {
     let data = fs::read(path)?;
     let mut cursor = 0;
 
@@ -1,1227 +1,362 @@
-use wasmparser::{FunctionBody, Operator, ValType, FuncType, BlockType};
-use crate::core::function::{BytecodeFunction, Function, CodePos};
-use crate::core::module::Module;
-use crate::core::val::{ValInfo, WasmType};
-use anyhow::Result;
-use strum_macros::{Display, EnumString};
-
+//! WAMRのclassicインタプリタとfastインタプリタのフレームの対応付け
+//!
+//! classicインタプリタは全ての値をオペランドスタック (32bitセルの列) に積むが、
+//! fastインタプリタはコンパイル時に `local.get` と定数をオペランドから取り除き、
+//! ローカル領域・定数領域を直接参照する. そのため同じコード位置でも
+//! fastインタプリタのオペランドスタックには動的に計算された値しか残らない.
+//!
+//! スタックテーブルの各要素には値を積んだ命令 (`CompiledOp`) が記録されているので、
+//! それを使って各値が実体を持つか (`SpaceKind`) を決め、一方のレイアウトからもう一方のレイアウトへ変換する.
+//!
+//! fastインタプリタは `local.set` / `local.tee` でローカルを書き換えるとき、そのローカルを参照している
+//! スタック上の値を動的な領域に退避する (preserve). 退避された値はそれ以降オペランドスタックに実体を持つ.
 
-#[derive(Clone)]
-pub struct OpType {
-    pub input: Vec<WasmType>,
-    pub output: Vec<WasmType>,
+use anyhow::Result;
+use clap::ValueEnum;
+use indexmap::IndexMap;
+use wasmparser::Operator;
+
+use crate::command::view::utils::state::{Array32, Array8, CallStack, CallStackEntry, LabelStack, TypedArray};
+use crate::core::function_v2::{BytecodeFunction, Function};
+use crate::core::module;
+use crate::core::stack_table::{from_codepos, CompiledOp, Offset, Stack};
+use crate::core::val::{SpaceKind, ValInfo, WasmType};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum InterpMode {
+    /// classic (standard) インタプリタ
+    Classic,
+    /// fastインタプリタ
+    Fast,
 }
 
-#[derive(Clone)]
-pub struct CompiledCodePos<'a> {
-    pub opcode: Operator<'a>,
-    pub optype: OpType,
-    pub offset: u32,
+/// 値を積んだ命令だけから決まる、値がオペランドスタック上に実体を持つか
+pub fn space_kind(op: &CompiledOp, mode: InterpMode) -> SpaceKind {
+    match (mode, op) {
+        (InterpMode::Fast, CompiledOp::LocalGet(_))
+        | (InterpMode::Fast, CompiledOp::I32Const(_))
+        | (InterpMode::Fast, CompiledOp::F32Const(_))
+        | (InterpMode::Fast, CompiledOp::I64Const(_))
+        | (InterpMode::Fast, CompiledOp::F64Const(_)) => SpaceKind::Static,
+        _ => SpaceKind::Dynamic,
+    }
 }
 
-pub struct CompiledBytecodeFunction<'a> {
-    pub locals: Vec<u8>,
-    pub codes: Vec<CompiledCodePos<'a>>,
+/// コード位置ごとのオペランドスタック、各値が実体を持つか、制御フレームの高さ
+pub struct CompiledCodePos {
+    pub stack: Stack,
+    pub vals: Vec<ValInfo>,
+    pub controls: Vec<usize>,
+}
 
-    // module: &'a Module<'a>,
-    // else_blockty: BlockType,
-    // func_body: &'a FunctionBody<'a>,
+/// 1つの関数をclassicまたはfastインタプリタ向けにコンパイルしたときのフレームのレイアウト
+pub struct CompiledBytecodeFunction {
+    pub locals: Vec<WasmType>,
+    pub codes: IndexMap<Offset, CompiledCodePos>,
 }
 
-impl<'a> CompiledBytecodeFunction<'a> {
-    pub fn new_standard_code(module: &Module, func: &BytecodeFunction<'a>) -> Self { 
-        return Self {
-            locals: func.locals.clone(),
-            codes: Self::compile_standard_bytecode(module, &func.codes),
-        };
+impl CompiledBytecodeFunction {
+    pub fn new_standard_code(f: &Function, bf: &BytecodeFunction) -> Result<Self> {
+        Self::compile(f, bf, InterpMode::Classic)
     }
 
-    pub fn new_fast_code(module: &Module, func: &BytecodeFunction<'a>) -> Self { 
-        return Self {
-            locals: func.locals.clone(),
-            codes: Self::compile_fast_bytecode(module, &func.codes),
-        };
+    pub fn new_fast_code(f: &Function, bf: &BytecodeFunction) -> Result<Self> {
+        Self::compile(f, bf, InterpMode::Fast)
     }
 
-    fn emit_label(codepos: &CodePos<'a>, input: Vec<WasmType> , output: Vec<WasmType>) -> CompiledCodePos<'a> {
-        let f = CompiledCodePos {
-            opcode: codepos.opcode.clone(),
-            optype: OpType { input: input, output: output},
-            offset: codepos.offset,
-        };
-
-        return f;
+    fn empty() -> Self {
+        Self { locals: vec![], codes: IndexMap::new() }
     }
 
-    // TODO: わかりやすい名前に変える
-    fn popf(stack: &mut Vec<ValInfo>, default_type: Vec<WasmType>) -> Vec<WasmType> {
-        let mut input = vec![];
-        for t in default_type {
-            let val_info = stack.pop().expect("[ERROR] stack is empty");
-            match val_info.space_kind {
-                SpaceKind::Dynamic => input.push(t),
-                SpaceKind::Static => {},
+    /// スタックテーブルをコード順にたどり、各値が実体を持つかを決める
+    fn compile(f: &Function, bf: &BytecodeFunction, mode: InterpMode) -> Result<Self> {
+        let mut codes = IndexMap::new();
+        let mut prev: Stack = vec![];
+        let mut vals: Vec<ValInfo> = vec![];
+        for codepos in bf.create_stack_table(false)? {
+            let controls = codepos.labels.iter().map(|l| l.height).collect();
+            let overwritten = match codepos.op {
+                Operator::LocalSet { local_index } | Operator::LocalTee { local_index } => Some(local_index),
+                _ => None,
+            };
+            let (offset, stack) = from_codepos(f, codepos);
+
+            // 直前のコード位置と同じ高さに同じ命令の値があれば、それは同じ値
+            let kept = prev.iter().zip(&stack).take_while(|(a, b)| a == b).count();
+            vals.truncate(kept);
+            vals.extend(stack[kept..].iter().map(|(op, _)| ValInfo::new(space_kind(op, mode))));
+
+            // 書き換えるローカルを参照している値は退避される
+            if let Some(idx) = overwritten {
+                for (val, (op, _)) in vals.iter_mut().zip(&stack) {
+                    if *op == CompiledOp::LocalGet(idx) {
+                        *val = ValInfo::new(SpaceKind::Dynamic);
+                    }
+                }
             }
-        }
-        return input;
-    }
 
-    // TODO: わかりやすい名前に変える
-    fn pushf(stack: &mut Vec<ValInfo>, default_type: Vec<WasmType>) -> Vec<WasmType> {
-        for _ in 0..default_type.len() {
-            stack.push(ValInfo::new(SpaceKind::Dynamic));
+            codes.insert(offset, CompiledCodePos { stack: stack.clone(), vals: vals.clone(), controls });
+            prev = stack;
         }
-        
-        return default_type;
+        Ok(Self { locals: bf.locals.clone(), codes })
     }
+}
 
-    pub fn compile_fast_bytecode(module: &Module, codes: &Vec<CodePos<'a>>) -> Vec<CompiledCodePos<'a>> {
-        // 仮スタック
-        // TODO: 多分stackにはコード位置も入る
-        let mut stack: Vec<ValInfo> = Vec::new();
-        let mut fast_bytecode: Vec<CompiledCodePos> = Vec::new();
-
-        for codepos in codes {
-            match codepos.opcode {
-                Operator::Unreachable => {
-                }
-                Operator::Nop => {
-                    // skip_label
-                }
-                Operator::Block{ .. } => {
-                    // skip_label
-                    // TODO: COPY_STACKをemitする
-                }
-                Operator::Loop{ .. } => {
-                    // skip_label
-                }
-                Operator::If{ .. } => {
-                }
-                Operator::Else{ .. } => {
-                }
-                Operator::End{ .. } => {
-                    // TODO: 挙動をちゃんと調べる
-                    let i: Vec<WasmType> = vec![];
-                    let o: Vec<WasmType> = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::Br{..} => {
-                    // [t1*, t*] -> [t2*]
-                    // NOTE: この位置では型スタックは変化しない
-                    let i: Vec<WasmType> = vec![];
-                    let o: Vec<WasmType> = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::BrIf{..} => {
-                    // [t1*, U32] -> [t2*]
-                    let i: Vec<WasmType> = vec![WasmType::U32];
-                    let o: Vec<WasmType> = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::BrTable{..} => {
-                    // [t1*, t*, U32] -> [t2*]
-                    let i: Vec<WasmType> = vec![WasmType::U32];
-                    let o: Vec<WasmType> = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::Return{ .. } => {
-                    // TODO: ほんとにbreakで良いのか確認
-                    let i: Vec<WasmType> = vec![];
-                    let o: Vec<WasmType> = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::Call{ function_index } => {
-                    // [Args*] -> [Rets*]
-                    let f = module.get_type_by_func(function_index);
-                    let params = f.params();
-                    let results = f.results();
-
-                    let i: Vec<WasmType> = params.iter().map(valtype_to_wasmtype).collect();
-                    let o: Vec<WasmType> = results.iter().map(valtype_to_wasmtype).collect();
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::CallIndirect{ type_index , .. } => {
-                    // [Args*, U32] -> [Rets*]
-                    let f = module.get_type_by_type(type_index);
-                    let params = f.params();
-                    let results = f.results();
-
-                    let i: Vec<WasmType> = params.iter().map(valtype_to_wasmtype)
-                                                 .chain(std::iter::once(WasmType::U32)).collect();
-                    let o: Vec<WasmType> = results.iter().map(valtype_to_wasmtype).collect();
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::Drop{ .. } => {
-                    // [Any] -> []
-                    // skip_label
-                    // let _ = stack.pop().expect("[ERROR] stack is empty");
-                    
-                    // TODO: skip_labelにする（ただし、stack.popするだけだとcalc_stackのときに反映されないのでどうするか考える)
-                    let i = vec![WasmType::Any];
-                    let o = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::Select{ .. } => {
-                    // [Any, Any, U32] -> [Any]
-                    let i = vec![WasmType::Any, WasmType::Any, WasmType::U32];
-                    let o = vec![WasmType::Any];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::TypedSelect{ .. } => {
-                    // [Any, Any, U32] -> [Any]
-                    let i = vec![WasmType::Any, WasmType::Any, WasmType::U32];
-                    let o = vec![WasmType::Any];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-
-                Operator::LocalGet{ local_index } => {
-                    // [] -> [Any]
-                    // skip_label
-                    // local_indexの型をstackにpushする
-                    stack.push(ValInfo::new(SpaceKind::Static));
-                }
-                Operator::LocalSet{ .. } => {
-                    // [Any] -> []
-                    // NOTE: 理解しやすくするために簡単にしている。
-                    // NOTE: preserveのためにCOPY命令が挿入されたり、LOCAL_SET命令が喪失したりするが一旦考慮しない
-                    let i = vec![WasmType::Any];
-                    let o = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::LocalTee{ .. } => {
-                    // [Any] -> [Any]
-                    // TODO: local.getをskipすることで発生するごにょごにょを処理しないといけない
-                    let i = vec![WasmType::Any];
-                    let o = vec![WasmType::Any];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::GlobalGet{ global_index } => {
-                    // [] -> [Any]
-                    let i = vec![];
-                    let o = vec![WasmType::Any];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::GlobalSet{ .. } => {
-                    // [Any] -> []
-                    let i = vec![WasmType::Any];
-                    let o = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::TableGet{ .. } => {
-                    // [U32] -> [Any]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::Any];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::TableSet{ .. } => {
-                    // [U32, Any] -> []
-                    let i = vec![WasmType::U32, WasmType::Any];
-                    let o = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-
-                Operator::I32Load{ .. } | Operator::F32Load{ .. } | 
-                Operator::I32Load8S{ .. } | Operator::I32Load8U{ .. } | Operator::I32Load16S{ .. } | Operator::I32Load16U{ .. } => {
-                    // [U32] -> [U32]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64Load{ .. } | Operator::F64Load{ .. } |
-                Operator::I64Load8S{ .. } | Operator::I64Load8U{ .. } | Operator::I64Load16S{ .. } | Operator::I64Load16U{ .. } | Operator::I64Load32S{ .. } | Operator::I64Load32U{ .. } => {
-                    // [U32] -> [U64]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-
-                Operator::I32Store{ .. } | Operator::I64Store{ .. } | Operator::F32Store{ .. } | Operator::F64Store{ .. } 
-                | Operator::I32Store8{ .. } | Operator::I32Store16 { .. } | Operator::I64Store8{ .. } | Operator::I64Store16 { .. } | Operator::I64Store32{ .. } => {
-                    // [U32, U32] -> []
-                    let i = vec![WasmType::U32, WasmType::U32];
-                    let o = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::MemorySize{ .. } => {
-                    // [] -> [U32]
-                    let i = vec![];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::MemoryGrow{ .. } => {
-                    // [U32] -> [U32]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-
-                Operator::I32Const{ .. } | Operator::F32Const{ .. } |
-                Operator::I64Const{ .. } | Operator::F64Const{ .. } => {
-                    // skip_label
-                    stack.push(ValInfo::new(SpaceKind::Static));
-                }
-
-                Operator::I32Eqz{ .. } => {
-                    // [U32] -> [U32]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I32Eq | Operator::I32Ne | Operator::I32LtS | Operator::I32LtU | Operator::I32GtS | Operator::I32GtU
-                | Operator::I32LeS | Operator::I32LeU | Operator::I32GeS | Operator::I32GeU 
-                | Operator::F32Eq | Operator::F32Ne | Operator::F32Lt | Operator::F32Gt | Operator::F32Le | Operator::F32Ge => {
-                    // [U32, U32] -> [U32]
-                    let i = vec![WasmType::U32, WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64Eqz{ .. } => {
-                    // [U64] -> [U32]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64Eq | Operator::I64Ne | Operator::I64LtS | Operator::I64LtU | Operator::I64GtS | Operator::I64GtU
-                | Operator::I64LeS | Operator::I64LeU | Operator::I64GeS | Operator::I64GeU
-                | Operator::F64Eq | Operator::F64Ne | Operator::F64Lt | Operator::F64Gt | Operator::F64Le | Operator::F64Ge => {
-                    // [U64, U64] -> [U32]
-                    let i = vec![WasmType::U64, WasmType::U64];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I32Clz | Operator::I32Ctz | Operator::I32Popcnt => {
-                    // [U32] -> [U32]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I32Add | Operator::I32Sub | Operator::I32Mul | Operator::I32DivS | Operator::I32DivU | Operator::I32RemS | Operator::I32RemU => {
-                    // [U32, U32] -> [U32]
-                    let i = vec![WasmType::U32, WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I32And | Operator::I32Or | Operator::I32Xor | Operator::I32Shl | Operator::I32ShrS | Operator::I32ShrU | Operator::I32Rotl | Operator::I32Rotr => {
-                    // [U32, U32] -> [U32]
-                    let i = vec![WasmType::U32, WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64Clz | Operator::I64Ctz | Operator::I64Popcnt => {
-                    // [U64] -> [U64]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64Add | Operator::I64Sub | Operator::I64Mul | Operator::I64DivS | Operator::I64DivU | Operator::I64RemS | Operator::I64RemU => {
-                    // [U64, U64] -> [U64]
-                    let i = vec![WasmType::U64, WasmType::U64];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64And | Operator::I64Or | Operator::I64Xor | Operator::I64Shl | Operator::I64ShrS | Operator::I64ShrU | Operator::I64Rotl | Operator::I64Rotr => {
-                    // [U64, U64] -> [U64]
-                    let i = vec![WasmType::U64, WasmType::U64];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-
-                Operator::F32Abs | Operator::F32Neg | Operator::F32Ceil | Operator::F32Floor | Operator::F32Trunc | Operator::F32Nearest | Operator::F32Sqrt => {
-                    // [U32] -> [U32]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F32Add | Operator::F32Sub | Operator::F32Mul | Operator::F32Div | Operator::F32Min | Operator::F32Max | Operator::F32Copysign => {
-                    // [f32 f32] -> [f32]
-                    let i = vec![WasmType::U32, WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F64Abs | Operator::F64Neg | Operator::F64Ceil | Operator::F64Floor | Operator::F64Trunc | Operator::F64Nearest | Operator::F64Sqrt => {
-                    // [f64] -> [f64]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F64Add | Operator::F64Sub | Operator::F64Mul | Operator::F64Div | Operator::F64Min | Operator::F64Max | Operator::F64Copysign => {
-                    // [f64 f64] -> [f64]
-                    let i = vec![WasmType::U64, WasmType::U64];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-
-                Operator::I32WrapI64 => {
-                    // [i64] -> [i32]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I32TruncF32S | Operator::I32TruncF32U => {
-                    // [f32] -> [i32]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I32TruncF64S | Operator::I32TruncF64U => {
-                    // [f64] -> [i32]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64ExtendI32S | Operator::I64ExtendI32U => {
-                    // [i32] -> [i64]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64TruncF32S | Operator::I64TruncF32U => {
-                    // [f32] -> [i64]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64TruncF64S | Operator::I64TruncF64U => {
-                    // [f64] -> [i64]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F32ConvertI32S | Operator::F32ConvertI32U => {
-                    // [i32] -> [f32]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F32ConvertI64S | Operator::F32ConvertI64U => {
-                    // [i64] -> [f32]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F32DemoteF64 => {
-                    // [i64] -> [f32]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F64ConvertI32S | Operator::F64ConvertI32U => {
-                    // [i32] -> [f64]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F64ConvertI64S | Operator::F64ConvertI64U => {
-                    // [i64] -> [f64]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F64PromoteF32 => {
-                    // [f32] -> [f64]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I32ReinterpretF32 | Operator::I64ReinterpretF64 | Operator::F32ReinterpretI32 | Operator::F64ReinterpretI64 => {
-                    // [t] -> [t]
-                    let i = vec![WasmType::Any];
-                    let o = vec![WasmType::Any];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I32Extend8S | Operator::I32Extend16S | Operator::I64Extend8S | Operator::I64Extend16S | Operator::I64Extend32S => {
-                    // [t] -> [t]
-                    let i = vec![WasmType::Any];
-                    let o = vec![WasmType::Any];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                
-                Operator::MemoryCopy{..} => {
-                    // [i32 i32 i32] -> []
-                    let i = vec![WasmType::U32, WasmType::U32, WasmType::U32];
-                    let o = vec![];
+/// 各関数をclassicとfastの両方のインタプリタ向けにコンパイルしたもの
+pub struct Translator {
+    classic: Vec<CompiledBytecodeFunction>,
+    fast: Vec<CompiledBytecodeFunction>,
+}
 
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::MemoryFill{..} => {
-                    // [i32 i32 i32] -> []
-                    let i = vec![WasmType::U32, WasmType::U32, WasmType::U32];
-                    let o = vec![];
+impl Translator {
+    pub fn from_wasm(buf: &[u8]) -> Result<Self> {
+        let buf = buf.to_vec();
+        let m = module::new_module(&buf)?;
+        let funcs = m.new_function_v2()?;
 
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
+        let mut classic = vec![];
+        let mut fast = vec![];
+        for f in &funcs {
+            match f {
+                Function::ImportFunction(_) => {
+                    classic.push(CompiledBytecodeFunction::empty());
+                    fast.push(CompiledBytecodeFunction::empty());
                 }
-
-                ref _other => {
-                    // println!("[WARN]: {:?}", op);
-                    log::error!("Unsupprted operator");
+                Function::BytecodeFunction(bf) => {
+                    classic.push(CompiledBytecodeFunction::new_standard_code(f, bf)?);
+                    fast.push(CompiledBytecodeFunction::new_fast_code(f, bf)?);
                 }
             }
         }
-
-        return fast_bytecode;
+        Ok(Self { classic, fast })
     }
 
-    pub fn compile_standard_bytecode(module: &Module, codes: &Vec<CodePos<'a>>) -> Vec<CompiledCodePos<'a>> {
-        // 仮スタック
-        // TODO: 多分stackにはコード位置も入る
-        let mut stack: Vec<ValInfo> = Vec::new();
-        let mut fast_bytecode: Vec<CompiledCodePos> = Vec::new();
-
-        let mut else_blockty = BlockType::Empty;
-
-        for codepos in codes {
-            match codepos.opcode {
-                Operator::Unreachable => {
-                    let i: Vec<WasmType> = vec![];
-                    let o: Vec<WasmType> = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::Nop => {
-                    // skip_label
-                    let i: Vec<WasmType> = vec![];
-                    let o: Vec<WasmType> = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::Block{ .. } => {
-                    // skip_label
-                    // TODO: COPY_STACKをemitする
-                    let i: Vec<WasmType> = vec![];
-                    let o: Vec<WasmType> = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::Loop{ .. } => {
-                    // skip_label
-                    let i: Vec<WasmType> = vec![];
-                    let o: Vec<WasmType> = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::If{ blockty } => {
-                    let i: Vec<WasmType> = vec![];
-                    let o: Vec<WasmType> = vec![];
+    fn compiled(&self, mode: InterpMode) -> &[CompiledBytecodeFunction] {
+        match mode {
+            InterpMode::Classic => &self.classic,
+            InterpMode::Fast => &self.fast,
+        }
+    }
 
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
+    /// スナップショットの全フレームを `from` のレイアウトから `to` のレイアウトに変換する
+    pub fn translate_call_stack(&self, call_stack: &CallStack, from: InterpMode, to: InterpMode) -> Result<CallStack> {
+        let entries = call_stack
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(depth, entry)| {
+                self.translate_frame(entry, from, to)
+                    .map_err(|e| anyhow::anyhow!("Failed to translate frame {}: {}", depth, e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(CallStack { entries })
+    }
 
-                    else_blockty = blockty;
-                }
-                Operator::Else{ .. } => {
-                    let i;
-                    let o;
+    pub fn translate_frame(&self, entry: &CallStackEntry, from: InterpMode, to: InterpMode) -> Result<CallStackEntry> {
+        let pc = entry.pc.as_ref().ok_or_else(|| anyhow::anyhow!("The frame has no pc"))?;
+        let (fidx, offset) = (pc.fidx as usize, pc.offset as u32);
+        if fidx >= self.classic.len() {
+            anyhow::bail!("Function {} is out of range ({} functions)", fidx, self.classic.len());
+        }
+        let (source_code, target_code) = (&self.compiled(from)[fidx], &self.compiled(to)[fidx]);
+        let local_types = &source_code.locals;
+        let (source_pos, target_pos) = match (source_code.codes.get(&offset), target_code.codes.get(&offset)) {
+            (Some(s), Some(t)) => (s, t),
+            _ => anyhow::bail!("No stack at (fidx {}, offset {})", fidx, offset),
+        };
+        let (stack, controls) = (&source_pos.stack, &source_pos.controls);
 
-                    // TODO: 多分この処理間違っている
-                    match else_blockty {
-                        BlockType::Empty => {
-                            i = vec![];
-                            o = vec![];
-                        }
-                        BlockType::Type( .. )=> {
-                            i = vec![WasmType::Any];
-                            o = vec![];
-                        }
-                        BlockType::FuncType(type_idx) => {
-                            // 関数型を持ってくる
-                            let func_type = module.get_type_by_type(type_idx);
-                            i = func_type.params()
-                                         .iter()
-                                         .map(|e| valtype_to_wasmtype(e))
-                                         .collect::<Vec<_>>();
+        let locals = cells_of(&entry.locals);
+        let expected_locals = local_types.iter().map(|t| t.cell_num()).sum::<usize>();
+        if locals.len() != expected_locals {
+            anyhow::bail!("The locals have {} cells, but (fidx {}) needs {}", locals.len(), fidx, expected_locals);
+        }
 
-                            o = func_type.results()
-                                         .iter()
-                                         .map(|e| valtype_to_wasmtype(e))
-                                         .collect::<Vec<_>>();
-                        }
+        // 元のレイアウトから各値のセルを取り出す (静的な値は命令から復元する)
+        let source = cells_of(&entry.value_stack);
+        let mut rest = source;
+        let mut values = vec![];
+        for ((op, ty), val) in stack.iter().zip(&source_pos.vals) {
+            let cells = match val.space_kind {
+                SpaceKind::Dynamic => {
+                    let n = ty.cell_num();
+                    if rest.len() < n {
+                        anyhow::bail!("The value stack has {} cells, which is too few for (fidx {}, offset {})", source.len(), fidx, offset);
                     }
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::End{ .. } => {
-                    // TODO: 挙動をちゃんと調べる
-                    let i: Vec<WasmType> = vec![];
-                    let o: Vec<WasmType> = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::Br{..} => {
-                    // [t1*, t*] -> [t2*]
-                    // NOTE: この位置では型スタックは変化しない
-                    let i: Vec<WasmType> = vec![];
-                    let o: Vec<WasmType> = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::BrIf{..} => {
-                    // [t1*, U32] -> [t2*]
-                    let i: Vec<WasmType> = vec![WasmType::U32];
-                    let o: Vec<WasmType> = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::BrTable{..} => {
-                    // [t1*, t*, U32] -> [t2*]
-                    let i: Vec<WasmType> = vec![WasmType::U32];
-                    let o: Vec<WasmType> = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::Return{ .. } => {
-                    // TODO: ほんとにbreakで良いのか確認
-                    let i: Vec<WasmType> = vec![];
-                    let o: Vec<WasmType> = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::Call{ function_index } => {
-                    // [Args*] -> [Rets*]
-                    let f = module.get_type_by_func(function_index);
-                    let params = f.params();
-                    let results = f.results();
-
-                    let i: Vec<WasmType> = params.iter().map(valtype_to_wasmtype).collect();
-                    let o: Vec<WasmType> = results.iter().map(valtype_to_wasmtype).collect();
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::CallIndirect{ type_index , .. } => {
-                    // [Args*, U32] -> [Rets*]
-                    let f = module.get_type_by_type(type_index);
-                    let params = f.params();
-                    let results = f.results();
-
-                    let i: Vec<WasmType> = params.iter().map(valtype_to_wasmtype)
-                                                 .chain(std::iter::once(WasmType::U32)).collect();
-                    let o: Vec<WasmType> = results.iter().map(valtype_to_wasmtype).collect();
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::Drop{ .. } => {
-                    // [Any] -> []
-                    // skip_label
-                    // let _ = stack.pop().expect("[ERROR] stack is empty");
-                    
-                    // TODO: skip_labelにする（ただし、stack.popするだけだとcalc_stackのときに反映されないのでどうするか考える)
-                    let i = vec![WasmType::Any];
-                    let o = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::Select{ .. } => {
-                    // [Any, Any, U32] -> [Any]
-                    let i = vec![WasmType::Any, WasmType::Any, WasmType::U32];
-                    let o = vec![WasmType::Any];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::TypedSelect{ .. } => {
-                    // [Any, Any, U32] -> [Any]
-                    let i = vec![WasmType::Any, WasmType::Any, WasmType::U32];
-                    let o = vec![WasmType::Any];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-
-                Operator::LocalGet{ local_index } => {
-                    // [] -> [Any]
-                    // skip_label
-                    // local_indexの型をstackにpushする
-                    let i = vec![];
-                    let o = vec![WasmType::Any];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
+                    let (cells, tail) = rest.split_at(n);
+                    rest = tail;
+                    cells.to_vec()
                 }
-                Operator::LocalSet{ .. } => {
-                    // [Any] -> []
-                    // NOTE: 理解しやすくするために簡単にしている。
-                    // NOTE: preserveのためにCOPY命令が挿入されたり、LOCAL_SET命令が喪失したりするが一旦考慮しない
-                    let i = vec![WasmType::Any];
-                    let o = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::LocalTee{ .. } => {
-                    // [Any] -> [Any]
-                    // TODO: local.getをskipすることで発生するごにょごにょを処理しないといけない
-                    let i = vec![WasmType::Any];
-                    let o = vec![WasmType::Any];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::GlobalGet{ global_index } => {
-                    // [] -> [Any]
-                    let i = vec![];
-                    let o = vec![WasmType::Any];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::GlobalSet{ .. } => {
-                    // [Any] -> []
-                    let i = vec![WasmType::Any];
-                    let o = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::TableGet{ .. } => {
-                    // [U32] -> [Any]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::Any];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::TableSet{ .. } => {
-                    // [U32, Any] -> []
-                    let i = vec![WasmType::U32, WasmType::Any];
-                    let o = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-
-                Operator::I32Load{ .. } | Operator::F32Load{ .. } | 
-                Operator::I32Load8S{ .. } | Operator::I32Load8U{ .. } | Operator::I32Load16S{ .. } | Operator::I32Load16U{ .. } => {
-                    // [U32] -> [U32]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64Load{ .. } | Operator::F64Load{ .. } |
-                Operator::I64Load8S{ .. } | Operator::I64Load8U{ .. } | Operator::I64Load16S{ .. } | Operator::I64Load16U{ .. } | Operator::I64Load32S{ .. } | Operator::I64Load32U{ .. } => {
-                    // [U32] -> [U64]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-
-                Operator::I32Store{ .. } | Operator::I64Store{ .. } | Operator::F32Store{ .. } | Operator::F64Store{ .. } 
-                | Operator::I32Store8{ .. } | Operator::I32Store16 { .. } | Operator::I64Store8{ .. } | Operator::I64Store16 { .. } | Operator::I64Store32{ .. } => {
-                    // [U32, U32] -> []
-                    let i = vec![WasmType::U32, WasmType::U32];
-                    let o = vec![];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::MemorySize{ .. } => {
-                    // [] -> [U32]
-                    let i = vec![];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::MemoryGrow{ .. } => {
-                    // [U32] -> [U32]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-
-                Operator::I32Const{ .. } | Operator::F32Const{ .. } |
-                Operator::I64Const{ .. } | Operator::F64Const{ .. } => {
-                    // skip_label
-                    let i = vec![];
-                    let o = vec![WasmType::Any];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-
-                Operator::I32Eqz{ .. } => {
-                    // [U32] -> [U32]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I32Eq | Operator::I32Ne | Operator::I32LtS | Operator::I32LtU | Operator::I32GtS | Operator::I32GtU
-                | Operator::I32LeS | Operator::I32LeU | Operator::I32GeS | Operator::I32GeU 
-                | Operator::F32Eq | Operator::F32Ne | Operator::F32Lt | Operator::F32Gt | Operator::F32Le | Operator::F32Ge => {
-                    // [U32, U32] -> [U32]
-                    let i = vec![WasmType::U32, WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64Eqz{ .. } => {
-                    // [U64] -> [U32]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64Eq | Operator::I64Ne | Operator::I64LtS | Operator::I64LtU | Operator::I64GtS | Operator::I64GtU
-                | Operator::I64LeS | Operator::I64LeU | Operator::I64GeS | Operator::I64GeU
-                | Operator::F64Eq | Operator::F64Ne | Operator::F64Lt | Operator::F64Gt | Operator::F64Le | Operator::F64Ge => {
-                    // [U64, U64] -> [U32]
-                    let i = vec![WasmType::U64, WasmType::U64];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I32Clz | Operator::I32Ctz | Operator::I32Popcnt => {
-                    // [U32] -> [U32]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I32Add | Operator::I32Sub | Operator::I32Mul | Operator::I32DivS | Operator::I32DivU | Operator::I32RemS | Operator::I32RemU => {
-                    // [U32, U32] -> [U32]
-                    let i = vec![WasmType::U32, WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I32And | Operator::I32Or | Operator::I32Xor | Operator::I32Shl | Operator::I32ShrS | Operator::I32ShrU | Operator::I32Rotl | Operator::I32Rotr => {
-                    // [U32, U32] -> [U32]
-                    let i = vec![WasmType::U32, WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64Clz | Operator::I64Ctz | Operator::I64Popcnt => {
-                    // [U64] -> [U64]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64Add | Operator::I64Sub | Operator::I64Mul | Operator::I64DivS | Operator::I64DivU | Operator::I64RemS | Operator::I64RemU => {
-                    // [U64, U64] -> [U64]
-                    let i = vec![WasmType::U64, WasmType::U64];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64And | Operator::I64Or | Operator::I64Xor | Operator::I64Shl | Operator::I64ShrS | Operator::I64ShrU | Operator::I64Rotl | Operator::I64Rotr => {
-                    // [U64, U64] -> [U64]
-                    let i = vec![WasmType::U64, WasmType::U64];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-
-                Operator::F32Abs | Operator::F32Neg | Operator::F32Ceil | Operator::F32Floor | Operator::F32Trunc | Operator::F32Nearest | Operator::F32Sqrt => {
-                    // [U32] -> [U32]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F32Add | Operator::F32Sub | Operator::F32Mul | Operator::F32Div | Operator::F32Min | Operator::F32Max | Operator::F32Copysign => {
-                    // [f32 f32] -> [f32]
-                    let i = vec![WasmType::U32, WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F64Abs | Operator::F64Neg | Operator::F64Ceil | Operator::F64Floor | Operator::F64Trunc | Operator::F64Nearest | Operator::F64Sqrt => {
-                    // [f64] -> [f64]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F64Add | Operator::F64Sub | Operator::F64Mul | Operator::F64Div | Operator::F64Min | Operator::F64Max | Operator::F64Copysign => {
-                    // [f64 f64] -> [f64]
-                    let i = vec![WasmType::U64, WasmType::U64];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-
-                Operator::I32WrapI64 => {
-                    // [i64] -> [i32]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I32TruncF32S | Operator::I32TruncF32U => {
-                    // [f32] -> [i32]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I32TruncF64S | Operator::I32TruncF64U => {
-                    // [f64] -> [i32]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64ExtendI32S | Operator::I64ExtendI32U => {
-                    // [i32] -> [i64]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64TruncF32S | Operator::I64TruncF32U => {
-                    // [f32] -> [i64]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I64TruncF64S | Operator::I64TruncF64U => {
-                    // [f64] -> [i64]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F32ConvertI32S | Operator::F32ConvertI32U => {
-                    // [i32] -> [f32]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U32];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F32ConvertI64S | Operator::F32ConvertI64U => {
-                    // [i64] -> [f32]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U64];
-
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F32DemoteF64 => {
-                    // [i64] -> [f32]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U32];
+                SpaceKind::Static => static_cells(op, local_types, locals)?,
+            };
+            values.push(cells);
+        }
+        if !rest.is_empty() {
+            anyhow::bail!("The value stack has {} cells, which is too many for (fidx {}, offset {})", source.len(), fidx, offset);
+        }
 
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F64ConvertI32S | Operator::F64ConvertI32U => {
-                    // [i32] -> [f64]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U64];
+        // 変換先のレイアウトで実体を持つ値だけを並べる
+        let mut value_cells = vec![];
+        let mut value_types = vec![];
+        for (((_, ty), cells), val) in stack.iter().zip(&values).zip(&target_pos.vals) {
+            if let SpaceKind::Dynamic = val.space_kind {
+                value_cells.extend_from_slice(cells);
+                value_types.push(*ty as u8);
+            }
+        }
 
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F64ConvertI64S | Operator::F64ConvertI64U => {
-                    // [i64] -> [f64]
-                    let i = vec![WasmType::U64];
-                    let o = vec![WasmType::U64];
+        let label_stack = entry
+            .label_stack
+            .as_ref()
+            .map(|labels| translate_labels(labels, stack, controls, &source_pos.vals, &target_pos.vals))
+            .transpose()?;
+
+        let value_stack = Some(TypedArray {
+            types: entry.value_stack.as_ref().and_then(|v| v.types.as_ref()).map(|_| Array8 { contents: value_types }),
+            values: Some(Array32 { contents: value_cells }),
+        });
+
+        Ok(CallStackEntry {
+            pc: entry.pc.clone(),
+            locals: entry.locals.clone(),
+            value_stack,
+            label_stack,
+        })
+    }
+}
 
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::F64PromoteF32 => {
-                    // [f32] -> [f64]
-                    let i = vec![WasmType::U32];
-                    let o = vec![WasmType::U64];
+/// ラベルのスタックポインタ (ブロックに入ったときのセル数) を変換先のレイアウトに合わせる.
+/// ラベルは関数フレームから順に、開いている制御フレームと1対1に対応する
+fn translate_labels(labels: &LabelStack, stack: &Stack, controls: &[usize], from: &[ValInfo], to: &[ValInfo]) -> Result<LabelStack> {
+    if labels.stack_pointers.len() != controls.len() {
+        anyhow::bail!("The label stack has {} labels, but {} blocks are open", labels.stack_pointers.len(), controls.len());
+    }
+    let (source, target) = (prefix_cells(stack, from), prefix_cells(stack, to));
+
+    let stack_pointers = labels
+        .stack_pointers
+        .iter()
+        .zip(controls)
+        .map(|(sp, height)| {
+            if *sp != source[*height] {
+                anyhow::bail!("Label stack pointer {} does not match the block height ({} cells)", sp, source[*height]);
+            }
+            Ok(target[*height])
+        })
+        .collect::<Result<Vec<_>>>()?;
 
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I32ReinterpretF32 | Operator::I64ReinterpretF64 | Operator::F32ReinterpretI32 | Operator::F64ReinterpretI64 => {
-                    // [t] -> [t]
-                    let i = vec![WasmType::Any];
-                    let o = vec![WasmType::Any];
+    Ok(LabelStack { stack_pointers, ..labels.clone() })
+}
 
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::I32Extend8S | Operator::I32Extend16S | Operator::I64Extend8S | Operator::I64Extend16S | Operator::I64Extend32S => {
-                    // [t] -> [t]
-                    let i = vec![WasmType::Any];
-                    let o = vec![WasmType::Any];
+/// スタックの先頭 n 要素が占めるセル数 (n = 0..=len)
+fn prefix_cells(stack: &Stack, vals: &[ValInfo]) -> Vec<u32> {
+    let mut cells = vec![0];
+    for ((_, ty), val) in stack.iter().zip(vals) {
+        let n = match val.space_kind {
+            SpaceKind::Dynamic => ty.cell_num() as u32,
+            SpaceKind::Static => 0,
+        };
+        cells.push(cells.last().unwrap() + n);
+    }
+    cells
+}
 
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                
-                Operator::MemoryCopy{..} => {
-                    // [i32 i32 i32] -> []
-                    let i = vec![WasmType::U32, WasmType::U32, WasmType::U32];
-                    let o = vec![];
+/// 静的な値のセルを命令から復元する
+fn static_cells(op: &CompiledOp, local_types: &[WasmType], locals: &[u32]) -> Result<Vec<u32>> {
+    let cells = match op {
+        CompiledOp::LocalGet(idx) => {
+            let idx = *idx as usize;
+            if idx >= local_types.len() {
+                anyhow::bail!("Local {} is out of range ({} locals)", idx, local_types.len());
+            }
+            let start = local_types[..idx].iter().map(|t| t.cell_num()).sum::<usize>();
+            locals[start..start + local_types[idx].cell_num()].to_vec()
+        }
+        CompiledOp::I32Const(v) => vec![*v as u32],
+        CompiledOp::F32Const(bits) => vec![*bits],
+        CompiledOp::I64Const(v) => split_u64(*v as u64),
+        CompiledOp::F64Const(bits) => split_u64(*bits),
+        CompiledOp::Call(_) | CompiledOp::Other(_) => unreachable!("dynamic value"),
+    };
+    Ok(cells)
+}
 
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
-                Operator::MemoryFill{..} => {
-                    // [i32 i32 i32] -> []
-                    let i = vec![WasmType::U32, WasmType::U32, WasmType::U32];
-                    let o = vec![];
+fn split_u64(n: u64) -> Vec<u32> {
+    vec![n as u32, (n >> 32) as u32]
+}
 
-                    fast_bytecode.push(
-                        Self::emit_label(codepos, Self::popf(&mut stack, i), Self::pushf(&mut stack, o))
-                    );
-                }
+fn cells_of(array: &Option<TypedArray>) -> &[u32] {
+    array.as_ref().and_then(|a| a.values.as_ref()).map_or(&[], |v| &v.contents[..])
+}
 
-                ref _other => {
-                    // println!("[WARN]: {:?}", op);
-                    log::error!("Unsupprted operator");
-                }
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::view::utils::state::CodePos;
+
+    fn frame(offset: u64, locals: Vec<u32>, stack: Vec<u32>, sps: Vec<u32>) -> CallStackEntry {
+        let n = sps.len();
+        CallStackEntry {
+            pc: Some(CodePos { fidx: 0, offset }),
+            locals: Some(TypedArray { types: None, values: Some(Array32 { contents: locals }) }),
+            value_stack: Some(TypedArray { types: None, values: Some(Array32 { contents: stack }) }),
+            label_stack: Some(LabelStack { begins: vec![0; n], targets: vec![0; n], stack_pointers: sps, cell_nums: vec![0; n] }),
         }
+    }
 
-        return fast_bytecode;
+    #[test]
+    fn test_translate_between_classic_and_fast() -> Result<()> {
+        let wasm = wat::parse_str(r#"
+            (module
+              (func (param i64 i32) (result i64)
+                local.get 0
+                local.get 1
+                i64.extend_i32_u
+                i64.const 5
+                block
+                  i64.const -2
+                  local.get 1
+                  unreachable
+                end
+                unreachable))
+        "#)?;
+        let translator = Translator::from_wasm(&wasm)?;
+        // local.get 1 (offset 11) の実行後
+        // stack: [local.get 0 (i64), extend (i64), i64.const 5, i64.const -2, local.get 1 (i32)]
+        let locals = vec![0x11, 0x22, 7];
+        let classic = frame(11, locals.clone(), vec![0x11, 0x22, 7, 0, 5, 0, 0xfffffffe, 0xffffffff, 7], vec![0, 6]);
+        let fast = frame(11, locals, vec![7, 0], vec![0, 2]);
+
+        let translated = translator.translate_frame(&classic, InterpMode::Classic, InterpMode::Fast)?;
+        assert_eq!(translated, fast);
+
+        let translated = translator.translate_frame(&fast, InterpMode::Fast, InterpMode::Classic)?;
+        assert_eq!(translated, classic);
+
+        // セル数が合わなければエラー
+        let broken = frame(11, vec![0x11, 0x22, 7], vec![7], vec![]);
+        assert!(translator.translate_frame(&broken, InterpMode::Fast, InterpMode::Classic).is_err());
+        Ok(())
     }
-}
 
+    #[test]
+    fn test_overwritten_local_stays_dynamic() -> Result<()> {
+        let wasm = wat::parse_str(r#"
+            (module
+              (func (param i32) (result i32)
+                local.get 0
+                i32.const 9
+                local.set 0
+                unreachable))
+        "#)?;
+        let translator = Translator::from_wasm(&wasm)?;
+        // local.set 0 (offset 4) の実行後. スタックの値は書き換える前のローカル (7) で、
+        // fastインタプリタでも退避されて実体を持つ
+        let classic = frame(4, vec![9], vec![7], vec![0]);
+        let fast = frame(4, vec![9], vec![7], vec![0]);
+
+        assert_eq!(translator.translate_frame(&classic, InterpMode::Classic, InterpMode::Fast)?, fast);
+        assert_eq!(translator.translate_frame(&fast, InterpMode::Fast, InterpMode::Classic)?, classic);
+        Ok(())
+    }
+}
@@ -1 +1 @@
-// pub mod compile;
\ No newline at end of file
+pub mod compile;
@@ -218,11 +218,11 @@ impl<'a> BytecodeFunction<'a> {
                 // [t] -> []
                 v.pop();
             }
-            Operator::TableGet{ .. } => {
+            Operator::TableGet{ table } => {
                 // [i32] -> [t]
                 v.pop();
-                // reftypeの場合は型スタックは255とする
-                v.push(255);
+                let table_type = self.module.get_type_by_table(*table);
+                v.push(valtype_to_size(&ValType::Ref(table_type.element_type)));
             }
             Operator::TableSet{ .. } => {
                 // [i32 t] -> []
@@ -417,6 +417,110 @@ impl<'a> BytecodeFunction<'a> {
                 v.pop();
             }
 
+            Operator::MemoryInit{..} => {
+                // [i32 i32 i32] -> []
+                v.pop();
+                v.pop();
+                v.pop();
+            }
+            Operator::DataDrop{..} | Operator::ElemDrop{..} => {
+                // [] -> []
+            }
+
+            // reference types
+            Operator::RefNull{..} | Operator::RefFunc{..} => {
+                // [] -> [t]
+                v.push(valtype_to_size(&ValType::FUNCREF));
+            }
+            Operator::RefIsNull => {
+                // [t] -> [i32]
+                v.pop();
+                v.push(1);
+            }
+
+            // table
+            Operator::TableSize{..} => {
+                // [] -> [i32]
+                v.push(1);
+            }
+            Operator::TableGrow{..} => {
+                // [t i32] -> [i32]
+                v.pop();
+            }
+            Operator::TableFill{..} | Operator::TableCopy{..} | Operator::TableInit{..} => {
+                // [i32 t i32] -> [] / [i32 i32 i32] -> []
+                v.pop();
+                v.pop();
+                v.pop();
+            }
+
+            // threads (atomics)
+            Operator::MemoryAtomicNotify{ .. } => {
+                // [i32 i32] -> [i32]
+                v.pop();
+            }
+            Operator::MemoryAtomicWait32{ .. } | Operator::MemoryAtomicWait64{ .. } => {
+                // [i32 t i64] -> [i32]
+                v.pop();
+                v.pop();
+                v.pop();
+                v.push(1);
+            }
+            Operator::AtomicFence => {
+                // [] -> []
+            }
+            Operator::I32AtomicLoad{ .. } | Operator::I32AtomicLoad8U{ .. } | Operator::I32AtomicLoad16U{ .. } => {
+                // [i32] -> [i32]
+            }
+            Operator::I64AtomicLoad{ .. } | Operator::I64AtomicLoad8U{ .. } | Operator::I64AtomicLoad16U{ .. } |
+            Operator::I64AtomicLoad32U{ .. } => {
+                // [i32] -> [i64]
+                v.pop();
+                v.push(2);
+            }
+            Operator::I32AtomicStore{ .. } | Operator::I64AtomicStore{ .. } | Operator::I32AtomicStore8{ .. } |
+            Operator::I32AtomicStore16{ .. } | Operator::I64AtomicStore8{ .. } | Operator::I64AtomicStore16{ .. } |
+            Operator::I64AtomicStore32{ .. } => {
+                // [i32 t] -> []
+                v.pop();
+                v.pop();
+            }
+            Operator::I32AtomicRmwAdd{ .. } | Operator::I32AtomicRmw8AddU{ .. } | Operator::I32AtomicRmw16AddU{ .. } |
+            Operator::I32AtomicRmwSub{ .. } | Operator::I32AtomicRmw8SubU{ .. } | Operator::I32AtomicRmw16SubU{ .. } |
+            Operator::I32AtomicRmwAnd{ .. } | Operator::I32AtomicRmw8AndU{ .. } | Operator::I32AtomicRmw16AndU{ .. } |
+            Operator::I32AtomicRmwOr{ .. } | Operator::I32AtomicRmw8OrU{ .. } | Operator::I32AtomicRmw16OrU{ .. } |
+            Operator::I32AtomicRmwXor{ .. } | Operator::I32AtomicRmw8XorU{ .. } | Operator::I32AtomicRmw16XorU{ .. } |
+            Operator::I32AtomicRmwXchg{ .. } | Operator::I32AtomicRmw8XchgU{ .. } | Operator::I32AtomicRmw16XchgU{ .. } => {
+                // [i32 i32] -> [i32]
+                v.pop();
+            }
+            Operator::I64AtomicRmwAdd{ .. } | Operator::I64AtomicRmw8AddU{ .. } | Operator::I64AtomicRmw16AddU{ .. } |
+            Operator::I64AtomicRmw32AddU{ .. } | Operator::I64AtomicRmwSub{ .. } | Operator::I64AtomicRmw8SubU{ .. } |
+            Operator::I64AtomicRmw16SubU{ .. } | Operator::I64AtomicRmw32SubU{ .. } | Operator::I64AtomicRmwAnd{ .. } |
+            Operator::I64AtomicRmw8AndU{ .. } | Operator::I64AtomicRmw16AndU{ .. } | Operator::I64AtomicRmw32AndU{ .. } |
+            Operator::I64AtomicRmwOr{ .. } | Operator::I64AtomicRmw8OrU{ .. } | Operator::I64AtomicRmw16OrU{ .. } |
+            Operator::I64AtomicRmw32OrU{ .. } | Operator::I64AtomicRmwXor{ .. } | Operator::I64AtomicRmw8XorU{ .. } |
+            Operator::I64AtomicRmw16XorU{ .. } | Operator::I64AtomicRmw32XorU{ .. } | Operator::I64AtomicRmwXchg{ .. } |
+            Operator::I64AtomicRmw8XchgU{ .. } | Operator::I64AtomicRmw16XchgU{ .. } | Operator::I64AtomicRmw32XchgU{ .. } => {
+                // [i32 i64] -> [i64]
+                v.pop();
+                v.pop();
+                v.push(2);
+            }
+            Operator::I32AtomicRmwCmpxchg{ .. } | Operator::I32AtomicRmw8CmpxchgU{ .. } | Operator::I32AtomicRmw16CmpxchgU{ .. } => {
+                // [i32 i32 i32] -> [i32]
+                v.pop();
+                v.pop();
+            }
+            Operator::I64AtomicRmwCmpxchg{ .. } | Operator::I64AtomicRmw8CmpxchgU{ .. } | Operator::I64AtomicRmw16CmpxchgU{ .. } |
+            Operator::I64AtomicRmw32CmpxchgU{ .. } => {
+                // [i32 i64 i64] -> [i64]
+                v.pop();
+                v.pop();
+                v.pop();
+                v.push(2);
+            }
+
             ref _other => {
                 // println!("[WARN]: {:?}", op);
                 return Err("Unsupprted operator".to_string());
@@ -431,6 +535,7 @@ pub fn valtype_to_size(valtype: &ValType) -> u8 {
         ValType::I32 | ValType::F32 => return 1,
         ValType::I64 | ValType::F64 => return 2,
         ValType::V128 => return 4,
-        ValType::Ref( .. ) => return 255,
+        // 参照は1セルに格納される
+        ValType::Ref( .. ) => return 1,
     }
 }
@@ -1,9 +1,10 @@
-use wasmparser::{FunctionBody, Operator};
+use wasmparser::{BlockType, FunctionBody, Operator};
 use anyhow::Result;
+use std::collections::HashMap;
 use crate::core::val::{WasmType, valtype_to_wasmtype};
 
 use crate::core::module::Module;
-use crate::core::opcode::OpInfo;
+use crate::core::opcode::{OpInfo, accessed_memories};
 
 pub enum Function<'a> {
     ImportFunction(ImportFunction<'a>),
@@ -33,6 +34,7 @@ impl<'a> ImportFunction<'a> {
 pub struct BytecodeFunction<'a> {
     pub module: &'a Module<'a>,
     pub body: &'a FunctionBody<'a>,
+    pub fidx: u32,
     pub locals: Vec<WasmType>,
 }
 
@@ -41,14 +43,32 @@ pub struct CodePos<'a> {
     pub op: Operator<'a>,
     pub offset: u32,
     pub stack: Stack<'a>,
+    /// 開いている制御フレーム (関数フレームから順). ランタイムのラベルスタックに対応する
+    pub labels: Vec<LabelInfo>,
+    /// 呼び出し中 (引数を取り除いた後、呼び出し先の実行中) の要素か. 呼び出し命令は
+    /// 呼び出し中 (`offset + 1`) → 呼び出し後 (`offset`) の順に2つ記録される
+    pub call_site: bool,
 }
 
 impl<'a> CodePos<'a> {
-    pub fn new(op: Operator<'a>, offset: u32, stack: Stack<'a>) -> Self {
-        Self {op, offset, stack}
+    pub fn new(op: Operator<'a>, offset: u32, stack: Stack<'a>, labels: Vec<LabelInfo>, call_site: bool) -> Self {
+        Self {op, offset, stack, labels, call_site}
     }
 }
 
+/// 制御フレームから決まるラベルの情報
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LabelInfo {
+    /// ブロック命令のオフセット (関数フレームは0)
+    pub begin: u32,
+    /// 分岐先のオフセット. loopは先頭、それ以外は対応する `end` (`delegate`)
+    pub target: u32,
+    /// ブロックに入ったときのオペランドスタックの要素数 (引数を除く)
+    pub height: usize,
+    /// 分岐で運ばれる値の型. loopは引数、それ以外は結果
+    pub types: Vec<WasmType>,
+}
+
 impl<'a> BytecodeFunction<'a> {
     pub fn new(module: &'a Module<'a>, body: &'a FunctionBody<'a>, fidx: u32) -> Self {
         let mut locals = module.get_type_by_func(fidx)
@@ -64,52 +84,302 @@ impl<'a> BytecodeFunction<'a> {
             locals.extend(std::iter::repeat(valtype_to_wasmtype(&typ)).take(count as usize));
         }
         
-        // debug
-        println!("(fidx, local size): {:?}", (fidx, locals.len()));
+        log::debug!("(fidx, local size): {:?}", (fidx, locals.len()));
 
         Self {
             module,
             body,
+            fidx,
             locals,
         }
     }
     
+    /// 命令列の先頭のファイル先頭からの位置
+    pub fn code_base(&self) -> Result<u32> {
+        Ok(self.body.get_operators_reader()?.original_position() as u32)
+    }
+
+    /// 命令を、命令列の先頭からのオフセット (スタックテーブルのキー) とともにコード順に返す
+    pub fn operators(&self) -> Result<Vec<(u32, Operator<'a>)>> {
+        let mut reader = self.body.get_operators_reader()?;
+        let base = reader.original_position() as u32;
+        let mut ops = vec![];
+        while !reader.eof() {
+            let offset = reader.original_position() as u32 - base;
+            ops.push((offset, reader.read()?));
+        }
+        Ok(ops)
+    }
+
+    /// 呼び出し命令のオフセット
+    pub fn call_sites(&self) -> Result<Vec<u32>> {
+        Ok(self.operators()?.into_iter().filter(|(_, op)| is_call_site(op)).map(|(offset, _)| offset).collect())
+    }
+
     pub fn get_type_by_local(&self, local_idx: u32) -> &WasmType {
         return &self.locals[local_idx as usize];
     }
     
+    /// Returns the `(params, results)` of a block signature.
+    pub fn block_signature(&self, blockty: &BlockType) -> (Vec<WasmType>, Vec<WasmType>) {
+        match blockty {
+            BlockType::Empty => (vec![], vec![]),
+            BlockType::Type(t) => (vec![], vec![valtype_to_wasmtype(t)]),
+            BlockType::FuncType(type_idx) => {
+                let f = self.module.get_type_by_type(*type_idx);
+                let params = f.params().iter().map(valtype_to_wasmtype).collect();
+                let results = f.results().iter().map(valtype_to_wasmtype).collect();
+                (params, results)
+            }
+        }
+    }
+
+    /// Whether the function accesses a shared linear memory, in which case
+    /// other threads may modify memory while this function is checkpointed.
+    pub fn touches_shared_memory(&self) -> Result<bool> {
+        let mut reader = self.body.get_operators_reader()?;
+        while !reader.eof() {
+            let op = reader.read()?;
+            let shared = accessed_memories(&op)
+                .into_iter()
+                .any(|mem| self.module.get_type_by_memory(mem).shared);
+            if shared {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
     pub fn create_stack_table(&self, _before_execution: bool) -> Result<Vec<CodePos>> {
         // 命令を取得
         let mut reader = self.body.get_operators_reader()?;
         let base_offset = reader.original_position() as u32;
 
+        let results = self.module.get_type_by_func(self.fidx)
+            .results()
+            .iter()
+            .map(valtype_to_wasmtype)
+            .collect();
+
+        let (block_ends, func_end) = self.block_ends()?;
+
         let mut stack = Stack::new();
+        let mut func_frame = ControlFrame::new(FrameKind::Func, 0, vec![], results);
+        func_frame.target = func_end;
+        let mut controls = vec![func_frame];
         let mut stack_table = vec![];
         while !reader.eof() {
             let offset_before = reader.original_position() as u32 - base_offset;
             let op = reader.read()?;
-            let opinfo = self.opinfo(&op);
+            let mut opinfo = self.opinfo(&op);
+            // 型なしselectの結果型はオペランドの型と同じ
+            if let Operator::Select = op {
+                if let Some((_, t)) = stack.inner.iter().rev().nth(1) {
+                    opinfo.output = vec![*t];
+                }
+            }
             // let offset_after = reader.original_position() as u32 - base_offset;
 
             // 入力適用
-            stack_apply_input(&mut stack, &opinfo);
+            let frame = controls.last().ok_or_else(|| anyhow::anyhow!("Operator after the end of function at offset {}", offset_before))?;
+            stack_apply_input(&mut stack, &opinfo, frame.height);
 
             // Call命令のときだけ、関数呼び出し直後の状態も特別に記録
-            if matches!(op, Operator::Call { .. } | Operator::CallIndirect { .. }) {
+            // (return_callはこの後フレームを捨てるので、制御命令の適用より先に記録する)
+            if is_call_site(&op) {
                 let call_site_offset = offset_before + 1;
                 // let call_site_offset = offset_after - 1;
-                stack_table.push(CodePos::new(op.clone(), call_site_offset, stack.clone()));
+                stack_table.push(CodePos::new(op.clone(), call_site_offset, stack.clone(), labels(&controls), true));
+            }
+
+            // 制御命令によるスタック高さの変化を適用
+            self.apply_control(&mut stack, &mut controls, &op)?;
+            if is_block_start(&op) {
+                let frame = controls.last_mut().expect("block frame");
+                frame.begin = offset_before;
+                frame.target = match frame.kind {
+                    FrameKind::Loop => offset_before,
+                    _ => *block_ends.get(&offset_before).ok_or_else(|| anyhow::anyhow!("Block at offset {} has no `end`", offset_before))?,
+                };
             }
 
             // 出力適用
             stack_apply_output(&mut stack, &op, &opinfo);
 
             // 通常の命令記録（実行後の状態）
-            stack_table.push(CodePos::new(op.clone(), offset_before, stack.clone()));
+            stack_table.push(CodePos::new(op.clone(), offset_before, stack.clone(), labels(&controls), false));
         }
 
         Ok(stack_table)
     }
+
+    /// 各ブロックの開始オフセットから対応する `end` (`delegate`) のオフセットへの対応と、
+    /// 関数本体の `end` のオフセットを返す
+    fn block_ends(&self) -> Result<(HashMap<u32, u32>, u32)> {
+        let mut reader = self.body.get_operators_reader()?;
+        let base_offset = reader.original_position() as u32;
+
+        let mut ends = HashMap::new();
+        let mut begins = vec![];
+        let mut func_end = 0;
+        while !reader.eof() {
+            let offset = reader.original_position() as u32 - base_offset;
+            let op = reader.read()?;
+            if is_block_start(&op) {
+                begins.push(offset);
+            } else if let Operator::End | Operator::Delegate { .. } = op {
+                match begins.pop() {
+                    Some(begin) => { ends.insert(begin, offset); }
+                    None => func_end = offset,
+                }
+            }
+        }
+        Ok((ends, func_end))
+    }
+}
+
+impl<'a> BytecodeFunction<'a> {
+    /// Applies the effect of a control instruction on the control stack and
+    /// the operand stack, in the same way as the validator does.
+    ///
+    /// Called after the operator's inputs have been popped and before its
+    /// outputs are pushed.
+    fn apply_control(&self, stack: &mut Stack<'a>, controls: &mut Vec<ControlFrame<'a>>, op: &Operator<'a>) -> Result<()> {
+        match op {
+            Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } |
+            Operator::Try { .. } | Operator::TryTable { .. } => {
+                let (kind, blockty) = match op {
+                    Operator::Block { blockty } => (FrameKind::Block, blockty),
+                    Operator::Loop { blockty } => (FrameKind::Loop, blockty),
+                    Operator::If { blockty } => (FrameKind::If, blockty),
+                    Operator::Try { blockty } => (FrameKind::Try, blockty),
+                    Operator::TryTable { try_table } => (FrameKind::TryTable, &try_table.ty),
+                    _ => unreachable!(),
+                };
+                let (params, results) = self.block_signature(blockty);
+                let floor = controls.last().map_or(0, |f| f.height);
+                let height = stack.len().saturating_sub(params.len()).max(floor);
+                // 到達不能コードでは引数が積まれていないことがあるので補う
+                let missing = params.len() - (stack.len() - height);
+                let entries: Vec<_> = params[..missing]
+                    .iter()
+                    .map(|t| (op.clone(), *t))
+                    .chain(stack.inner[height..].iter().cloned())
+                    .collect();
+                stack.inner.truncate(height);
+                stack.inner.extend(entries.iter().cloned());
+                controls.push(ControlFrame::new(kind, height, entries, results));
+            }
+            Operator::Else => {
+                let frame = controls.last_mut().ok_or_else(|| anyhow::anyhow!("`else` without a matching `if`"))?;
+                // then節の結果を捨て、ifの引数を復元する
+                stack.inner.truncate(frame.height);
+                stack.inner.extend(frame.params.iter().cloned());
+                frame.kind = FrameKind::Else;
+                frame.unreachable = false;
+            }
+            Operator::Catch { .. } | Operator::CatchAll => {
+                let frame = controls.last_mut().ok_or_else(|| anyhow::anyhow!("`catch` without a matching `try`"))?;
+                // try節の結果を捨てる (catchのタグ引数は出力として積まれる)
+                stack.inner.truncate(frame.height);
+                frame.kind = match op {
+                    Operator::Catch { .. } => FrameKind::Catch,
+                    _ => FrameKind::CatchAll,
+                };
+                frame.unreachable = false;
+            }
+            Operator::End | Operator::Delegate { .. } => {
+                let frame = controls.pop().ok_or_else(|| anyhow::anyhow!("`end` without a matching block"))?;
+                let n = frame.results.len();
+                let results = if !frame.unreachable && stack.len() >= frame.height + n {
+                    stack.inner[stack.len() - n..].to_vec()
+                } else {
+                    frame.results.iter().map(|t| (op.clone(), *t)).collect()
+                };
+                stack.inner.truncate(frame.height);
+                stack.inner.extend(results);
+            }
+            Operator::Br { .. } | Operator::BrTable { .. } | Operator::Return | Operator::Unreachable |
+            Operator::ReturnCall { .. } | Operator::ReturnCallIndirect { .. } |
+            Operator::Throw { .. } | Operator::ThrowRef | Operator::Rethrow { .. } => {
+                // 無条件分岐の後はスタックが多相になる
+                let frame = controls.last_mut().ok_or_else(|| anyhow::anyhow!("Branch outside of function body"))?;
+                stack.inner.truncate(frame.height);
+                frame.unreachable = true;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn labels(controls: &[ControlFrame]) -> Vec<LabelInfo> {
+    controls
+        .iter()
+        .map(|f| LabelInfo {
+            begin: f.begin,
+            target: f.target,
+            height: f.height,
+            types: match f.kind {
+                FrameKind::Loop => f.params.iter().map(|(_, t)| *t).collect(),
+                _ => f.results.clone(),
+            },
+        })
+        .collect()
+}
+
+fn is_block_start(op: &Operator) -> bool {
+    matches!(
+        op,
+        Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } |
+        Operator::Try { .. } | Operator::TryTable { .. }
+    )
+}
+
+/// Whether the stack table records an extra entry at `offset + 1` holding the
+/// caller's stack while the callee runs (i.e. after the arguments are popped).
+pub fn is_call_site(op: &Operator) -> bool {
+    matches!(
+        op,
+        Operator::Call { .. } | Operator::CallIndirect { .. } |
+        Operator::ReturnCall { .. } | Operator::ReturnCallIndirect { .. }
+    )
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    Func,
+    Block,
+    Loop,
+    If,
+    Else,
+    Try,
+    Catch,
+    CatchAll,
+    TryTable,
+}
+
+/// An entry of the control stack, modelled after the validator's control frame.
+#[derive(Clone)]
+pub struct ControlFrame<'a> {
+    pub kind: FrameKind,
+    /// Operand stack height when the frame was entered, excluding its params.
+    pub height: usize,
+    /// Params of the block, restored at `else`.
+    pub params: Vec<(Operator<'a>, WasmType)>,
+    pub results: Vec<WasmType>,
+    /// Set after an unconditional branch; the rest of the frame is dead code
+    /// and the operand stack is polymorphic.
+    pub unreachable: bool,
+    /// Offset of the block instruction, and of the branch target.
+    pub begin: u32,
+    pub target: u32,
+}
+
+impl<'a> ControlFrame<'a> {
+    pub fn new(kind: FrameKind, height: usize, params: Vec<(Operator<'a>, WasmType)>, results: Vec<WasmType>) -> Self {
+        Self { kind, height, params, results, unreachable: false, begin: 0, target: 0 }
+    }
 }
 
 #[derive(Clone)]
@@ -147,13 +417,13 @@ fn stack_apply<'a>(stack: &mut Stack<'a>, opcode: &Operator<'a>, opinfo: &OpInfo
     }
 }
 
-fn stack_apply_input<'a>(stack: &mut Stack<'a>, opinfo: &OpInfo) {
+fn stack_apply_input<'a>(stack: &mut Stack<'a>, opinfo: &OpInfo, floor: usize) {
     let input = &opinfo.input;
 
-    // pop
+    // pop (現在のブロックの高さより下はpopしない)
     let pop_len = input.len();
     let stack_len = stack.len();
-    stack.inner.truncate(stack_len.saturating_sub(pop_len));
+    stack.inner.truncate(stack_len.saturating_sub(pop_len).max(floor));
 }
 
 fn stack_apply_output<'a>(stack: &mut Stack<'a>, opcode: &Operator<'a>, opinfo: &OpInfo) {
@@ -165,3 +435,113 @@ fn stack_apply_output<'a>(stack: &mut Stack<'a>, opcode: &Operator<'a>, opinfo:
         stack.push((opcode.clone(), typ.clone()));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::module::new_module;
+
+    /// Returns the types on the operand stack after each operator of the last function.
+    fn stack_types(wat: &str) -> Vec<Vec<WasmType>> {
+        let buf = wat::parse_str(wat).unwrap();
+        let m = new_module(&buf).unwrap();
+        let funcs = m.new_function_v2().unwrap();
+        let f = match funcs.last().unwrap() {
+            Function::BytecodeFunction(f) => f,
+            Function::ImportFunction(_) => panic!("expected a bytecode function"),
+        };
+        f.create_stack_table(false)
+            .unwrap()
+            .iter()
+            .map(|c| c.stack.inner.iter().map(|(_, t)| *t).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_if_else_restores_params() {
+        let stacks = stack_types(r#"
+            (module
+              (type $t (func (param i32) (result i32)))
+              (func (param i32) (result i32)
+                i32.const 1
+                local.get 0
+                if (type $t)
+                  i32.const 2
+                  i32.add
+                else
+                  i64.const 3
+                  drop
+                end))
+        "#);
+        use WasmType::*;
+        assert_eq!(stacks, vec![
+            vec![I32],            // i32.const 1
+            vec![I32, I32],       // local.get 0
+            vec![I32],            // if
+            vec![I32, I32],       // i32.const 2
+            vec![I32],            // i32.add
+            vec![I32],            // else
+            vec![I32, I64],       // i64.const 3
+            vec![I32],            // drop
+            vec![I32],            // end (if)
+            vec![I32],            // end (func)
+        ]);
+    }
+
+    #[test]
+    fn test_unconditional_branch_makes_stack_polymorphic() {
+        let stacks = stack_types(r#"
+            (module
+              (func (result i32)
+                i64.const 0
+                block (result i32)
+                  i32.const 1
+                  i64.const 2
+                  br 0
+                  i32.const 5
+                end
+                return
+                unreachable
+                i32.const 7
+                i32.eqz))
+        "#);
+        use WasmType::*;
+        assert_eq!(stacks, vec![
+            vec![I64],            // i64.const 0
+            vec![I64],            // block
+            vec![I64, I32],       // i32.const 1
+            vec![I64, I32, I64],  // i64.const 2
+            vec![I64],            // br 0
+            vec![I64, I32],       // i32.const 5 (dead code)
+            vec![I64, I32],       // end (block)
+            vec![],               // return
+            vec![],               // unreachable
+            vec![I32],            // i32.const 7 (dead code)
+            vec![I32],            // i32.eqz
+            vec![I32],            // end (func)
+        ]);
+    }
+
+    #[test]
+    fn test_loop_params() {
+        let stacks = stack_types(r#"
+            (module
+              (type $t (func (param i64) (result i64)))
+              (func (result i64)
+                i64.const 0
+                loop (type $t)
+                  i64.const 1
+                  i64.add
+                end))
+        "#);
+        use WasmType::*;
+        assert_eq!(stacks, vec![
+            vec![I64],            // i64.const 0
+            vec![I64],            // loop
+            vec![I64, I64],       // i64.const 1
+            vec![I64],            // i64.add
+            vec![I64],            // end (loop)
+            vec![I64],            // end (func)
+        ]);
+    }
+}
@@ -0,0 +1,294 @@
+//! 生存解析
+//!
+//! 各コード位置で、後で読まれる可能性のあるローカルとオペランドスタックの値を求める.
+//! 死んでいる値はチェックポイント時に保存する必要がなく、リストア時は0で埋めればよい.
+//!
+//! - ローカル: 命令単位の制御フローグラフ上の後ろ向きデータフロー解析.
+//!   例外処理 (try/catch/try_table) を含む関数は解析せず、全て生存とみなす.
+//! - オペランドスタック: 値を最初に取り除く命令が `drop` なら死んでいる.
+//!   途中で条件付き分岐を通る値は分岐先で使われうるので生存とみなす.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use wasmparser::Operator;
+
+use crate::core::function_v2::{BytecodeFunction, CodePos};
+
+/// あるコード位置で生存している値のマスク (`true` が生存)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LiveMask {
+    pub locals: Vec<bool>,
+    pub stack: Vec<bool>,
+}
+
+/// `create_stack_table` の各要素に対応する生存マスクを返す
+pub fn analyze(f: &BytecodeFunction, table: &[CodePos]) -> Result<Vec<LiveMask>> {
+    let ops = read_operators(f)?;
+    let locals = live_locals(f, &ops)?;
+
+    let (entry_ops, call_sites) = entry_layout(table);
+    if entry_ops.last().map_or(0, |i| i + 1) != ops.len() {
+        anyhow::bail!("Stack table of function {} does not match its {} instructions", f.fidx, ops.len());
+    }
+
+    let heights = min_heights(f, table, &call_sites);
+
+    let masks = table
+        .iter()
+        .zip(&entry_ops)
+        .map(|(codepos, i)| LiveMask {
+            locals: locals[*i].clone(),
+            // 呼び出し中の値は呼び出し命令では取り除かれないので、どちらも次の命令から調べる
+            stack: (0..codepos.stack.len()).map(|p| stack_slot_live(&ops, &heights, *i + 1, p)).collect(),
+        })
+        .collect();
+    Ok(masks)
+}
+
+/// スタックテーブルの各要素に対応する命令の番号と、呼び出し中の要素かどうか.
+/// 呼び出し命令は「呼び出し中」→「呼び出し後」の順に2要素ある
+pub(crate) fn entry_layout(table: &[CodePos]) -> (Vec<usize>, Vec<bool>) {
+    let mut entry_ops = Vec::with_capacity(table.len());
+    let mut i = 0;
+    for codepos in table {
+        entry_ops.push(i);
+        if !codepos.call_site {
+            i += 1;
+        }
+    }
+    (entry_ops, table.iter().map(|c| c.call_site).collect())
+}
+
+/// 各命令の実行中にスタックが最も低くなる高さ. これより上の値はその命令で取り除かれる
+pub(crate) fn min_heights(f: &BytecodeFunction, table: &[CodePos], call_sites: &[bool]) -> Vec<usize> {
+    let mut heights = vec![];
+    let mut pre_len: usize = 0;
+    for (codepos, call_site) in table.iter().zip(call_sites) {
+        if *call_site {
+            continue;
+        }
+        let inputs = f.opinfo(&codepos.op).input.len();
+        heights.push(pre_len.saturating_sub(inputs).min(codepos.stack.len()));
+        pre_len = codepos.stack.len();
+    }
+    heights
+}
+
+pub(crate) fn read_operators<'a>(f: &BytecodeFunction<'a>) -> Result<Vec<Operator<'a>>> {
+    Ok(f.operators()?.into_iter().map(|(_, op)| op).collect())
+}
+
+/// スタックの `p` 番目の値が命令 `from` 以降で読まれるか
+fn stack_slot_live(ops: &[Operator], heights: &[usize], from: usize, p: usize) -> bool {
+    for j in from..ops.len() {
+        if heights[j] <= p {
+            return !matches!(ops[j], Operator::Drop);
+        }
+        if is_conditional_branch(&ops[j]) {
+            return true;
+        }
+    }
+    true
+}
+
+/// 後続の命令が1通りに決まらない命令. ifの引数はthen節とelse節のどちらで読まれるか分からない
+fn is_conditional_branch(op: &Operator) -> bool {
+    matches!(
+        op,
+        Operator::If { .. } | Operator::BrIf { .. } | Operator::BrTable { .. } |
+        Operator::BrOnNull { .. } | Operator::BrOnNonNull { .. } |
+        Operator::BrOnCast { .. } | Operator::BrOnCastFail { .. }
+    )
+}
+
+/// 各命令の実行後に生存しているローカル
+fn live_locals(f: &BytecodeFunction, ops: &[Operator]) -> Result<Vec<Vec<bool>>> {
+    let n = f.locals.len();
+    let has_exceptions = ops.iter().any(|op| matches!(
+        op,
+        Operator::Try { .. } | Operator::TryTable { .. } | Operator::Catch { .. } |
+        Operator::CatchAll | Operator::Delegate { .. }
+    ));
+    if has_exceptions {
+        return Ok(vec![vec![true; n]; ops.len()]);
+    }
+
+    let succs = successors(ops)?;
+    let mut live_in = vec![vec![false; n]; ops.len()];
+    let mut live_out = vec![vec![false; n]; ops.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..ops.len()).rev() {
+            let mut out = vec![false; n];
+            for s in &succs[i] {
+                for (o, l) in out.iter_mut().zip(&live_in[*s]) {
+                    *o |= *l;
+                }
+            }
+
+            let mut input = out.clone();
+            match ops[i] {
+                Operator::LocalSet { local_index } | Operator::LocalTee { local_index } => input[local_index as usize] = false,
+                Operator::LocalGet { local_index } => input[local_index as usize] = true,
+                _ => {}
+            }
+
+            if input != live_in[i] || out != live_out[i] {
+                changed = true;
+                live_in[i] = input;
+                live_out[i] = out;
+            }
+        }
+    }
+    Ok(live_out)
+}
+
+/// 各命令の後に実行されうる命令. 関数から抜ける命令は空
+fn successors(ops: &[Operator]) -> Result<Vec<Vec<usize>>> {
+    // ブロックの開始命令 → (else, end)
+    let mut elses = vec![None; ops.len()];
+    let mut ends = vec![0; ops.len()];
+    let mut begins = vec![];
+    for (i, op) in ops.iter().enumerate() {
+        match op {
+            Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } => begins.push(i),
+            Operator::Else => {
+                let b = *begins.last().ok_or_else(|| anyhow::anyhow!("`else` without a matching `if`"))?;
+                elses[b] = Some(i);
+            }
+            Operator::End => {
+                if let Some(b) = begins.pop() {
+                    ends[b] = i;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // 開いているブロック (関数フレームは None)
+    let mut controls: Vec<Option<usize>> = vec![None];
+    let mut succs = Vec::with_capacity(ops.len());
+    for (i, op) in ops.iter().enumerate() {
+        let target = |depth: u32| -> Result<Option<usize>> {
+            let frame = controls
+                .iter()
+                .rev()
+                .nth(depth as usize)
+                .ok_or_else(|| anyhow::anyhow!("Branch depth {} is out of range", depth))?;
+            Ok(frame.map(|b| match ops[b] {
+                Operator::Loop { .. } => b + 1,
+                _ => ends[b],
+            }))
+        };
+
+        let s: Vec<usize> = match op {
+            Operator::Br { relative_depth } => target(*relative_depth)?.into_iter().collect(),
+            Operator::BrIf { relative_depth } | Operator::BrOnNull { relative_depth } |
+            Operator::BrOnNonNull { relative_depth } | Operator::BrOnCast { relative_depth, .. } |
+            Operator::BrOnCastFail { relative_depth, .. } => {
+                std::iter::once(i + 1).chain(target(*relative_depth)?).collect()
+            }
+            Operator::BrTable { targets } => {
+                let mut s = vec![];
+                for depth in targets.targets().chain(std::iter::once(Ok(targets.default()))) {
+                    s.extend(target(depth?)?);
+                }
+                s
+            }
+            Operator::Return | Operator::Unreachable | Operator::ReturnCall { .. } |
+            Operator::ReturnCallIndirect { .. } | Operator::Throw { .. } | Operator::ThrowRef |
+            Operator::Rethrow { .. } => vec![],
+            Operator::If { .. } => {
+                let otherwise = elses[i].map_or(ends[i], |e| e + 1);
+                vec![i + 1, otherwise]
+            }
+            Operator::Else => {
+                let b = controls.last().copied().flatten().expect("if frame");
+                vec![ends[b]]
+            }
+            Operator::End if controls.len() == 1 => vec![],
+            _ => vec![i + 1],
+        };
+        succs.push(s);
+
+        match op {
+            Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } => controls.push(Some(i)),
+            Operator::End => { controls.pop(); }
+            _ => {}
+        }
+    }
+    Ok(succs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::function_v2::Function;
+    use crate::core::module::new_module;
+
+    #[test]
+    fn test_live_locals_and_dropped_values() -> Result<()> {
+        let buf = wat::parse_str(r#"
+            (module
+              (func (param i32 i32) (result i32)
+                (local i32)
+                i32.const 1
+                local.set 2
+                i32.const 5
+                local.get 0
+                i32.const 7
+                drop
+                br_if 0
+                drop
+                local.get 2))
+        "#)?;
+        let m = new_module(&buf)?;
+        let funcs = m.new_function_v2()?;
+        let f = match &funcs[0] {
+            Function::BytecodeFunction(f) => f,
+            Function::ImportFunction(_) => unreachable!(),
+        };
+        let table = f.create_stack_table(false)?;
+        let masks = analyze(f, &table)?;
+        assert_eq!(masks.len(), table.len());
+
+        // i32.const 1 の後: 引数1は読まれない、ローカル2は上書きされる
+        assert_eq!(masks[0].locals, vec![true, false, false]);
+        // local.set 2 の後: ローカル2は最後に読まれる
+        assert_eq!(masks[1].locals, vec![true, false, true]);
+        // i32.const 7 の後: 7 はdropされるので死んでいる. 5 はbr_ifで運ばれうる
+        assert_eq!(masks[4].stack, vec![true, true, false]);
+        // br_if の後: 5 はdropされる
+        assert_eq!(masks[6].stack, vec![false]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_param_read_in_else() -> Result<()> {
+        let buf = wat::parse_str(r#"
+            (module
+              (func (param i32 i32) (result i32)
+                local.get 1
+                local.get 0
+                if (param i32)
+                  drop
+                else
+                  local.set 1
+                end
+                local.get 1))
+        "#)?;
+        let m = new_module(&buf)?;
+        let funcs = m.new_function_v2()?;
+        let f = match &funcs[0] {
+            Function::BytecodeFunction(f) => f,
+            Function::ImportFunction(_) => unreachable!(),
+        };
+        let table = f.create_stack_table(false)?;
+        let masks = analyze(f, &table)?;
+
+        // local.get 0 の後: ifの引数はthen節ではdropされるが、else節で読まれる
+        assert_eq!(masks[1].stack, vec![true, true]);
+        Ok(())
+    }
+}
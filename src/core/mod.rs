@@ -3,4 +3,11 @@ pub mod function;
 pub mod function_v2;
 pub mod val;
 pub mod opcode;
-pub mod stack_table;
\ No newline at end of file
+pub mod liveness;
+pub mod offset_map;
+pub mod remat;
+pub mod safepoint;
+pub mod stack_table;
+pub mod stack_table_bin;
+pub mod type_table;
+pub mod verify;
\ No newline at end of file
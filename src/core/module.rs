@@ -1,6 +1,6 @@
 use anyhow::Result;
 use wasmparser::{Parser, Payload, TypeRef};
-use wasmparser::{FunctionBody, FuncType, GlobalType, BlockType, ValType};
+use wasmparser::{FunctionBody, FuncType, GlobalType, BlockType, ValType, TableType, MemoryType, TagType};
 
 use crate::core::function::{Function, BytecodeFunction, ImportFunction, CodePos, valtype_to_size};
 use crate::core::function_v2;
@@ -10,18 +10,52 @@ pub struct Fn<'a> {
     pub body: Option<FunctionBody<'a>>,
 }
 
+/// Number of imported entities in each index space.
+///
+/// Imports always occupy the lowest indices of their index space, so an index
+/// below the corresponding count refers to an import.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImportCounts {
+    pub funcs: u32,
+    pub globals: u32,
+    pub tables: u32,
+    pub memories: u32,
+    pub tags: u32,
+}
+
+/// A parsed module.
+///
+/// `funcs`, `globals`, `tables`, `memories` and `tags` are indexed by the
+/// combined index space (imports first, then definitions), i.e. the same
+/// indices that appear in instruction immediates.
 pub struct Module<'a> {
     pub types: Vec<FuncType>,
     pub funcs: Vec<Fn<'a>>,
     pub globals: Vec<GlobalType>,
+    pub tables: Vec<TableType>,
+    pub memories: Vec<MemoryType>,
+    pub tags: Vec<TagType>,
+    pub imports: ImportCounts,
 }
 
 impl<'a> Module<'a> {
-    pub fn new(types: Vec<FuncType>, funcs: Vec<Fn<'a>>, globals: Vec<GlobalType>) -> Self {
+    pub fn new(
+        types: Vec<FuncType>,
+        funcs: Vec<Fn<'a>>,
+        globals: Vec<GlobalType>,
+        tables: Vec<TableType>,
+        memories: Vec<MemoryType>,
+        tags: Vec<TagType>,
+        imports: ImportCounts,
+    ) -> Self {
         Self {
             types,
             funcs,
             globals,
+            tables,
+            memories,
+            tags,
+            imports,
         }
     }
 
@@ -37,6 +71,27 @@ impl<'a> Module<'a> {
         return &self.globals[global_idx as usize].content_type;
     }
 
+    pub fn get_type_by_table(&self, table_idx: u32) -> &TableType {
+        return &self.tables[table_idx as usize];
+    }
+
+    pub fn get_type_by_memory(&self, memory_idx: u32) -> &MemoryType {
+        return &self.memories[memory_idx as usize];
+    }
+
+    /// Returns the function type describing the payload of a tag.
+    pub fn get_type_by_tag(&self, tag_idx: u32) -> &FuncType {
+        return self.get_type_by_type(self.tags[tag_idx as usize].func_type_idx);
+    }
+
+    pub fn is_imported_func(&self, func_idx: u32) -> bool {
+        func_idx < self.imports.funcs
+    }
+
+    pub fn is_imported_global(&self, global_idx: u32) -> bool {
+        global_idx < self.imports.globals
+    }
+
     pub fn parse(&self) -> Result<Vec<Function>> {
         let mut ret : Vec<Function> = vec![];
 
@@ -119,6 +174,13 @@ impl<'a> Module<'a> {
 
 pub fn new_module(buf: &Vec<u8>) -> Result<Module> {
     let mut globals: Vec<GlobalType> = Vec::new();
+    let mut tables: Vec<TableType> = Vec::new();
+    let mut memories: Vec<MemoryType> = Vec::new();
+    let mut tags: Vec<TagType> = Vec::new();
+    let mut import_globals: Vec<GlobalType> = Vec::new();
+    let mut import_tables: Vec<TableType> = Vec::new();
+    let mut import_memories: Vec<MemoryType> = Vec::new();
+    let mut import_tags: Vec<TagType> = Vec::new();
     let mut codes: Vec<FunctionBody> = Vec::new();
     let mut types: Vec<FuncType> = Vec::new();
     let mut bytecode_funcs: Vec<u32> = Vec::new();
@@ -154,7 +216,17 @@ pub fn new_module(buf: &Vec<u8>) -> Result<Module> {
                         TypeRef::Func(func_idx) => {
                             import_funcs.push(func_idx);
                         }
-                        _other => {
+                        TypeRef::Global(global_type) => {
+                            import_globals.push(global_type);
+                        }
+                        TypeRef::Table(table_type) => {
+                            import_tables.push(table_type);
+                        }
+                        TypeRef::Memory(memory_type) => {
+                            import_memories.push(memory_type);
+                        }
+                        TypeRef::Tag(tag_type) => {
+                            import_tags.push(tag_type);
                         }
                     }
                 }
@@ -166,6 +238,21 @@ pub fn new_module(buf: &Vec<u8>) -> Result<Module> {
                     globals.push(glob.ty);
                 }
             }
+            Payload::TableSection(table_reader) => {
+                for table in table_reader {
+                    tables.push(table?.ty);
+                }
+            }
+            Payload::MemorySection(memory_reader) => {
+                for memory in memory_reader {
+                    memories.push(memory?);
+                }
+            }
+            Payload::TagSection(tag_reader) => {
+                for tag in tag_reader {
+                    tags.push(tag?);
+                }
+            }
             Payload::CodeSectionEntry(body) => {
                 codes.push(body);
             }
@@ -186,5 +273,55 @@ pub fn new_module(buf: &Vec<u8>) -> Result<Module> {
         funcs.push(Fn{fidx: type_idx, body: Some(codes[func_idx].clone())});
     }
 
-    return Ok(Module::new(types, funcs, globals));
+    // importとモジュール内定義をマージ (importが先頭のインデックスを占める)
+    let imports = ImportCounts {
+        funcs: import_funcs.len() as u32,
+        globals: import_globals.len() as u32,
+        tables: import_tables.len() as u32,
+        memories: import_memories.len() as u32,
+        tags: import_tags.len() as u32,
+    };
+    import_globals.extend(globals);
+    import_tables.extend(tables);
+    import_memories.extend(memories);
+    import_tags.extend(tags);
+
+    return Ok(Module::new(types, funcs, import_globals, import_tables, import_memories, import_tags, imports));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_imported_globals_precede_defined_globals() -> Result<()> {
+        let buf = wat::parse_str(r#"
+            (module
+              (import "env" "__stack_pointer" (global (mut i32)))
+              (import "env" "memory" (memory 1))
+              (import "env" "f" (func (param i32)))
+              (import "env" "__table_base" (global i32))
+              (global (mut i64) (i64.const 0))
+              (table 1 funcref)
+              (func (result i64) global.get 2))
+        "#)?;
+        let m = new_module(&buf)?;
+
+        assert_eq!(m.globals.len(), 3);
+        assert_eq!(m.imports.globals, 2);
+        assert!(m.is_imported_global(1));
+        assert!(!m.is_imported_global(2));
+        assert_eq!(*m.get_type_by_global(0), ValType::I32);
+        assert_eq!(*m.get_type_by_global(1), ValType::I32);
+        assert_eq!(*m.get_type_by_global(2), ValType::I64);
+
+        assert_eq!(m.memories.len(), 1);
+        assert_eq!(m.imports.memories, 1);
+        assert_eq!(m.tables.len(), 1);
+        assert_eq!(m.imports.tables, 0);
+        assert_eq!(m.imports.funcs, 1);
+        assert!(m.is_imported_func(0));
+        assert!(!m.is_imported_func(1));
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -0,0 +1,259 @@
+//! 計装前後のコード位置の対応
+//!
+//! 計装 (`insert` など) でwasmを書き換えると、命令のバイトオフセットだけでなく
+//! 関数の並び順やローカルの番号も変わることがある. そのため元のバイナリで作った
+//! スタックテーブルやスナップショットは、この対応表で計装後のバイナリに合わせて書き換える.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use wasmparser::Operator;
+
+use crate::core::function_v2::{BytecodeFunction, Function};
+use crate::core::module;
+use crate::core::stack_table::{CompiledOp, Label, Offset, StackTable, StackTables};
+use crate::core::val::WasmType;
+
+/// 1関数の対応
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FuncMap {
+    /// 計装後の関数番号
+    pub fidx: u32,
+    /// 元の命令のオフセット → 計装後のオフセット
+    pub offsets: BTreeMap<Offset, Offset>,
+    /// 元のローカル番号 → 計装後の番号. 使われていないローカルは取り除かれて `None`
+    pub locals: Vec<Option<u32>>,
+    /// 元のローカルの型
+    pub local_types: Vec<WasmType>,
+    /// 呼び出し命令のオフセット (呼び出し中のコード位置 `offset + 1` の変換に使う)
+    pub call_sites: Vec<Offset>,
+}
+
+impl FuncMap {
+    /// 命令のオフセット、または呼び出し中のコード位置 (呼び出し命令のオフセット + 1) を変換する
+    pub fn offset(&self, offset: Offset) -> Result<Offset> {
+        if let Some(new) = self.offsets.get(&offset) {
+            return Ok(*new);
+        }
+        if offset > 0 && self.call_sites.contains(&(offset - 1)) {
+            if let Some(new) = self.offsets.get(&(offset - 1)) {
+                return Ok(new + 1);
+            }
+        }
+        Err(anyhow::anyhow!("Offset {} is not an instruction boundary of the original function", offset))
+    }
+
+    pub fn local(&self, idx: u32) -> Result<Option<u32>> {
+        self.locals
+            .get(idx as usize)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("Local {} is out of range ({} locals)", idx, self.locals.len()))
+    }
+
+    /// 計装後のローカルの数
+    pub fn new_local_count(&self) -> usize {
+        self.locals.iter().flatten().map(|i| *i as usize + 1).max().unwrap_or(0)
+    }
+
+    /// 元のローカル順の値を計装後の順に並べ替える
+    pub fn reorder<T: Clone>(&self, values: &[T], default: T) -> Vec<T> {
+        let mut out = vec![default; self.new_local_count()];
+        for (old, new) in self.locals.iter().enumerate() {
+            if let (Some(new), Some(v)) = (new, values.get(old)) {
+                out[*new as usize] = v.clone();
+            }
+        }
+        out
+    }
+
+    /// ラベルの変換. 関数フレーム (先頭) のbeginは関数の先頭を指すので0のまま
+    pub fn label(&self, depth: usize, label: &Label) -> Result<Label> {
+        let begin = if depth == 0 { 0 } else { self.offset(label.begin)? };
+        Ok(Label { begin, target: self.offset(label.target)?, ..label.clone() })
+    }
+}
+
+/// 元のバイナリの関数番号 → 対応
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OffsetMap {
+    pub funcs: BTreeMap<u32, FuncMap>,
+    /// 計装後の関数の数 (importを含む)
+    pub new_func_count: u32,
+}
+
+impl OffsetMap {
+    pub fn func(&self, fidx: u32) -> Result<&FuncMap> {
+        self.funcs
+            .get(&fidx)
+            .ok_or_else(|| anyhow::anyhow!("Function {} is not in the offset map", fidx))
+    }
+
+    /// 計装後のバイナリのスタックテーブルに書き換える. importの関数は空のテーブルになる
+    pub fn apply_to_stack_tables(&self, tables: StackTables) -> Result<StackTables> {
+        let mut new_tables: Vec<Option<StackTable>> = (0..self.new_func_count).map(|_| None).collect();
+        for (fidx, table) in tables.0.into_iter().enumerate() {
+            let map = match self.funcs.get(&(fidx as u32)) {
+                Some(map) => map,
+                None if table.inner().is_empty() => continue,
+                None => anyhow::bail!("Function {} is not in the offset map", fidx),
+            };
+            let slot = new_tables
+                .get_mut(map.fidx as usize)
+                .ok_or_else(|| anyhow::anyhow!("Function {} is out of range ({} functions)", map.fidx, self.new_func_count))?;
+            *slot = Some(remap_table(map, table).map_err(|e| anyhow::anyhow!("Function {}: {}", fidx, e))?);
+        }
+
+        let tables = new_tables
+            .into_iter()
+            .map(|t| t.unwrap_or_else(|| StackTable::new(vec![], IndexMap::new(), IndexMap::new(), false)))
+            .collect();
+        Ok(StackTables(tables))
+    }
+}
+
+fn remap_table(map: &FuncMap, table: StackTable) -> Result<StackTable> {
+    let locals = map.reorder(&map.local_types, WasmType::Any);
+
+    let mut inner = IndexMap::new();
+    for (offset, stack) in table.inner() {
+        let stack = stack
+            .iter()
+            .map(|(op, ty)| {
+                let op = match op {
+                    CompiledOp::LocalGet(idx) => match map.local(*idx)? {
+                        Some(new) => CompiledOp::LocalGet(new),
+                        None => CompiledOp::Other(*ty),
+                    },
+                    op => op.clone(),
+                };
+                Ok((op, *ty))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        inner.insert(map.offset(*offset)?, stack);
+    }
+
+    let mut labels = IndexMap::new();
+    for (offset, ls) in table.labels() {
+        let ls = ls.iter().enumerate().map(|(depth, l)| map.label(depth, l)).collect::<Result<Vec<_>>>()?;
+        labels.insert(map.offset(*offset)?, ls);
+    }
+
+    let mut live = IndexMap::new();
+    for (offset, mask) in table.live() {
+        let mut mask = mask.clone();
+        mask.locals = map.reorder(&mask.locals, false);
+        live.insert(map.offset(*offset)?, mask);
+    }
+
+    let mut recomputable = IndexMap::new();
+    for (offset, flags) in table.recomputable() {
+        recomputable.insert(map.offset(*offset)?, flags.clone());
+    }
+
+    Ok(StackTable::new(locals, inner, labels, table.shared_memory())
+        .with_live(live)
+        .with_recomputable(recomputable))
+}
+
+
+/// 命令の種類のうち対応付けに必要なもの
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Local(u32),
+    Else,
+    End,
+    Other,
+}
+
+struct Instr {
+    fidx: u32,
+    offset: Offset,
+    kind: Kind,
+}
+
+/// バイナリ中の全ての命令を、ファイル先頭からの位置で引けるようにする
+fn scan(buf: &[u8], mut on_func: impl FnMut(&BytecodeFunction) -> Result<()>) -> Result<(BTreeMap<u32, Instr>, u32)> {
+    let buf = buf.to_vec();
+    let m = module::new_module(&buf)?;
+    let funcs = m.new_function_v2()?;
+
+    let mut instrs = BTreeMap::new();
+    for f in &funcs {
+        let f = match f {
+            Function::BytecodeFunction(f) => f,
+            Function::ImportFunction(_) => continue,
+        };
+        on_func(f)?;
+
+        let base = f.code_base()?;
+        for (offset, op) in f.operators()? {
+            let kind = match op {
+                Operator::LocalGet { local_index } | Operator::LocalSet { local_index } |
+                Operator::LocalTee { local_index } => Kind::Local(local_index),
+                Operator::Else => Kind::Else,
+                Operator::End => Kind::End,
+                _ => Kind::Other,
+            };
+            instrs.insert(base + offset, Instr { fidx: f.fidx, offset, kind });
+        }
+    }
+    Ok((instrs, funcs.len() as u32))
+}
+
+impl OffsetMap {
+    /// 元のバイナリと計装後のバイナリ、命令の位置の対応 (ファイル先頭からの位置) から作る.
+    /// 計装で追加された命令は対応に含まれない
+    pub fn from_binaries(old: &[u8], new: &[u8], instruction_map: &[(u32, u32)]) -> Result<Self> {
+        let mut funcs: BTreeMap<u32, (Option<u32>, FuncMap)> = BTreeMap::new();
+        let (old_instrs, _) = scan(old, |f| {
+            let params = f.module.get_type_by_func(f.fidx).params().len();
+            let call_sites = f.call_sites()?;
+            let map = FuncMap {
+                fidx: f.fidx,
+                offsets: BTreeMap::new(),
+                locals: (0..f.locals.len()).map(|i| (i < params).then_some(i as u32)).collect(),
+                local_types: f.locals.clone(),
+                call_sites,
+            };
+            funcs.insert(f.fidx, (None, map));
+            Ok(())
+        })?;
+        let (new_instrs, new_func_count) = scan(new, |_| Ok(()))?;
+
+        for (old_pos, new_pos) in instruction_map {
+            let Some(old) = old_instrs.get(old_pos) else { continue };
+            let mut new = new_instrs
+                .get(new_pos)
+                .ok_or_else(|| anyhow::anyhow!("Position {:#x} is not an instruction in the instrumented binary", new_pos))?;
+            // elseのないifは `else` と空のelse節が補われるので、元の `end` は補われた `else` の次の `end` に対応する
+            if old.kind == Kind::End && new.kind == Kind::Else {
+                new = new_instrs
+                    .get(&(new_pos + 1))
+                    .ok_or_else(|| anyhow::anyhow!("No `end` after `else` at {:#x} in the instrumented binary", new_pos))?;
+            }
+
+            let (fidx, map) = funcs.get_mut(&old.fidx).expect("scanned function");
+            match fidx {
+                Some(fidx) if *fidx != new.fidx => anyhow::bail!(
+                    "Instructions of function {} are spread over functions {} and {}", old.fidx, fidx, new.fidx
+                ),
+                _ => *fidx = Some(new.fidx),
+            }
+            map.offsets.insert(old.offset, new.offset);
+            if let (Kind::Local(old_local), Kind::Local(new_local)) = (old.kind, new.kind) {
+                map.locals[old_local as usize] = Some(new_local);
+            }
+        }
+
+        let funcs = funcs
+            .into_iter()
+            .map(|(old_fidx, (fidx, map))| {
+                let fidx = fidx.ok_or_else(|| anyhow::anyhow!("Function {} was not found in the instrumented binary", old_fidx))?;
+                Ok((old_fidx, FuncMap { fidx, ..map }))
+            })
+            .collect::<Result<_>>()?;
+        Ok(OffsetMap { funcs, new_func_count })
+    }
+}
@@ -1,5 +1,5 @@
 use wasmparser::Operator;
-use crate::core::val::{WasmType, valtype_to_wasmtype};
+use crate::core::val::{WasmType, heaptype_to_wasmtype, reftype_to_wasmtype, valtype_to_wasmtype};
 use crate::core::function_v2::BytecodeFunction;
 
 pub struct OpInfo {
@@ -39,8 +39,9 @@ impl<'a> BytecodeFunction<'a> {
                 };
             }
             Operator::If{ .. } => {
+                // [t1*, I32] -> [t1*]
                 return OpInfo {
-                    input: vec![],
+                    input: vec![WasmType::I32],
                     output: vec![],
                 };
             }
@@ -121,6 +122,69 @@ impl<'a> BytecodeFunction<'a> {
                     output: o,
                 };
             }
+            Operator::ReturnCall{ function_index } => {
+                // [Args*] -> [] (呼び出し元のフレームはここで終わる)
+                let f = self.module.get_type_by_func(*function_index);
+                let i: Vec<WasmType> = f.params().iter().map(valtype_to_wasmtype).collect();
+
+                return OpInfo {
+                    input: i,
+                    output: vec![],
+                };
+            }
+            Operator::ReturnCallIndirect{ type_index, .. } => {
+                // [Args*, U32] -> []
+                let f = self.module.get_type_by_type(*type_index);
+                let i: Vec<WasmType> = f.params().iter().map(valtype_to_wasmtype)
+                                        .chain(std::iter::once(WasmType::I32)).collect();
+
+                return OpInfo {
+                    input: i,
+                    output: vec![],
+                };
+            }
+
+            // exception handling
+            Operator::Try{ .. } | Operator::TryTable{ .. } => {
+                // ブロックの引数はcreate_stack_tableで扱う
+                return OpInfo {
+                    input: vec![],
+                    output: vec![],
+                };
+            }
+            Operator::Catch{ tag_index } => {
+                // [] -> [tag params*]
+                let f = self.module.get_type_by_tag(*tag_index);
+                let o: Vec<WasmType> = f.params().iter().map(valtype_to_wasmtype).collect();
+
+                return OpInfo {
+                    input: vec![],
+                    output: o,
+                };
+            }
+            Operator::CatchAll | Operator::Delegate{ .. } | Operator::Rethrow{ .. } => {
+                return OpInfo {
+                    input: vec![],
+                    output: vec![],
+                };
+            }
+            Operator::Throw{ tag_index } => {
+                // [tag params*] -> []
+                let f = self.module.get_type_by_tag(*tag_index);
+                let i: Vec<WasmType> = f.params().iter().map(valtype_to_wasmtype).collect();
+
+                return OpInfo {
+                    input: i,
+                    output: vec![],
+                };
+            }
+            Operator::ThrowRef => {
+                // [exnref] -> []
+                return OpInfo {
+                    input: vec![WasmType::Any],
+                    output: vec![],
+                };
+            }
             Operator::Drop{ .. } => {
                 // [Any] -> []
                 // skip_label
@@ -136,8 +200,7 @@ impl<'a> BytecodeFunction<'a> {
                 };
             }
             Operator::Select{ .. } => {
-                // NOTE: don't emit any types
-                // unimplemented!("Not supported yet");
+                // NOTE: 結果型はcreate_stack_tableでオペランドの型に置き換える
 
                 // [Any, Any, U32] -> [Any]
                 let i = vec![WasmType::Any, WasmType::Any, WasmType::I32];
@@ -148,13 +211,11 @@ impl<'a> BytecodeFunction<'a> {
                     output: o,
                 };
             }
-            Operator::TypedSelect{ .. } => {
-                // NOTE: don't emit any types
-                // unimplemented!("Not supported yet");
-
-                // [Any, Any, U32] -> [Any]
-                let i = vec![WasmType::Any, WasmType::Any, WasmType::I32];
-                let o = vec![WasmType::Any];
+            Operator::TypedSelect{ ty } => {
+                // [t, t, U32] -> [t]
+                let t = valtype_to_wasmtype(ty);
+                let i = vec![t, t, WasmType::I32];
+                let o = vec![t];
 
                 return OpInfo {
                     input: i,
@@ -209,15 +270,21 @@ impl<'a> BytecodeFunction<'a> {
                     output: o,
                 };
             }
-            Operator::TableGet{ .. } => {
-                unimplemented!("Not supported yet");
-                // [U32] -> [Any]
-                // let i = vec![WasmType::I32];
-                // let o = vec![WasmType::Any];
+            Operator::TableGet{ table } => {
+                // [i32] -> [t]
+                let t = reftype_to_wasmtype(&self.module.get_type_by_table(*table).element_type);
+                let i = vec![WasmType::I32];
+                let o = vec![t];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
             }
-            Operator::TableSet{ .. } => {
-                // [U32, Any] -> []
-                let i = vec![WasmType::I32, WasmType::Any];
+            Operator::TableSet{ table } => {
+                // [i32, t] -> []
+                let t = reftype_to_wasmtype(&self.module.get_type_by_table(*table).element_type);
+                let i = vec![WasmType::I32, t];
                 let o = vec![];
 
                 return OpInfo {
@@ -543,7 +610,7 @@ impl<'a> BytecodeFunction<'a> {
             Operator::F32ConvertI64S | Operator::F32ConvertI64U => {
                 // [i64] -> [f32]
                 let i = vec![WasmType::I64];
-                let o = vec![WasmType::F64];
+                let o = vec![WasmType::F32];
 
                 return OpInfo {
                     input: i,
@@ -551,8 +618,8 @@ impl<'a> BytecodeFunction<'a> {
                 };
             }
             Operator::F32DemoteF64 => {
-                // [i64] -> [f32]
-                let i = vec![WasmType::I64];
+                // [f64] -> [f32]
+                let i = vec![WasmType::F64];
                 let o = vec![WasmType::F32];
 
                 return OpInfo {
@@ -657,9 +724,737 @@ impl<'a> BytecodeFunction<'a> {
                     output: o,
                 };
             }
+            Operator::MemoryInit{ .. } => {
+                // [i32 i32 i32] -> []
+                let i = vec![WasmType::I32, WasmType::I32, WasmType::I32];
+                let o = vec![];
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+            Operator::DataDrop{ .. } | Operator::ElemDrop{ .. } => {
+                // [] -> []
+                return OpInfo {
+                    input: vec![],
+                    output: vec![],
+                };
+            }
+
+            // reference types
+            Operator::RefNull{ hty } => {
+                // [] -> [t]
+                let i = vec![];
+                let o = vec![heaptype_to_wasmtype(hty)];
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+            Operator::RefIsNull => {
+                // [t] -> [i32]
+                let i = vec![WasmType::Any];
+                let o = vec![WasmType::I32];
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+            Operator::RefFunc{ .. } => {
+                // [] -> [funcref]
+                let i = vec![];
+                let o = vec![WasmType::FuncRef];
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            // table
+            Operator::TableSize{ .. } => {
+                // [] -> [i32]
+                let i = vec![];
+                let o = vec![WasmType::I32];
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+            Operator::TableGrow{ table } => {
+                // [t i32] -> [i32]
+                let t = reftype_to_wasmtype(&self.module.get_type_by_table(*table).element_type);
+                let i = vec![t, WasmType::I32];
+                let o = vec![WasmType::I32];
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+            Operator::TableFill{ table } => {
+                // [i32 t i32] -> []
+                let t = reftype_to_wasmtype(&self.module.get_type_by_table(*table).element_type);
+                let i = vec![WasmType::I32, t, WasmType::I32];
+                let o = vec![];
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+            Operator::TableCopy{ .. } | Operator::TableInit{ .. } => {
+                // [i32 i32 i32] -> []
+                let i = vec![WasmType::I32, WasmType::I32, WasmType::I32];
+                let o = vec![];
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            // threads (atomics)
+            Operator::MemoryAtomicNotify{ .. } => {
+                // [i32, i32] -> [i32]
+                let i = vec![WasmType::I32, WasmType::I32];
+                let o = vec![WasmType::I32];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::MemoryAtomicWait32{ .. } => {
+                // [i32, i32, i64] -> [i32]
+                let i = vec![WasmType::I32, WasmType::I32, WasmType::I64];
+                let o = vec![WasmType::I32];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::MemoryAtomicWait64{ .. } => {
+                // [i32, i64, i64] -> [i32]
+                let i = vec![WasmType::I32, WasmType::I64, WasmType::I64];
+                let o = vec![WasmType::I32];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::AtomicFence => {
+                // [] -> []
+                let i = vec![];
+                let o = vec![];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I32AtomicLoad{ .. } | Operator::I32AtomicLoad8U{ .. } | Operator::I32AtomicLoad16U{ .. } => {
+                // [i32] -> [i32]
+                let i = vec![WasmType::I32];
+                let o = vec![WasmType::I32];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I64AtomicLoad{ .. } | Operator::I64AtomicLoad8U{ .. } | Operator::I64AtomicLoad16U{ .. } |
+            Operator::I64AtomicLoad32U{ .. } => {
+                // [i32] -> [i64]
+                let i = vec![WasmType::I32];
+                let o = vec![WasmType::I64];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I32AtomicStore{ .. } | Operator::I32AtomicStore8{ .. } | Operator::I32AtomicStore16{ .. } => {
+                // [i32, i32] -> []
+                let i = vec![WasmType::I32, WasmType::I32];
+                let o = vec![];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I64AtomicStore{ .. } | Operator::I64AtomicStore8{ .. } | Operator::I64AtomicStore16{ .. } |
+            Operator::I64AtomicStore32{ .. } => {
+                // [i32, i64] -> []
+                let i = vec![WasmType::I32, WasmType::I64];
+                let o = vec![];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I32AtomicRmwAdd{ .. } | Operator::I32AtomicRmw8AddU{ .. } | Operator::I32AtomicRmw16AddU{ .. } |
+            Operator::I32AtomicRmwSub{ .. } | Operator::I32AtomicRmw8SubU{ .. } | Operator::I32AtomicRmw16SubU{ .. } |
+            Operator::I32AtomicRmwAnd{ .. } | Operator::I32AtomicRmw8AndU{ .. } | Operator::I32AtomicRmw16AndU{ .. } |
+            Operator::I32AtomicRmwOr{ .. } | Operator::I32AtomicRmw8OrU{ .. } | Operator::I32AtomicRmw16OrU{ .. } |
+            Operator::I32AtomicRmwXor{ .. } | Operator::I32AtomicRmw8XorU{ .. } | Operator::I32AtomicRmw16XorU{ .. } |
+            Operator::I32AtomicRmwXchg{ .. } | Operator::I32AtomicRmw8XchgU{ .. } | Operator::I32AtomicRmw16XchgU{ .. } => {
+                // [i32, i32] -> [i32]
+                let i = vec![WasmType::I32, WasmType::I32];
+                let o = vec![WasmType::I32];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I64AtomicRmwAdd{ .. } | Operator::I64AtomicRmw8AddU{ .. } | Operator::I64AtomicRmw16AddU{ .. } |
+            Operator::I64AtomicRmw32AddU{ .. } | Operator::I64AtomicRmwSub{ .. } | Operator::I64AtomicRmw8SubU{ .. } |
+            Operator::I64AtomicRmw16SubU{ .. } | Operator::I64AtomicRmw32SubU{ .. } | Operator::I64AtomicRmwAnd{ .. } |
+            Operator::I64AtomicRmw8AndU{ .. } | Operator::I64AtomicRmw16AndU{ .. } | Operator::I64AtomicRmw32AndU{ .. } |
+            Operator::I64AtomicRmwOr{ .. } | Operator::I64AtomicRmw8OrU{ .. } | Operator::I64AtomicRmw16OrU{ .. } |
+            Operator::I64AtomicRmw32OrU{ .. } | Operator::I64AtomicRmwXor{ .. } | Operator::I64AtomicRmw8XorU{ .. } |
+            Operator::I64AtomicRmw16XorU{ .. } | Operator::I64AtomicRmw32XorU{ .. } | Operator::I64AtomicRmwXchg{ .. } |
+            Operator::I64AtomicRmw8XchgU{ .. } | Operator::I64AtomicRmw16XchgU{ .. } | Operator::I64AtomicRmw32XchgU{ .. } => {
+                // [i32, i64] -> [i64]
+                let i = vec![WasmType::I32, WasmType::I64];
+                let o = vec![WasmType::I64];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I32AtomicRmwCmpxchg{ .. } | Operator::I32AtomicRmw8CmpxchgU{ .. } | Operator::I32AtomicRmw16CmpxchgU{ .. } => {
+                // [i32, i32, i32] -> [i32]
+                let i = vec![WasmType::I32, WasmType::I32, WasmType::I32];
+                let o = vec![WasmType::I32];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I64AtomicRmwCmpxchg{ .. } | Operator::I64AtomicRmw8CmpxchgU{ .. } | Operator::I64AtomicRmw16CmpxchgU{ .. } |
+            Operator::I64AtomicRmw32CmpxchgU{ .. } => {
+                // [i32, i64, i64] -> [i64]
+                let i = vec![WasmType::I32, WasmType::I64, WasmType::I64];
+                let o = vec![WasmType::I64];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            // SIMD
+            Operator::V128Load{ .. } | Operator::V128Load8x8S{ .. } | Operator::V128Load8x8U{ .. } | Operator::V128Load16x4S{ .. } |
+            Operator::V128Load16x4U{ .. } | Operator::V128Load32x2S{ .. } | Operator::V128Load32x2U{ .. } | Operator::V128Load8Splat{ .. } |
+            Operator::V128Load16Splat{ .. } | Operator::V128Load32Splat{ .. } | Operator::V128Load64Splat{ .. } | Operator::V128Load32Zero{ .. } |
+            Operator::V128Load64Zero{ .. } => {
+                // [i32] -> [v128]
+                let i = vec![WasmType::I32];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::V128Load8Lane{ .. } | Operator::V128Load16Lane{ .. } | Operator::V128Load32Lane{ .. } | Operator::V128Load64Lane{ .. } => {
+                // [i32, v128] -> [v128]
+                let i = vec![WasmType::I32, WasmType::V128];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::V128Store{ .. } | Operator::V128Store8Lane{ .. } | Operator::V128Store16Lane{ .. } | Operator::V128Store32Lane{ .. } |
+            Operator::V128Store64Lane{ .. } => {
+                // [i32, v128] -> []
+                let i = vec![WasmType::I32, WasmType::V128];
+                let o = vec![];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::V128Const{ .. } => {
+                // [] -> [v128]
+                let i = vec![];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I8x16ExtractLaneS{ .. } | Operator::I8x16ExtractLaneU{ .. } | Operator::I16x8ExtractLaneS{ .. } | Operator::I16x8ExtractLaneU{ .. } |
+            Operator::I32x4ExtractLane{ .. } => {
+                // [v128] -> [i32]
+                let i = vec![WasmType::V128];
+                let o = vec![WasmType::I32];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I64x2ExtractLane{ .. } => {
+                // [v128] -> [i64]
+                let i = vec![WasmType::V128];
+                let o = vec![WasmType::I64];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::F32x4ExtractLane{ .. } => {
+                // [v128] -> [f32]
+                let i = vec![WasmType::V128];
+                let o = vec![WasmType::F32];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::F64x2ExtractLane{ .. } => {
+                // [v128] -> [f64]
+                let i = vec![WasmType::V128];
+                let o = vec![WasmType::F64];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I8x16ReplaceLane{ .. } | Operator::I16x8ReplaceLane{ .. } | Operator::I32x4ReplaceLane{ .. } => {
+                // [v128, i32] -> [v128]
+                let i = vec![WasmType::V128, WasmType::I32];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I64x2ReplaceLane{ .. } => {
+                // [v128, i64] -> [v128]
+                let i = vec![WasmType::V128, WasmType::I64];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::F32x4ReplaceLane{ .. } => {
+                // [v128, f32] -> [v128]
+                let i = vec![WasmType::V128, WasmType::F32];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::F64x2ReplaceLane{ .. } => {
+                // [v128, f64] -> [v128]
+                let i = vec![WasmType::V128, WasmType::F64];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I8x16Splat | Operator::I16x8Splat | Operator::I32x4Splat => {
+                // [i32] -> [v128]
+                let i = vec![WasmType::I32];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I64x2Splat => {
+                // [i64] -> [v128]
+                let i = vec![WasmType::I64];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::F32x4Splat => {
+                // [f32] -> [v128]
+                let i = vec![WasmType::F32];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::F64x2Splat => {
+                // [f64] -> [v128]
+                let i = vec![WasmType::F64];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::V128AnyTrue | Operator::I8x16AllTrue | Operator::I8x16Bitmask | Operator::I16x8AllTrue |
+            Operator::I16x8Bitmask | Operator::I32x4AllTrue | Operator::I32x4Bitmask | Operator::I64x2AllTrue |
+            Operator::I64x2Bitmask => {
+                // [v128] -> [i32]
+                let i = vec![WasmType::V128];
+                let o = vec![WasmType::I32];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I8x16Shl | Operator::I8x16ShrS | Operator::I8x16ShrU | Operator::I16x8Shl |
+            Operator::I16x8ShrS | Operator::I16x8ShrU | Operator::I32x4Shl | Operator::I32x4ShrS |
+            Operator::I32x4ShrU | Operator::I64x2Shl | Operator::I64x2ShrS | Operator::I64x2ShrU => {
+                // [v128, i32] -> [v128]
+                let i = vec![WasmType::V128, WasmType::I32];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::V128Not | Operator::I8x16Abs | Operator::I8x16Neg | Operator::I8x16Popcnt |
+            Operator::I16x8ExtAddPairwiseI8x16S | Operator::I16x8ExtAddPairwiseI8x16U | Operator::I16x8Abs | Operator::I16x8Neg |
+            Operator::I16x8ExtendLowI8x16S | Operator::I16x8ExtendHighI8x16S | Operator::I16x8ExtendLowI8x16U | Operator::I16x8ExtendHighI8x16U |
+            Operator::I32x4ExtAddPairwiseI16x8S | Operator::I32x4ExtAddPairwiseI16x8U | Operator::I32x4Abs | Operator::I32x4Neg |
+            Operator::I32x4ExtendLowI16x8S | Operator::I32x4ExtendHighI16x8S | Operator::I32x4ExtendLowI16x8U | Operator::I32x4ExtendHighI16x8U |
+            Operator::I64x2Abs | Operator::I64x2Neg | Operator::I64x2ExtendLowI32x4S | Operator::I64x2ExtendHighI32x4S |
+            Operator::I64x2ExtendLowI32x4U | Operator::I64x2ExtendHighI32x4U | Operator::F32x4Ceil | Operator::F32x4Floor |
+            Operator::F32x4Trunc | Operator::F32x4Nearest | Operator::F32x4Abs | Operator::F32x4Neg |
+            Operator::F32x4Sqrt | Operator::F64x2Ceil | Operator::F64x2Floor | Operator::F64x2Trunc |
+            Operator::F64x2Nearest | Operator::F64x2Abs | Operator::F64x2Neg | Operator::F64x2Sqrt |
+            Operator::I32x4TruncSatF32x4S | Operator::I32x4TruncSatF32x4U | Operator::F32x4ConvertI32x4S | Operator::F32x4ConvertI32x4U |
+            Operator::I32x4TruncSatF64x2SZero | Operator::I32x4TruncSatF64x2UZero | Operator::F64x2ConvertLowI32x4S | Operator::F64x2ConvertLowI32x4U |
+            Operator::F32x4DemoteF64x2Zero | Operator::F64x2PromoteLowF32x4 => {
+                // [v128] -> [v128]
+                let i = vec![WasmType::V128];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I8x16Shuffle{ .. } | Operator::I8x16Swizzle | Operator::I8x16Eq | Operator::I8x16Ne |
+            Operator::I8x16LtS | Operator::I8x16LtU | Operator::I8x16GtS | Operator::I8x16GtU |
+            Operator::I8x16LeS | Operator::I8x16LeU | Operator::I8x16GeS | Operator::I8x16GeU |
+            Operator::I16x8Eq | Operator::I16x8Ne | Operator::I16x8LtS | Operator::I16x8LtU |
+            Operator::I16x8GtS | Operator::I16x8GtU | Operator::I16x8LeS | Operator::I16x8LeU |
+            Operator::I16x8GeS | Operator::I16x8GeU | Operator::I32x4Eq | Operator::I32x4Ne |
+            Operator::I32x4LtS | Operator::I32x4LtU | Operator::I32x4GtS | Operator::I32x4GtU |
+            Operator::I32x4LeS | Operator::I32x4LeU | Operator::I32x4GeS | Operator::I32x4GeU |
+            Operator::I64x2Eq | Operator::I64x2Ne | Operator::I64x2LtS | Operator::I64x2GtS |
+            Operator::I64x2LeS | Operator::I64x2GeS | Operator::F32x4Eq | Operator::F32x4Ne |
+            Operator::F32x4Lt | Operator::F32x4Gt | Operator::F32x4Le | Operator::F32x4Ge |
+            Operator::F64x2Eq | Operator::F64x2Ne | Operator::F64x2Lt | Operator::F64x2Gt |
+            Operator::F64x2Le | Operator::F64x2Ge | Operator::V128And | Operator::V128AndNot |
+            Operator::V128Or | Operator::V128Xor | Operator::I8x16NarrowI16x8S | Operator::I8x16NarrowI16x8U |
+            Operator::I8x16Add | Operator::I8x16AddSatS | Operator::I8x16AddSatU | Operator::I8x16Sub |
+            Operator::I8x16SubSatS | Operator::I8x16SubSatU | Operator::I8x16MinS | Operator::I8x16MinU |
+            Operator::I8x16MaxS | Operator::I8x16MaxU | Operator::I8x16AvgrU | Operator::I16x8Q15MulrSatS |
+            Operator::I16x8NarrowI32x4S | Operator::I16x8NarrowI32x4U | Operator::I16x8Add | Operator::I16x8AddSatS |
+            Operator::I16x8AddSatU | Operator::I16x8Sub | Operator::I16x8SubSatS | Operator::I16x8SubSatU |
+            Operator::I16x8Mul | Operator::I16x8MinS | Operator::I16x8MinU | Operator::I16x8MaxS |
+            Operator::I16x8MaxU | Operator::I16x8AvgrU | Operator::I16x8ExtMulLowI8x16S | Operator::I16x8ExtMulHighI8x16S |
+            Operator::I16x8ExtMulLowI8x16U | Operator::I16x8ExtMulHighI8x16U | Operator::I32x4Add | Operator::I32x4Sub |
+            Operator::I32x4Mul | Operator::I32x4MinS | Operator::I32x4MinU | Operator::I32x4MaxS |
+            Operator::I32x4MaxU | Operator::I32x4DotI16x8S | Operator::I32x4ExtMulLowI16x8S | Operator::I32x4ExtMulHighI16x8S |
+            Operator::I32x4ExtMulLowI16x8U | Operator::I32x4ExtMulHighI16x8U | Operator::I64x2Add | Operator::I64x2Sub |
+            Operator::I64x2Mul | Operator::I64x2ExtMulLowI32x4S | Operator::I64x2ExtMulHighI32x4S | Operator::I64x2ExtMulLowI32x4U |
+            Operator::I64x2ExtMulHighI32x4U | Operator::F32x4Add | Operator::F32x4Sub | Operator::F32x4Mul |
+            Operator::F32x4Div | Operator::F32x4Min | Operator::F32x4Max | Operator::F32x4PMin |
+            Operator::F32x4PMax | Operator::F64x2Add | Operator::F64x2Sub | Operator::F64x2Mul |
+            Operator::F64x2Div | Operator::F64x2Min | Operator::F64x2Max | Operator::F64x2PMin |
+            Operator::F64x2PMax => {
+                // [v128, v128] -> [v128]
+                let i = vec![WasmType::V128, WasmType::V128];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::V128Bitselect => {
+                // [v128, v128, v128] -> [v128]
+                let i = vec![WasmType::V128, WasmType::V128, WasmType::V128];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I32x4RelaxedTruncF32x4S | Operator::I32x4RelaxedTruncF32x4U | Operator::I32x4RelaxedTruncF64x2SZero | Operator::I32x4RelaxedTruncF64x2UZero => {
+                // relaxed-simd: [v128] -> [v128]
+                let i = vec![WasmType::V128];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::I8x16RelaxedSwizzle | Operator::F32x4RelaxedMin | Operator::F32x4RelaxedMax | Operator::F64x2RelaxedMin |
+            Operator::F64x2RelaxedMax | Operator::I16x8RelaxedQ15mulrS | Operator::I16x8RelaxedDotI8x16I7x16S => {
+                // relaxed-simd: [v128, v128] -> [v128]
+                let i = vec![WasmType::V128, WasmType::V128];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
+
+            Operator::F32x4RelaxedMadd | Operator::F32x4RelaxedNmadd | Operator::F64x2RelaxedMadd | Operator::F64x2RelaxedNmadd |
+            Operator::I8x16RelaxedLaneselect | Operator::I16x8RelaxedLaneselect | Operator::I32x4RelaxedLaneselect | Operator::I64x2RelaxedLaneselect |
+            Operator::I32x4RelaxedDotI8x16I7x16AddS => {
+                // relaxed-simd: [v128, v128, v128] -> [v128]
+                let i = vec![WasmType::V128, WasmType::V128, WasmType::V128];
+                let o = vec![WasmType::V128];
+
+                return OpInfo {
+                    input: i,
+                    output: o,
+                };
+            }
             ref _other => {
                 unimplemented!("Unsupported operator: {:?}", op);
             }
         }
     }
-}
\ No newline at end of file
+}
+
+
+/// Returns the indices of the linear memories accessed by `op`.
+pub fn accessed_memories(op: &Operator) -> Vec<u32> {
+    match op {
+        Operator::I32Load { memarg, .. } | Operator::I64Load { memarg, .. } | Operator::F32Load { memarg, .. } |
+        Operator::F64Load { memarg, .. } | Operator::I32Load8S { memarg, .. } | Operator::I32Load8U { memarg, .. } |
+        Operator::I32Load16S { memarg, .. } | Operator::I32Load16U { memarg, .. } | Operator::I64Load8S { memarg, .. } |
+        Operator::I64Load8U { memarg, .. } | Operator::I64Load16S { memarg, .. } | Operator::I64Load16U { memarg, .. } |
+        Operator::I64Load32S { memarg, .. } | Operator::I64Load32U { memarg, .. } | Operator::I32Store { memarg, .. } |
+        Operator::I64Store { memarg, .. } | Operator::F32Store { memarg, .. } | Operator::F64Store { memarg, .. } |
+        Operator::I32Store8 { memarg, .. } | Operator::I32Store16 { memarg, .. } | Operator::I64Store8 { memarg, .. } |
+        Operator::I64Store16 { memarg, .. } | Operator::I64Store32 { memarg, .. } | Operator::MemoryAtomicNotify { memarg, .. } |
+        Operator::MemoryAtomicWait32 { memarg, .. } | Operator::MemoryAtomicWait64 { memarg, .. } | Operator::I32AtomicLoad { memarg, .. } |
+        Operator::I64AtomicLoad { memarg, .. } | Operator::I32AtomicLoad8U { memarg, .. } | Operator::I32AtomicLoad16U { memarg, .. } |
+        Operator::I64AtomicLoad8U { memarg, .. } | Operator::I64AtomicLoad16U { memarg, .. } | Operator::I64AtomicLoad32U { memarg, .. } |
+        Operator::I32AtomicStore { memarg, .. } | Operator::I64AtomicStore { memarg, .. } | Operator::I32AtomicStore8 { memarg, .. } |
+        Operator::I32AtomicStore16 { memarg, .. } | Operator::I64AtomicStore8 { memarg, .. } | Operator::I64AtomicStore16 { memarg, .. } |
+        Operator::I64AtomicStore32 { memarg, .. } | Operator::I32AtomicRmwAdd { memarg, .. } | Operator::I64AtomicRmwAdd { memarg, .. } |
+        Operator::I32AtomicRmw8AddU { memarg, .. } | Operator::I32AtomicRmw16AddU { memarg, .. } | Operator::I64AtomicRmw8AddU { memarg, .. } |
+        Operator::I64AtomicRmw16AddU { memarg, .. } | Operator::I64AtomicRmw32AddU { memarg, .. } | Operator::I32AtomicRmwSub { memarg, .. } |
+        Operator::I64AtomicRmwSub { memarg, .. } | Operator::I32AtomicRmw8SubU { memarg, .. } | Operator::I32AtomicRmw16SubU { memarg, .. } |
+        Operator::I64AtomicRmw8SubU { memarg, .. } | Operator::I64AtomicRmw16SubU { memarg, .. } | Operator::I64AtomicRmw32SubU { memarg, .. } |
+        Operator::I32AtomicRmwAnd { memarg, .. } | Operator::I64AtomicRmwAnd { memarg, .. } | Operator::I32AtomicRmw8AndU { memarg, .. } |
+        Operator::I32AtomicRmw16AndU { memarg, .. } | Operator::I64AtomicRmw8AndU { memarg, .. } | Operator::I64AtomicRmw16AndU { memarg, .. } |
+        Operator::I64AtomicRmw32AndU { memarg, .. } | Operator::I32AtomicRmwOr { memarg, .. } | Operator::I64AtomicRmwOr { memarg, .. } |
+        Operator::I32AtomicRmw8OrU { memarg, .. } | Operator::I32AtomicRmw16OrU { memarg, .. } | Operator::I64AtomicRmw8OrU { memarg, .. } |
+        Operator::I64AtomicRmw16OrU { memarg, .. } | Operator::I64AtomicRmw32OrU { memarg, .. } | Operator::I32AtomicRmwXor { memarg, .. } |
+        Operator::I64AtomicRmwXor { memarg, .. } | Operator::I32AtomicRmw8XorU { memarg, .. } | Operator::I32AtomicRmw16XorU { memarg, .. } |
+        Operator::I64AtomicRmw8XorU { memarg, .. } | Operator::I64AtomicRmw16XorU { memarg, .. } | Operator::I64AtomicRmw32XorU { memarg, .. } |
+        Operator::I32AtomicRmwXchg { memarg, .. } | Operator::I64AtomicRmwXchg { memarg, .. } | Operator::I32AtomicRmw8XchgU { memarg, .. } |
+        Operator::I32AtomicRmw16XchgU { memarg, .. } | Operator::I64AtomicRmw8XchgU { memarg, .. } | Operator::I64AtomicRmw16XchgU { memarg, .. } |
+        Operator::I64AtomicRmw32XchgU { memarg, .. } | Operator::I32AtomicRmwCmpxchg { memarg, .. } | Operator::I64AtomicRmwCmpxchg { memarg, .. } |
+        Operator::I32AtomicRmw8CmpxchgU { memarg, .. } | Operator::I32AtomicRmw16CmpxchgU { memarg, .. } | Operator::I64AtomicRmw8CmpxchgU { memarg, .. } |
+        Operator::I64AtomicRmw16CmpxchgU { memarg, .. } | Operator::I64AtomicRmw32CmpxchgU { memarg, .. } | Operator::V128Load { memarg, .. } |
+        Operator::V128Load8x8S { memarg, .. } | Operator::V128Load8x8U { memarg, .. } | Operator::V128Load16x4S { memarg, .. } |
+        Operator::V128Load16x4U { memarg, .. } | Operator::V128Load32x2S { memarg, .. } | Operator::V128Load32x2U { memarg, .. } |
+        Operator::V128Load8Splat { memarg, .. } | Operator::V128Load16Splat { memarg, .. } | Operator::V128Load32Splat { memarg, .. } |
+        Operator::V128Load64Splat { memarg, .. } | Operator::V128Load32Zero { memarg, .. } | Operator::V128Load64Zero { memarg, .. } |
+        Operator::V128Store { memarg, .. } | Operator::V128Load8Lane { memarg, .. } | Operator::V128Load16Lane { memarg, .. } |
+        Operator::V128Load32Lane { memarg, .. } | Operator::V128Load64Lane { memarg, .. } | Operator::V128Store8Lane { memarg, .. } |
+        Operator::V128Store16Lane { memarg, .. } | Operator::V128Store32Lane { memarg, .. } | Operator::V128Store64Lane { memarg, .. } => {
+            vec![memarg.memory]
+        }
+        Operator::MemorySize { mem } | Operator::MemoryGrow { mem } |
+        Operator::MemoryFill { mem } | Operator::MemoryInit { mem, .. } |
+        Operator::MemoryDiscard { mem } => {
+            vec![*mem]
+        }
+        Operator::MemoryCopy { dst_mem, src_mem } => {
+            vec![*dst_mem, *src_mem]
+        }
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::function_v2::Function;
+    use crate::core::module::new_module;
+    use crate::core::verify::{verify_stack_tables, verify_stack_tables_v1};
+    use anyhow::Result;
+
+    /// 各関数の最後の命令 (endの前) の入力と出力の型
+    fn last_signatures(wat: &str) -> Result<Vec<(Vec<WasmType>, Vec<WasmType>)>> {
+        let buf = wat::parse_str(wat)?;
+        let m = new_module(&buf)?;
+        let mut signatures = vec![];
+        for f in m.new_function_v2()? {
+            let Function::BytecodeFunction(f) = f else { continue };
+            let mut reader = f.body.get_operators_reader()?;
+            let mut ops = vec![];
+            while !reader.eof() {
+                ops.push(reader.read()?);
+            }
+            let info = f.opinfo(&ops[ops.len() - 2]);
+            signatures.push((info.input, info.output));
+        }
+        Ok(signatures)
+    }
+
+    /// フィクスチャのスタックテーブルとバリデータの食い違い
+    fn fixture_reports(wat: &str, v1: bool) -> Result<Vec<String>> {
+        let buf = wat::parse_str(wat)?;
+        let mut reports = verify_stack_tables(&buf)?;
+        if v1 {
+            reports.extend(verify_stack_tables_v1(&buf)?);
+        }
+        Ok(reports.iter().map(|m| m.to_string()).collect())
+    }
+
+    #[test]
+    fn test_simd_operators() -> Result<()> {
+        let reports = fixture_reports(include_str!("../../tests/fixtures/simd.wat"), false)?;
+        assert!(reports.is_empty(), "{:#?}", reports);
+
+        let signatures = last_signatures(r#"
+            (module
+              (func (param v128 v128) (result v128)
+                local.get 0
+                local.get 1
+                i8x16.shuffle 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15))
+        "#)?;
+        assert_eq!(signatures, vec![(vec![WasmType::V128, WasmType::V128], vec![WasmType::V128])]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_atomic_operators() -> Result<()> {
+        let reports = fixture_reports(include_str!("../../tests/fixtures/atomics.wat"), true)?;
+        assert!(reports.is_empty(), "{:#?}", reports);
+
+        let signatures = last_signatures(r#"
+            (module
+              (memory 1 1 shared)
+              (func (param i32 i32 i64) (result i32)
+                local.get 0
+                local.get 1
+                local.get 2
+                memory.atomic.wait32))
+        "#)?;
+        assert_eq!(signatures, vec![(vec![WasmType::I32, WasmType::I32, WasmType::I64], vec![WasmType::I32])]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reference_types_operators() -> Result<()> {
+        let reports = fixture_reports(include_str!("../../tests/fixtures/reference_types.wat"), true)?;
+        assert!(reports.is_empty(), "{:#?}", reports);
+
+        // table.grow の要素の型はテーブルの型
+        let signatures = last_signatures(r#"
+            (module
+              (table $funcs 1 funcref)
+              (table $externs 1 externref)
+              (func (result i32)
+                ref.null func
+                i32.const 1
+                table.grow $funcs)
+              (func (param externref) (result i32)
+                local.get 0
+                i32.const 1
+                table.grow $externs))
+        "#)?;
+        assert_eq!(signatures, vec![
+            (vec![WasmType::FuncRef, WasmType::I32], vec![WasmType::I32]),
+            (vec![WasmType::ExternRef, WasmType::I32], vec![WasmType::I32]),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_exception_and_tail_call_operators() -> Result<()> {
+        let reports = fixture_reports(include_str!("../../tests/fixtures/exceptions.wat"), false)?;
+        assert!(reports.is_empty(), "{:#?}", reports);
+
+        // throw の入力はタグの引数
+        let signatures = last_signatures(r#"
+            (module
+              (tag $e (param i32 f64))
+              (func (param i32 f64)
+                local.get 0
+                local.get 1
+                throw $e)
+              (func (param exnref)
+                local.get 0
+                throw_ref))
+        "#)?;
+        assert_eq!(signatures, vec![
+            (vec![WasmType::I32, WasmType::F64], vec![]),
+            (vec![WasmType::Any], vec![]),
+        ]);
+        Ok(())
+    }
+}
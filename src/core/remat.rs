@@ -0,0 +1,160 @@
+//! 再計算 (rematerialization) できるスタックの値
+//!
+//! 定数や、積まれてから書き換えられていないローカルのコピーは、
+//! チェックポイントに保存しなくてもリストア時に `CompiledOp` から復元できる.
+//!
+//! ローカルのコピーは、`local.get` から現在のコード位置までに同じローカルへの
+//! `local.set` / `local.tee` がなければ再計算できる. 途中で開いたloopの中にいる場合は
+//! 前の反復で実行されたloopの残りの部分も調べる.
+//! ブロックの境界で合流する値 (ブロックの引数・結果) はどの経路から来たか分からないので再計算しない.
+//! また、リストア時にローカルから読み直すので、そのコード位置でローカルが生きている (保存される) 必要がある.
+
+use anyhow::Result;
+use wasmparser::Operator;
+
+use crate::core::function_v2::{BytecodeFunction, CodePos};
+use crate::core::liveness::{entry_layout, min_heights, read_operators, LiveMask};
+
+/// `create_stack_table` の各要素について、スタックの各値が再計算できるか. `live` は `liveness::analyze` の結果
+pub fn analyze(f: &BytecodeFunction, table: &[CodePos], live: &[LiveMask]) -> Result<Vec<Vec<bool>>> {
+    let ops = read_operators(f)?;
+    let (entry_ops, call_sites) = entry_layout(table);
+    if entry_ops.last().map_or(0, |i| i + 1) != ops.len() {
+        anyhow::bail!("Stack table of function {} does not match its {} instructions", f.fidx, ops.len());
+    }
+    let heights = min_heights(f, table, &call_sites);
+    let loop_ends = loop_ends(&ops);
+
+    // 各値を積んだ命令の番号
+    let mut pushes: Vec<usize> = vec![];
+    let mut block_height = 0;
+    let mut flags = Vec::with_capacity(table.len());
+    for (((codepos, i), call_site), mask) in table.iter().zip(&entry_ops).zip(&call_sites).zip(live) {
+        let i = *i;
+        if !call_site {
+            let merge = match ops[i] {
+                Operator::End | Operator::Delegate { .. } => block_height,
+                Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } |
+                Operator::Try { .. } | Operator::TryTable { .. } |
+                Operator::Else | Operator::Catch { .. } | Operator::CatchAll => {
+                    codepos.labels.last().map_or(0, |l| l.height)
+                }
+                _ => usize::MAX,
+            };
+            let h = heights[i].min(merge);
+            pushes = (0..codepos.stack.len()).map(|p| if p < h { pushes[p] } else { i }).collect();
+            block_height = codepos.labels.last().map_or(0, |l| l.height);
+        }
+
+        let recomputable = codepos.stack.inner
+            .iter()
+            .zip(&pushes)
+            .map(|((op, _), push)| {
+                // 死んだローカルは保存されないので、そのコピーは読み直せない
+                let saved = match op {
+                    Operator::LocalGet { local_index } => mask.locals[*local_index as usize],
+                    _ => true,
+                };
+                saved && recomputable(&ops, &loop_ends, op, *push, i)
+            })
+            .collect();
+        flags.push(recomputable);
+    }
+    Ok(flags)
+}
+
+/// 命令 `push` で積まれた値 (`op`) が命令 `at` の時点で再計算できるか
+fn recomputable(ops: &[Operator], loop_ends: &[Option<usize>], op: &Operator, push: usize, at: usize) -> bool {
+    match (op, &ops[push]) {
+        (Operator::I32Const { .. }, Operator::I32Const { .. }) |
+        (Operator::I64Const { .. }, Operator::I64Const { .. }) |
+        (Operator::F32Const { .. }, Operator::F32Const { .. }) |
+        (Operator::F64Const { .. }, Operator::F64Const { .. }) => true,
+        (Operator::LocalGet { local_index }, Operator::LocalGet { local_index: pushed }) if local_index == pushed => {
+            // 積んだ後に開いて、まだ閉じていないloopは丸ごと調べる
+            let end = (push + 1..=at)
+                .filter_map(|k| loop_ends[k])
+                .filter(|end| *end > at)
+                .max()
+                .unwrap_or(at);
+            !ops[push + 1..=end].iter().any(|op| matches!(
+                op,
+                Operator::LocalSet { local_index: l } | Operator::LocalTee { local_index: l } if l == local_index
+            ))
+        }
+        _ => false,
+    }
+}
+
+/// loop命令の番号 → 対応する `end` の番号
+fn loop_ends(ops: &[Operator]) -> Vec<Option<usize>> {
+    let mut ends = vec![None; ops.len()];
+    let mut begins = vec![];
+    for (i, op) in ops.iter().enumerate() {
+        match op {
+            Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } |
+            Operator::Try { .. } | Operator::TryTable { .. } => begins.push(i),
+            Operator::End | Operator::Delegate { .. } => {
+                if let Some(b) = begins.pop() {
+                    if let Operator::Loop { .. } = ops[b] {
+                        ends[b] = Some(i);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    ends
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::function_v2::Function;
+    use crate::core::liveness;
+    use crate::core::module::new_module;
+
+    #[test]
+    fn test_recomputable_slots() -> Result<()> {
+        let buf = wat::parse_str(r#"
+            (module
+              (func (param i32 i32)
+                local.get 0
+                i64.const 3
+                local.get 1
+                loop
+                  i32.const 0
+                  local.get 1
+                  i32.add
+                  local.set 1
+                  local.get 0
+                  br_if 0
+                end
+                unreachable)
+              (func (param i32) (result i32)
+                local.get 0
+                nop))
+        "#)?;
+        let m = new_module(&buf)?;
+        let funcs = m.new_function_v2()?;
+        let flags = |fidx: usize| -> Result<Vec<Vec<bool>>> {
+            let f = match &funcs[fidx] {
+                Function::BytecodeFunction(f) => f,
+                Function::ImportFunction(_) => unreachable!(),
+            };
+            let table = f.create_stack_table(false)?;
+            let live = liveness::analyze(f, &table)?;
+            analyze(f, &table, &live)
+        };
+
+        let loop_flags = flags(0)?;
+        // local.get 1 の後: 全て再計算できる
+        assert_eq!(loop_flags[2], vec![true, true, true]);
+        // loop の中: 前の反復で local.set 1 が実行されるので local.get 1 のコピーは再計算できない
+        assert_eq!(loop_flags[4], vec![true, true, false, true]);
+
+        // nop の後: ローカル0はもう読まれない (保存されない) ので、そのコピーは再計算できない
+        assert_eq!(flags(1)?[1], vec![false]);
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+//! チェックポイントを取りうるコード位置 (セーフポイント)
+//!
+//! ランタイムは関数の入口、loopの先頭 (後方分岐の飛び先) と関数呼び出しでしかチェックポイントを取らないので、
+//! スタックテーブルもそれらのコード位置の要素だけあれば十分である.
+//!
+//! - 関数の入口: オペランドスタックは空で、ローカルの型はスタックテーブルにあるので要素は不要
+//! - loopの先頭: `loop` 命令のオフセットの要素 (loopに入った直後の状態)
+//! - 関数呼び出し: 呼び出し中の要素 (呼び出し命令のオフセット + 1)
+
+use std::collections::HashSet;
+
+use anyhow::Result;
+use serde::Serialize;
+use strum_macros::{Display, EnumString};
+use wasmparser::Operator;
+
+use crate::core::function_v2::{is_call_site, BytecodeFunction};
+use crate::core::stack_table::Offset;
+
+/// どのコード位置をセーフポイントにするか
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum SafepointPolicy {
+    /// loopの先頭だけ
+    Loops,
+    /// 関数呼び出しだけ
+    Calls,
+    /// 関数の入口、loopの先頭、関数呼び出し
+    All,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum SafepointKind {
+    FunctionEntry,
+    LoopHeader,
+    CallSite,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Safepoint {
+    pub kind: SafepointKind,
+    /// スタックテーブルのキー. 関数の入口は0 (要素はない)
+    pub offset: Offset,
+}
+
+impl Safepoint {
+    /// スタックテーブルの要素が必要か
+    pub fn has_entry(&self) -> bool {
+        self.kind != SafepointKind::FunctionEntry
+    }
+}
+
+/// 関数のセーフポイントをコード順に列挙する
+pub fn safepoints(f: &BytecodeFunction, policy: SafepointPolicy) -> Result<Vec<Safepoint>> {
+    let loops = matches!(policy, SafepointPolicy::Loops | SafepointPolicy::All);
+    let calls = matches!(policy, SafepointPolicy::Calls | SafepointPolicy::All);
+
+    let mut points = vec![];
+    if policy == SafepointPolicy::All {
+        points.push(Safepoint { kind: SafepointKind::FunctionEntry, offset: 0 });
+    }
+
+    for (offset, op) in f.operators()? {
+        if loops && matches!(op, Operator::Loop { .. }) {
+            points.push(Safepoint { kind: SafepointKind::LoopHeader, offset });
+        }
+        if calls && is_call_site(&op) {
+            points.push(Safepoint { kind: SafepointKind::CallSite, offset: offset + 1 });
+        }
+    }
+    Ok(points)
+}
+
+/// スタックテーブルに残すべきオフセット
+pub fn safepoint_offsets(f: &BytecodeFunction, policy: SafepointPolicy) -> Result<HashSet<Offset>> {
+    let offsets = safepoints(f, policy)?
+        .into_iter()
+        .filter(|p| p.has_entry())
+        .map(|p| p.offset)
+        .collect();
+    Ok(offsets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::function_v2::Function;
+    use crate::core::module::new_module;
+
+    #[test]
+    fn test_safepoints_by_policy() -> Result<()> {
+        let buf = wat::parse_str(r#"
+            (module
+              (func $f)
+              (func
+                loop
+                  call $f
+                  br 0
+                end))
+        "#)?;
+        let m = new_module(&buf)?;
+        let funcs = m.new_function_v2()?;
+        let f = match &funcs[1] {
+            Function::BytecodeFunction(f) => f,
+            Function::ImportFunction(_) => unreachable!(),
+        };
+
+        use SafepointKind::*;
+        let kinds = |policy| -> Result<Vec<(SafepointKind, Offset)>> {
+            Ok(safepoints(f, policy)?.into_iter().map(|p| (p.kind, p.offset)).collect())
+        };
+        // loop (offset 0), call (offset 2, 呼び出し中は 3)
+        assert_eq!(kinds(SafepointPolicy::Loops)?, vec![(LoopHeader, 0)]);
+        assert_eq!(kinds(SafepointPolicy::Calls)?, vec![(CallSite, 3)]);
+        assert_eq!(kinds(SafepointPolicy::All)?, vec![(FunctionEntry, 0), (LoopHeader, 0), (CallSite, 3)]);
+        assert_eq!("all".parse::<SafepointPolicy>()?, SafepointPolicy::All);
+        Ok(())
+    }
+}
@@ -1,14 +1,20 @@
 use anyhow::Result;
+use std::collections::HashSet;
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use wasmparser::Operator;
 
 use crate::core::function_v2::{CodePos, Function};
+use crate::core::liveness::{self, LiveMask};
+use crate::core::remat;
+use crate::core::safepoint::{self, SafepointPolicy};
+use crate::core::module;
 use crate::core::val::WasmType;
 
 use super::function_v2;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum CompiledOp {
     LocalGet(u32),
     I32Const(i32),
@@ -20,22 +26,144 @@ pub enum CompiledOp {
 }
 
 
+/// ラベルスタックの1要素. スナップショットの `LabelStack` の各列に対応する.
+/// アドレスは関数本体の先頭からのオフセット、スタックポインタと値の数はclassicインタプリタのセル単位
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Label {
+    pub begin: u32,
+    pub target: u32,
+    pub stack_pointer: u32,
+    pub cell_num: u32,
+}
+
 pub type Offset = u32;
 pub type Stack = Vec<(CompiledOp, WasmType)>;
+pub type Labels = Vec<Label>;
 #[derive(Serialize, Deserialize)]
 pub struct StackTable {
     locals: Vec<WasmType>,
     inner: IndexMap<Offset, Stack>,
+    /// 共有メモリにアクセスする関数かどうか (他スレッドとの一貫したスナップショットが必要)
+    #[serde(default)]
+    shared_memory: bool,
+    /// 各コード位置のラベルスタック (関数フレームから順)
+    #[serde(default)]
+    labels: IndexMap<Offset, Labels>,
+    /// 各コード位置で生存しているローカルとスタックの値. 死んでいる値は保存せず、リストア時は0で埋める
+    #[serde(default)]
+    live: IndexMap<Offset, LiveMask>,
+    /// 各コード位置でスタックの値が再計算できるか (定数か、書き換えられていないローカルのコピー)
+    #[serde(default)]
+    recomputable: IndexMap<Offset, Vec<bool>>,
 }
 
 impl StackTable {
-    pub fn new(locals: Vec<WasmType>, inner: IndexMap<Offset, Stack>) -> Self {
-        Self { locals, inner }
+    pub fn new(locals: Vec<WasmType>, inner: IndexMap<Offset, Stack>, labels: IndexMap<Offset, Labels>, shared_memory: bool) -> Self {
+        Self { locals, inner, shared_memory, labels, live: IndexMap::new(), recomputable: IndexMap::new() }
+    }
+
+    pub fn with_live(mut self, live: IndexMap<Offset, LiveMask>) -> Self {
+        self.live = live;
+        self
+    }
+
+    pub fn with_recomputable(mut self, recomputable: IndexMap<Offset, Vec<bool>>) -> Self {
+        self.recomputable = recomputable;
+        self
     }
     
+    pub fn locals(&self) -> &Vec<WasmType> {
+        &self.locals
+    }
+
     pub fn inner(&self) -> &IndexMap<Offset, Stack> {
         &self.inner
     }
+
+    pub fn shared_memory(&self) -> bool {
+        self.shared_memory
+    }
+
+    pub fn labels(&self) -> &IndexMap<Offset, Labels> {
+        &self.labels
+    }
+
+    pub fn live(&self) -> &IndexMap<Offset, LiveMask> {
+        &self.live
+    }
+
+    pub fn recomputable(&self) -> &IndexMap<Offset, Vec<bool>> {
+        &self.recomputable
+    }
+
+    /// `offsets` 以外のコード位置の要素を取り除く
+    pub fn retain_offsets(&mut self, offsets: &HashSet<Offset>) {
+        self.inner.retain(|offset, _| offsets.contains(offset));
+        self.labels.retain(|offset, _| offsets.contains(offset));
+        self.live.retain(|offset, _| offsets.contains(offset));
+        self.recomputable.retain(|offset, _| offsets.contains(offset));
+    }
+}
+
+/// スタックテーブルファイルの先頭4バイト
+pub const MAGIC: [u8; 4] = *b"WCST";
+/// ファイル形式のバージョン. 互換性のない変更をしたら上げる
+pub const FORMAT_VERSION: u32 = 1;
+
+/// スタックテーブルファイルのヘッダ
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub format_version: u32,
+    /// 生成したwacretのバージョン
+    pub wacret_version: String,
+    /// 入力wasmのSHA-256
+    pub wasm_sha256: [u8; 32],
+    pub before_execution: bool,
+}
+
+impl Header {
+    pub fn new(wasm: &[u8], before_execution: bool) -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            wacret_version: env!("CARGO_PKG_VERSION").to_string(),
+            wasm_sha256: Sha256::digest(wasm).into(),
+            before_execution,
+        }
+    }
+
+    /// マジックナンバーとヘッダを読み、形式のバージョンを検査する
+    pub fn read(reader: &mut &[u8]) -> Result<Self> {
+        if reader.len() < MAGIC.len() || reader[..MAGIC.len()] != MAGIC {
+            anyhow::bail!("Not a stack table file (missing magic number {:?})", std::str::from_utf8(&MAGIC)?);
+        }
+        *reader = &reader[MAGIC.len()..];
+
+        let header: Header = rmp_serde::from_read(&mut *reader)
+            .map_err(|e| anyhow::anyhow!("Failed to read stack table header: {}", e))?;
+        if header.format_version != FORMAT_VERSION {
+            anyhow::bail!(
+                "Unsupported stack table format version {} (written by wacret {}), expected {}",
+                header.format_version, header.wacret_version, FORMAT_VERSION
+            );
+        }
+        Ok(header)
+    }
+
+    /// テーブルが `wasm` から生成されたものか検査する
+    pub fn check_wasm(&self, wasm: &[u8]) -> Result<()> {
+        let actual: [u8; 32] = Sha256::digest(wasm).into();
+        if actual != self.wasm_sha256 {
+            anyhow::bail!(
+                "Stack table was generated from a different wasm module (sha256 {}, but the module is {})",
+                hex(&self.wasm_sha256), hex(&actual)
+            );
+        }
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
 #[derive(Serialize, Deserialize)]
@@ -47,37 +175,106 @@ impl StackTables {
         let stack_tables_iter = funcs
             .iter()
             .map(|f| match f {
-                Function::ImportFunction(_) => (f, Vec::new()),
+                Function::ImportFunction(_) => Ok((f, Vec::new())),
                 Function::BytecodeFunction(bf) => {
-                    let table = bf.create_stack_table(before_execution).expect("Failed to create stack table from BytecodeFunction");
-                    (f, table)
+                    let table = bf
+                        .create_stack_table(before_execution)
+                        .map_err(|e| anyhow::anyhow!("Failed to create the stack table of function {}: {}", bf.fidx, e))?;
+                    Ok((f, table))
                 }
-            });
-            // .collect::<Vec<_>>();
+            })
+            .collect::<Result<Vec<_>>>()?;
 
         // Vec<Vec<CodePos>> → Vec<StackTable> に変換
         let stack_tables = stack_tables_iter
+            .into_iter()
             .map(|(f, codepos_vec)| {
-                let locals = match f {
-                    Function::ImportFunction(_) => vec![],
-                    Function::BytecodeFunction(bf) => bf.locals.clone(),
+                let (locals, shared_memory, live, recomputable) = match f {
+                    Function::ImportFunction(_) => (vec![], false, vec![], vec![]),
+                    Function::BytecodeFunction(bf) => {
+                        let live = liveness::analyze(bf, &codepos_vec)?;
+                        let recomputable = remat::analyze(bf, &codepos_vec, &live)?;
+                        (bf.locals.clone(), bf.touches_shared_memory()?, live, recomputable)
+                    }
                 };
-                let inner = codepos_vec.into_iter().map(|codepos| from_codepos(&f, codepos)).collect();
-                StackTable::new(locals, inner)
+                let live = codepos_vec.iter().map(|c| c.offset).zip(live).collect();
+                let recomputable = codepos_vec.iter().map(|c| c.offset).zip(recomputable).collect();
+                let (inner, labels) = codepos_vec
+                    .into_iter()
+                    .map(|codepos| {
+                        let labels = (codepos.offset, labels_from_codepos(&codepos));
+                        (from_codepos(&f, codepos), labels)
+                    })
+                    .unzip();
+                Ok(StackTable::new(locals, inner, labels, shared_memory).with_live(live).with_recomputable(recomputable))
             })
-            .collect();
+            .collect::<Result<Vec<_>>>()?;
 
         Ok(StackTables(stack_tables))
     }
 
-    pub fn serialize(&self) -> Vec<u8> {
-        rmp_serde::to_vec_named(self).expect("Failed to serialize StackTables")
+    /// wasmバイナリから StackTables を構築する
+    pub fn from_wasm(buf: &[u8], before_execution: bool) -> Result<Self> {
+        let buf = buf.to_vec();
+
+        // コードから各セクションの情報を抽出
+        let m = module::new_module(&buf)?;
+        log::debug!("function size is {}", m.funcs.len());
+
+        // 関数クラスを初期化
+        let funcs = m.new_function_v2()?;
+
+        // 型スタック・命令スタックテーブルを生成
+        Self::from_func(funcs, before_execution)
+    }
+
+    /// セーフポイントの要素だけを残した StackTables を構築する
+    pub fn from_wasm_at_safepoints(buf: &[u8], before_execution: bool, policy: SafepointPolicy) -> Result<Self> {
+        let buf = buf.to_vec();
+        let m = module::new_module(&buf)?;
+        let funcs = m.new_function_v2()?;
+
+        let offsets = funcs
+            .iter()
+            .map(|f| match f {
+                Function::ImportFunction(_) => Ok(HashSet::new()),
+                Function::BytecodeFunction(bf) => safepoint::safepoint_offsets(bf, policy),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut tables = Self::from_func(funcs, before_execution)?;
+        for (table, offsets) in tables.0.iter_mut().zip(&offsets) {
+            table.retain_offsets(offsets);
+        }
+        Ok(tables)
+    }
+
+    /// ヘッダを付けてシリアライズする
+    ///
+    /// レイアウト: `MAGIC` (4 bytes) | msgpack(Header) | msgpack(StackTables)
+    pub fn serialize(&self, header: &Header) -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        rmp_serde::encode::write_named(&mut buf, header).expect("Failed to serialize Header");
+        rmp_serde::encode::write_named(&mut buf, self).expect("Failed to serialize StackTables");
+        buf
     }
 
-    pub fn deserialize(data: &[u8]) -> Self {
-        rmp_serde::from_slice(data).expect("Failed to deserialize StackTables")
+    /// ヘッダを検査してデシリアライズする
+    pub fn deserialize(data: &[u8]) -> Result<(Header, Self)> {
+        let mut reader = data;
+        let header = Header::read(&mut reader)?;
+        let tables = rmp_serde::from_read(reader)
+            .map_err(|e| anyhow::anyhow!("Failed to deserialize stack tables: {}", e))?;
+        Ok((header, tables))
     }
-    
+
+    /// `deserialize` に加えて、テーブルが `wasm` から生成されたものか検査する
+    pub fn deserialize_for(data: &[u8], wasm: &[u8]) -> Result<(Header, Self)> {
+        let (header, tables) = Self::deserialize(data)?;
+        header.check_wasm(wasm)?;
+        Ok((header, tables))
+    }
+
     pub fn get_locals(&self, fidx: usize) -> Result<&Vec<WasmType>> {
         let s = &self.0[fidx];
         Ok(&s.locals)
@@ -90,6 +287,27 @@ impl StackTables {
             .ok_or_else(|| anyhow::anyhow!("Stack not found for offset {}", offset))
     }
 
+    pub fn get_labels(&self, fidx: usize, offset: u32) -> Result<&Labels> {
+        let s = &self.0[fidx];
+        s.labels
+            .get(&offset)
+            .ok_or_else(|| anyhow::anyhow!("Labels not found for offset {}", offset))
+    }
+
+    pub fn get_live(&self, fidx: usize, offset: u32) -> Result<&LiveMask> {
+        let s = &self.0[fidx];
+        s.live
+            .get(&offset)
+            .ok_or_else(|| anyhow::anyhow!("Live mask not found for offset {}", offset))
+    }
+
+    pub fn get_recomputable(&self, fidx: usize, offset: u32) -> Result<&Vec<bool>> {
+        let s = &self.0[fidx];
+        s.recomputable
+            .get(&offset)
+            .ok_or_else(|| anyhow::anyhow!("Recomputable flags not found for offset {}", offset))
+    }
+
     pub fn get_stack_nth(&self, fidx: usize, n: usize) -> Result<&Stack> {
         let s = &self.0[fidx];
         let a = s.inner
@@ -133,4 +351,140 @@ pub fn from_codepos(func: &function_v2::Function, codepos: CodePos) -> (Offset,
         .collect();
 
     (offset, stack_vec)
-}
\ No newline at end of file
+}
+
+/// CodePos の制御フレームをセル単位のラベルスタックに変換
+pub fn labels_from_codepos(codepos: &CodePos) -> Labels {
+    let cells = |types: &mut dyn Iterator<Item = &WasmType>| types.map(|t| t.cell_num() as u32).sum::<u32>();
+    codepos
+        .labels
+        .iter()
+        .map(|label| {
+            let below = &codepos.stack.inner[..label.height.min(codepos.stack.len())];
+            Label {
+                begin: label.begin,
+                target: label.target,
+                stack_pointer: cells(&mut below.iter().map(|(_, t)| t)),
+                cell_num: cells(&mut label.types.iter()),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::module::new_module;
+
+    #[test]
+    fn test_shared_memory_flag() -> Result<()> {
+        let buf = wat::parse_str(r#"
+            (module
+              (memory $private 1)
+              (memory $shared 1 1 shared)
+              (func (param i32) (result i32)
+                (i32.atomic.rmw.add $shared (local.get 0) (i32.const 1)))
+              (func (param i32) (result i32)
+                (i32.load $private (local.get 0)))
+              (func (param i32)
+                (memory.copy $private $shared (local.get 0) (local.get 0) (local.get 0))))
+        "#)?;
+        let m = new_module(&buf)?;
+        let tables = StackTables::from_func(m.new_function_v2()?, false)?;
+        let flags = tables.iter().map(|t| t.shared_memory()).collect::<Vec<_>>();
+        assert_eq!(flags, vec![true, false, true]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_return_call_site_entry() -> Result<()> {
+        let buf = wat::parse_str(r#"
+            (module
+              (func $callee (param i64) (result i32)
+                i32.const 0)
+              (func (result i32)
+                f32.const 1
+                i64.const 2
+                return_call $callee))
+        "#)?;
+        let m = new_module(&buf)?;
+        let tables = StackTables::from_func(m.new_function_v2()?, false)?;
+        // return_call (offset 7) の呼び出し中は引数を除いたスタックが残る
+        let call_site = tables.get_stack(1, 8)?;
+        let types = call_site.iter().map(|(_, t)| *t).collect::<Vec<_>>();
+        assert_eq!(types, vec![WasmType::F32]);
+        assert!(tables.get_stack(1, 7)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_labels() -> Result<()> {
+        let buf = wat::parse_str(r#"
+            (module
+              (func (param i64) (result i32)
+                i32.const 9
+                block (result i64)
+                  local.get 0
+                  loop
+                    br 0
+                  end
+                end
+                drop))
+        "#)?;
+        let tables = StackTables::from_wasm(&buf, false)?;
+        let label = |begin, target, stack_pointer, cell_num| Label { begin, target, stack_pointer, cell_num };
+        // local.get 0 (offset 4) の実行後: 関数フレームとblock
+        assert_eq!(tables.get_labels(0, 4)?, &vec![label(0, 13, 0, 1), label(2, 11, 1, 2)]);
+        // br 0 (offset 8) の実行後: loopの分岐先は先頭
+        assert_eq!(tables.get_labels(0, 8)?, &vec![label(0, 13, 0, 1), label(2, 11, 1, 2), label(6, 6, 3, 0)]);
+        // end (offset 11) の実行後はblockを抜けている
+        assert_eq!(tables.get_labels(0, 11)?, &vec![label(0, 13, 0, 1)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_wasm_at_safepoints() -> Result<()> {
+        let buf = wat::parse_str(r#"
+            (module
+              (func $f (param i32))
+              (func (param i32)
+                loop
+                  local.get 0
+                  call $f
+                  br 0
+                end))
+        "#)?;
+        let all = StackTables::from_wasm(&buf, false)?;
+        let tables = StackTables::from_wasm_at_safepoints(&buf, false, SafepointPolicy::All)?;
+        // loop (offset 0) と call の呼び出し中 (offset 5) だけが残る
+        assert_eq!(tables.0[1].inner().keys().copied().collect::<Vec<_>>(), vec![0, 5]);
+        assert_eq!(tables.get_stack(1, 5)?, all.get_stack(1, 5)?);
+        assert_eq!(tables.0[1].live().len(), 2);
+        assert!(tables.0[0].inner().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_round_trip_and_rejects_mismatches() -> Result<()> {
+        let wasm = wat::parse_str("(module (func (result i32) i32.const 1))")?;
+        let other = wat::parse_str("(module (func (result i32) i32.const 2))")?;
+        let tables = StackTables::from_wasm(&wasm, true)?;
+        let data = tables.serialize(&Header::new(&wasm, true));
+
+        let (header, tables) = StackTables::deserialize_for(&data, &wasm)?;
+        assert_eq!(header, Header::new(&wasm, true));
+        assert_eq!(tables.0.len(), 1);
+
+        let err = StackTables::deserialize_for(&data, &other).err().unwrap();
+        assert!(err.to_string().contains("different wasm module"), "{}", err);
+
+        let err = StackTables::deserialize(&data[4..]).err().unwrap();
+        assert!(err.to_string().contains("magic"), "{}", err);
+
+        let mut header = Header::new(&wasm, true);
+        header.format_version = FORMAT_VERSION + 1;
+        let err = StackTables::deserialize(&tables.serialize(&header)).err().unwrap();
+        assert!(err.to_string().contains("format version"), "{}", err);
+        Ok(())
+    }
+}
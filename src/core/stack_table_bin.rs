@@ -0,0 +1,451 @@
+//! ランタイムから直接引けるスタックテーブルのバイナリ形式
+//!
+//! msgpack版 (`StackTables::serialize`) は全体をデシリアライズしないと引けないので、
+//! mmapしたまま二分探索できる固定長レイアウトも用意する. 数値はすべてリトルエンディアン.
+//!
+//! ```text
+//! Header (64 bytes)
+//!   0  magic            "WCSB"
+//!   4  format_version   u32
+//!   8  flags            u32   bit0: before_execution
+//!  12  func_count       u32
+//!  16  wasm_sha256      [u8; 32]
+//!  48  entries_pos      u32   スタック要素領域の先頭 (8byte境界)
+//!  52  entry_count      u32
+//!  56  labels_pos       u32   ラベル領域の先頭
+//!  60  label_count      u32
+//! FuncEntry (32 bytes) * func_count
+//!   0  locals_pos       u32   -> u8 (WasmType) * locals_count
+//!   4  locals_count     u32
+//!   8  offsets_pos      u32   -> u32 (コードオフセット, 昇順) * stack_count
+//!  12  stacks_pos       u32   -> StackRef * stack_count
+//!  16  stack_count      u32
+//!  20  flags            u32   bit0: shared_memory
+//!  24  label_refs_pos   u32   -> LabelRef * stack_count
+//!  28  live_refs_pos    u32   -> u32 (LiveMaskの位置) * stack_count
+//! StackRef (8 bytes)
+//!   0  first            u32   スタック要素領域内のインデックス (底から)
+//!   4  len              u32
+//! LabelRef (8 bytes)
+//!   0  first            u32   ラベル領域内のインデックス (関数フレームから)
+//!   4  len              u32
+//! StackEntry (16 bytes)
+//!   0  kind             u8    0: Other, 1: LocalGet, 2: I32Const, 3: F32Const,
+//!                             4: I64Const, 5: F64Const, 6: Call
+//!   1  type             u8    WasmType
+//!   2  reserved         [u8; 6]
+//!   8  value            u64   kindごとの即値 (ビット列)
+//! Label (16 bytes)
+//!   0  begin            u32
+//!   4  target           u32
+//!   8  stack_pointer    u32
+//!  12  cell_num         u32
+//! LiveMask ((locals_count + スタックの長さ) bits, バイト単位に切り上げ)
+//!   ローカル、スタックの値 (底から) の順に、bit i (i / 8 バイト目の i % 8 ビット) が1なら生きている
+//! ```
+//! `*_pos` はファイル先頭からのバイト位置.
+
+use anyhow::Result;
+
+use crate::core::liveness::LiveMask;
+use crate::core::stack_table::{CompiledOp, Header, Label, Labels, Offset, StackTables};
+use crate::core::val::WasmType;
+
+pub const MAGIC: [u8; 4] = *b"WCSB";
+pub const FORMAT_VERSION: u32 = 3;
+
+const HEADER_SIZE: usize = 64;
+const FUNC_ENTRY_SIZE: usize = 32;
+const STACK_REF_SIZE: usize = 8;
+const STACK_ENTRY_SIZE: usize = 16;
+const LABEL_SIZE: usize = 16;
+
+const FLAG_BEFORE_EXECUTION: u32 = 1;
+const FLAG_SHARED_MEMORY: u32 = 1;
+
+/// StackTables をバイナリ形式に変換する
+pub fn encode(tables: &StackTables, header: &Header) -> Vec<u8> {
+    let func_count = tables.0.len();
+
+    // 関数ごとのデータ (offsets, stacks, label_refs, live_refs, live masks, locals) はディレクトリの直後に並べる
+    let mut funcs = vec![];
+    let mut data = vec![];
+    let mut entries = vec![];
+    let mut labels = vec![];
+    let data_base = HEADER_SIZE + func_count * FUNC_ENTRY_SIZE;
+    for table in tables.iter() {
+        let pos = |data: &Vec<u8>| (data_base + data.len()) as u32;
+
+        // 呼び出し中のエントリ (offset + 1) は呼び出し後より先に記録されているので並べ直す
+        let mut sorted = table.inner().iter().collect::<Vec<_>>();
+        sorted.sort_by_key(|(offset, _)| **offset);
+
+        let offsets_pos = pos(&data);
+        for (offset, _) in &sorted {
+            put_u32(&mut data, **offset);
+        }
+
+        let stacks_pos = pos(&data);
+        for (_, stack) in &sorted {
+            put_u32(&mut data, (entries.len() / STACK_ENTRY_SIZE) as u32);
+            put_u32(&mut data, stack.len() as u32);
+            for (op, ty) in stack.iter() {
+                encode_entry(&mut entries, op, *ty);
+            }
+        }
+
+        let label_refs_pos = pos(&data);
+        for (offset, _) in &sorted {
+            let stack_labels = table.labels().get(*offset).map_or(&[][..], |l| &l[..]);
+            put_u32(&mut data, (labels.len() / LABEL_SIZE) as u32);
+            put_u32(&mut data, stack_labels.len() as u32);
+            for label in stack_labels {
+                for field in [label.begin, label.target, label.stack_pointer, label.cell_num] {
+                    put_u32(&mut labels, field);
+                }
+            }
+        }
+
+        // 生存情報がなければ全て生きているとする (保存を省かない)
+        let live_refs_pos = pos(&data);
+        let mut masks = vec![];
+        let masks_pos = live_refs_pos as usize + sorted.len() * 4;
+        for (offset, stack) in &sorted {
+            let bits = match table.live().get(*offset) {
+                Some(mask) => mask.locals.iter().chain(&mask.stack).copied().collect::<Vec<_>>(),
+                None => vec![true; table.locals().len() + stack.len()],
+            };
+            put_u32(&mut data, (masks_pos + masks.len()) as u32);
+            masks.extend(bits.chunks(8).map(|b| b.iter().enumerate().fold(0u8, |byte, (i, live)| byte | ((*live as u8) << i))));
+        }
+        data.extend_from_slice(&masks);
+        data.resize(data.len().next_multiple_of(4), 0);
+
+        let locals_pos = pos(&data);
+        data.extend(table.locals().iter().map(|t| *t as u8));
+        data.resize(data.len().next_multiple_of(4), 0);
+
+        let flags = if table.shared_memory() { FLAG_SHARED_MEMORY } else { 0 };
+        funcs.push([locals_pos, table.locals().len() as u32, offsets_pos, stacks_pos, table.inner().len() as u32, flags, label_refs_pos, live_refs_pos]);
+    }
+    let entries_pos = (data_base + data.len()).next_multiple_of(8);
+    let labels_pos = entries_pos + entries.len();
+
+    let mut buf = Vec::with_capacity(labels_pos + labels.len());
+    buf.extend_from_slice(&MAGIC);
+    put_u32(&mut buf, FORMAT_VERSION);
+    put_u32(&mut buf, if header.before_execution { FLAG_BEFORE_EXECUTION } else { 0 });
+    put_u32(&mut buf, func_count as u32);
+    buf.extend_from_slice(&header.wasm_sha256);
+    put_u32(&mut buf, entries_pos as u32);
+    put_u32(&mut buf, (entries.len() / STACK_ENTRY_SIZE) as u32);
+    put_u32(&mut buf, labels_pos as u32);
+    put_u32(&mut buf, (labels.len() / LABEL_SIZE) as u32);
+    for func in funcs {
+        for field in func {
+            put_u32(&mut buf, field);
+        }
+    }
+    buf.extend_from_slice(&data);
+    buf.resize(entries_pos, 0);
+    buf.extend_from_slice(&entries);
+    buf.extend_from_slice(&labels);
+    buf
+}
+
+/// StackEntry の (kind, value)
+pub fn raw_entry(op: &CompiledOp) -> (u8, u64) {
+    match op {
+        CompiledOp::Other(_) => (0, 0),
+        CompiledOp::LocalGet(idx) => (1, *idx as u64),
+        CompiledOp::I32Const(v) => (2, *v as u32 as u64),
+        CompiledOp::F32Const(bits) => (3, *bits as u64),
+        CompiledOp::I64Const(v) => (4, *v as u64),
+        CompiledOp::F64Const(bits) => (5, *bits),
+        CompiledOp::Call(result_size) => (6, *result_size as u64),
+    }
+}
+
+fn encode_entry(buf: &mut Vec<u8>, op: &CompiledOp, ty: WasmType) {
+    let (kind, value) = raw_entry(op);
+    buf.push(kind);
+    buf.push(ty as u8);
+    buf.extend_from_slice(&[0; 6]);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, n: u32) {
+    buf.extend_from_slice(&n.to_le_bytes());
+}
+
+/// バイナリ形式のスタックテーブルをコピーせずに引くためのリーダ
+#[derive(Clone, Copy)]
+pub struct StackTableView<'a> {
+    data: &'a [u8],
+    flags: u32,
+    func_count: u32,
+    entries_pos: usize,
+    entry_count: u32,
+    labels_pos: usize,
+    label_count: u32,
+}
+
+impl<'a> StackTableView<'a> {
+    /// ヘッダを検査する. 各関数の領域は引くときに範囲検査する
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        if data.len() < HEADER_SIZE || data[..4] != MAGIC {
+            anyhow::bail!("Not a binary stack table (missing magic number \"WCSB\")");
+        }
+        let version = read_u32(data, 4)?;
+        if version != FORMAT_VERSION {
+            anyhow::bail!("Unsupported binary stack table version {}, expected {}", version, FORMAT_VERSION);
+        }
+        let flags = read_u32(data, 8)?;
+        let func_count = read_u32(data, 12)?;
+        let entries_pos = read_u32(data, 48)? as usize;
+        let entry_count = read_u32(data, 52)?;
+        let labels_pos = read_u32(data, 56)? as usize;
+        let label_count = read_u32(data, 60)?;
+        let dir_end = HEADER_SIZE + func_count as usize * FUNC_ENTRY_SIZE;
+        let entries_end = entries_pos + entry_count as usize * STACK_ENTRY_SIZE;
+        let labels_end = labels_pos + label_count as usize * LABEL_SIZE;
+        if dir_end > data.len() || entries_end > data.len() || labels_end > data.len() {
+            anyhow::bail!("Binary stack table is truncated ({} bytes)", data.len());
+        }
+        Ok(Self { data, flags, func_count, entries_pos, entry_count, labels_pos, label_count })
+    }
+
+    pub fn func_count(&self) -> u32 {
+        self.func_count
+    }
+
+    pub fn before_execution(&self) -> bool {
+        self.flags & FLAG_BEFORE_EXECUTION != 0
+    }
+
+    pub fn wasm_sha256(&self) -> &'a [u8] {
+        &self.data[16..48]
+    }
+
+    pub fn shared_memory(&self, fidx: u32) -> Result<bool> {
+        Ok(self.func(fidx)?[5] & FLAG_SHARED_MEMORY != 0)
+    }
+
+    pub fn get_locals(&self, fidx: u32) -> Result<Vec<WasmType>> {
+        let [locals_pos, locals_count, ..] = self.func(fidx)?;
+        let raw = self.slice(locals_pos as usize, locals_count as usize)?;
+        raw.iter().map(|t| to_wasmtype(*t)).collect()
+    }
+
+    /// コードオフセットの昇順に並んだ記録位置
+    pub fn offsets(&self, fidx: u32) -> Result<impl Iterator<Item = Offset> + 'a> {
+        let [_, _, offsets_pos, _, stack_count, ..] = self.func(fidx)?;
+        let raw = self.slice(offsets_pos as usize, stack_count as usize * 4)?;
+        Ok(raw.chunks_exact(4).map(|b| u32::from_le_bytes(b.try_into().unwrap())))
+    }
+
+    /// オフセットを二分探索してスタックを引く
+    pub fn get_stack(&self, fidx: u32, offset: Offset) -> Result<StackView<'a>> {
+        let n = self.find(fidx, offset)?.ok_or_else(|| anyhow::anyhow!("Stack not found for offset {}", offset))?;
+        self.get_stack_nth(fidx, n)
+    }
+
+    pub fn get_stack_nth(&self, fidx: u32, n: usize) -> Result<StackView<'a>> {
+        let [_, _, _, stacks_pos, stack_count, ..] = self.func(fidx)?;
+        if n >= stack_count as usize {
+            anyhow::bail!("Stack not found for index {}", n);
+        }
+        let pos = stacks_pos as usize + n * STACK_REF_SIZE;
+        let (first, len) = (read_u32(self.data, pos)?, read_u32(self.data, pos + 4)?);
+        if first as u64 + len as u64 > self.entry_count as u64 {
+            anyhow::bail!("Stack {} of function {} is out of range", n, fidx);
+        }
+        let raw = self.slice(self.entries_pos + first as usize * STACK_ENTRY_SIZE, len as usize * STACK_ENTRY_SIZE)?;
+        Ok(StackView { raw })
+    }
+
+    /// オフセットを二分探索してラベルスタック (関数フレームから順) を引く
+    pub fn get_labels(&self, fidx: u32, offset: Offset) -> Result<Labels> {
+        let n = self.find(fidx, offset)?.ok_or_else(|| anyhow::anyhow!("Labels not found for offset {}", offset))?;
+        let [.., label_refs_pos, _] = self.func(fidx)?;
+        let pos = label_refs_pos as usize + n * 8;
+        let (first, len) = (read_u32(self.data, pos)?, read_u32(self.data, pos + 4)?);
+        if first as u64 + len as u64 > self.label_count as u64 {
+            anyhow::bail!("Labels {} of function {} are out of range", n, fidx);
+        }
+        let raw = self.slice(self.labels_pos + first as usize * LABEL_SIZE, len as usize * LABEL_SIZE)?;
+        Ok(raw
+            .chunks_exact(LABEL_SIZE)
+            .map(|b| {
+                let field = |i: usize| u32::from_le_bytes(b[i * 4..i * 4 + 4].try_into().unwrap());
+                Label { begin: field(0), target: field(1), stack_pointer: field(2), cell_num: field(3) }
+            })
+            .collect())
+    }
+
+    /// オフセットを二分探索して、生存しているローカルとスタックの値を引く
+    pub fn get_live(&self, fidx: u32, offset: Offset) -> Result<LiveMask> {
+        let n = self.find(fidx, offset)?.ok_or_else(|| anyhow::anyhow!("Live mask not found for offset {}", offset))?;
+        let [_, locals_count, .., live_refs_pos] = self.func(fidx)?;
+        let stack_len = self.get_stack_nth(fidx, n)?.len();
+        let pos = read_u32(self.data, live_refs_pos as usize + n * 4)? as usize;
+        let bit_count = locals_count as usize + stack_len;
+        let raw = self.slice(pos, bit_count.div_ceil(8))?;
+        let mut bits = (0..bit_count).map(|i| raw[i / 8] & (1 << (i % 8)) != 0);
+        let locals = bits.by_ref().take(locals_count as usize).collect();
+        Ok(LiveMask { locals, stack: bits.collect() })
+    }
+
+    /// `offset` の記録位置の番号. 見つからなければ None
+    fn find(&self, fidx: u32, offset: Offset) -> Result<Option<usize>> {
+        let [_, _, offsets_pos, _, stack_count, ..] = self.func(fidx)?;
+        let (mut lo, mut hi) = (0, stack_count);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let at = read_u32(self.data, offsets_pos as usize + mid as usize * 4)?;
+            if at < offset {
+                lo = mid + 1;
+            } else if at > offset {
+                hi = mid;
+            } else {
+                return Ok(Some(mid as usize));
+            }
+        }
+        Ok(None)
+    }
+
+    fn func(&self, fidx: u32) -> Result<[u32; 8]> {
+        if fidx >= self.func_count {
+            anyhow::bail!("Function {} is out of range ({} functions)", fidx, self.func_count);
+        }
+        let pos = HEADER_SIZE + fidx as usize * FUNC_ENTRY_SIZE;
+        let mut fields = [0; 8];
+        for (i, field) in fields.iter_mut().enumerate() {
+            *field = read_u32(self.data, pos + i * 4)?;
+        }
+        Ok(fields)
+    }
+
+    fn slice(&self, pos: usize, len: usize) -> Result<&'a [u8]> {
+        self.data
+            .get(pos..pos + len)
+            .ok_or_else(|| anyhow::anyhow!("Binary stack table is truncated ({} bytes)", self.data.len()))
+    }
+}
+
+/// 1つのスタック (底から順)
+#[derive(Clone, Copy)]
+pub struct StackView<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> StackView<'a> {
+    pub fn len(&self) -> usize {
+        self.raw.len() / STACK_ENTRY_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Result<(CompiledOp, WasmType)>> + 'a {
+        self.raw.chunks_exact(STACK_ENTRY_SIZE).map(decode_entry)
+    }
+}
+
+fn decode_entry(raw: &[u8]) -> Result<(CompiledOp, WasmType)> {
+    let ty = to_wasmtype(raw[1])?;
+    let value = u64::from_le_bytes(raw[8..16].try_into().unwrap());
+    let op = match raw[0] {
+        0 => CompiledOp::Other(ty),
+        1 => CompiledOp::LocalGet(value as u32),
+        2 => CompiledOp::I32Const(value as u32 as i32),
+        3 => CompiledOp::F32Const(value as u32),
+        4 => CompiledOp::I64Const(value as i64),
+        5 => CompiledOp::F64Const(value),
+        6 => CompiledOp::Call(value as u32),
+        kind => anyhow::bail!("Unknown stack entry kind {}", kind),
+    };
+    Ok((op, ty))
+}
+
+fn to_wasmtype(n: u8) -> Result<WasmType> {
+    WasmType::from_u8(n).ok_or_else(|| anyhow::anyhow!("Unknown wasm type {}", n))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32> {
+    let bytes = data
+        .get(pos..pos + 4)
+        .ok_or_else(|| anyhow::anyhow!("Binary stack table is truncated ({} bytes)", data.len()))?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_matches_msgpack_tables() -> Result<()> {
+        let wasm = wat::parse_str(r#"
+            (module
+              (memory 1 1 shared)
+              (func $callee (param i64) (result f32)
+                f32.const 1.5)
+              (func (param i32) (result f32)
+                (local f64)
+                local.get 0
+                i32.const -7
+                i64.const 3
+                call $callee
+                drop
+                drop
+                i32.atomic.load
+                drop
+                block
+                  f64.const 2
+                  local.set 1
+                end
+                f32.const 0))
+        "#)?;
+        let tables = StackTables::from_wasm(&wasm, false)?;
+        let buf = encode(&tables, &Header::new(&wasm, false));
+        let view = StackTableView::new(&buf)?;
+
+        assert_eq!(view.func_count(), 2);
+        assert!(!view.before_execution());
+        assert_eq!(view.wasm_sha256(), &Header::new(&wasm, false).wasm_sha256[..]);
+        for (fidx, table) in tables.iter().enumerate() {
+            let fidx = fidx as u32;
+            assert_eq!(&view.get_locals(fidx)?, table.locals());
+            assert_eq!(view.shared_memory(fidx)?, table.shared_memory());
+            let mut offsets = table.inner().keys().copied().collect::<Vec<_>>();
+            offsets.sort();
+            assert_eq!(view.offsets(fidx)?.collect::<Vec<_>>(), offsets);
+            for (offset, stack) in table.inner() {
+                let entries = view.get_stack(fidx, *offset)?.iter().collect::<Result<Vec<_>>>()?;
+                assert_eq!(&entries, stack, "fidx {}, offset {}", fidx, offset);
+                assert_eq!(&view.get_labels(fidx, *offset)?, &table.labels()[offset], "fidx {}, offset {}", fidx, offset);
+                assert_eq!(&view.get_live(fidx, *offset)?, &table.live()[offset], "fidx {}, offset {}", fidx, offset);
+            }
+        }
+        // block の中の f64.const: 関数フレームとblockの2つ
+        let block = Label { begin: 15, target: 28, stack_pointer: 0, cell_num: 0 };
+        assert_eq!(view.get_labels(1, 17)?[1], block);
+        // local.set 1 の直前: ローカル0はもう読まれず、ローカル1は上書きされるので両方死んでいる
+        assert_eq!(view.get_live(1, 17)?.locals, vec![false, false]);
+        assert!(view.get_stack(1, 1000).is_err());
+        assert!(view.get_labels(1, 1000).is_err());
+        assert!(view.get_live(1, 1000).is_err());
+        assert!(view.get_locals(2).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_truncated_table() -> Result<()> {
+        let wasm = wat::parse_str("(module (func (result i32) i32.const 1))")?;
+        let buf = encode(&StackTables::from_wasm(&wasm, true)?, &Header::new(&wasm, true));
+        assert!(StackTableView::new(&buf[..buf.len() - 1]).is_err());
+        assert!(StackTableView::new(&buf[1..]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+use crate::core::function::{BytecodeFunction, Function};
+use crate::core::module;
+
+use wasmparser::Operator;
+
+use anyhow::Result;
+
+const BYTE_U8: u32 = 1;
+const BYTE_U32: u32 = 4;
+const BYTE_U64: u32 = 8;
+
+/// v1形式の型スタックテーブル (WAMRが読み込む3つのファイルの中身)
+pub struct TypeTables {
+    pub type_table: Vec<u8>,
+    pub tablemap_func: Vec<u8>,
+    pub tablemap_offset: Vec<u8>,
+}
+
+impl TypeTables {
+    /// ファイル名と中身の組
+    pub fn files(&self) -> [(&'static str, &[u8]); 3] {
+        [
+            ("type_table", &self.type_table),
+            ("tablemap_func", &self.tablemap_func),
+            ("tablemap_offset", &self.tablemap_offset),
+        ]
+    }
+}
+
+pub fn create_type_tables(buf: &[u8]) -> Result<TypeTables> {
+    let buf = buf.to_vec();
+
+    // コードから各セクションの情報を抽出
+    let m = module::new_module(&buf)?;
+    log::debug!("function size is {}", m.funcs.len());
+
+    // 型スタックを生成
+    let funcs = m.parse()?;
+
+    // 型スタックから型スタックテーブルを生成する
+    let (tablemap_func, tablemap_offset) = calc_tablemap(&funcs);
+
+    Ok(TypeTables {
+        type_table: encode_type_stack_table(&funcs)?,
+        tablemap_func: encode_tablemap_func(&tablemap_func)?,
+        tablemap_offset: encode_tablemap_offset(&tablemap_offset, &funcs)?,
+    })
+}
+
+// TODO: テスト書く
+pub fn calc_tablemap(funcs: &Vec<Function>) -> (Vec<u32>, Vec<Vec<u32>>) {
+    let mut tablemap_func: Vec<u32> = vec![];
+    let mut tablemap_offset: Vec<Vec<u32>> = vec![];
+
+    let mut tablemap_func_addr = 0;
+    let mut tablemap_offset_addr = 0;
+
+    for func in funcs {
+        match func {
+            Function::ImportFunction(_) => {
+                tablemap_func.push(0);
+            }
+            Function::BytecodeFunction(f) => {
+                tablemap_func.push(tablemap_func_addr);
+                tablemap_func_addr += calc_tablefunc(&f);
+
+                let (v, a) = calc_tableoffset(&f, tablemap_offset_addr);
+                tablemap_offset.push(v);
+                tablemap_offset_addr = a;
+            }
+        }
+    }
+    return (tablemap_func, tablemap_offset);
+}
+
+fn calc_tablefunc(func: &BytecodeFunction) -> u32 {
+    // "tablemap_offset format"
+    // 関数fについて
+    //  - fのローカルの長さ(u32)
+    //  - fのローカル (local.len * u8)
+    //  - 各コード位置について
+    //      - offset  (u32)
+    //      - address (u64)
+    let local_len = func.locals.len() as u32;
+    let codes_len = func.codes.len() as u32;
+    log::debug!("local: {}, codes: {}", local_len, codes_len);
+    return BYTE_U32 + (local_len * BYTE_U8) + (codes_len * (BYTE_U32 + BYTE_U64));
+}
+
+fn calc_tableoffset(func: &BytecodeFunction, base_addr: u32) -> (Vec<u32>, u32) {
+    let last = func.codes.last().expect("codes last");
+    let mut offset_to_codepos: Vec<u32> = vec![0; last.offset as usize + 1];
+    let mut addr = base_addr as u32;
+
+    for codepos in &func.codes {
+        offset_to_codepos[codepos.offset as usize] = addr;
+
+        // "type_stack_table format"
+        // 各コード位置について
+        //  - 型スタックの長さ (u32)
+        //  - 型スタックの中身 (stack.len * u8)
+        // 但し、OpcodeがCallの場合は、「呼び出し途中」と「呼び出し後」の2パターン書く
+        let opcode = &codepos.opcode;
+        let len  = codepos.type_stack.len() as u32;
+        if let Operator::Call{..} = opcode {
+            addr += BYTE_U32 + (len - codepos.callee_return_size) * BYTE_U8;
+        }
+        addr += BYTE_U32 + len * BYTE_U8;
+    }
+    return (offset_to_codepos, addr);
+}
+
+pub fn encode_type_stack_table(funcs: &Vec<Function>) -> Result<Vec<u8>> {
+    let mut f: Vec<u8> = vec![];
+
+    for function in funcs {
+        match function {
+            Function::ImportFunction(_) => {
+            }
+            Function::BytecodeFunction(func) => {
+                for codepos in &func.codes {
+                    io::write_u32(&mut f, codepos.type_stack.len() as u32)?;
+                    io::write_u8s(&mut f, &codepos.type_stack)?;
+
+                    if let Operator::Call{..} = codepos.opcode {
+                        let size = codepos.type_stack.len() - codepos.callee_return_size as usize;
+                        io::write_u32(&mut f, size as u32)?;
+                        io::write_u8s(&mut f, &codepos.type_stack[..size])?;
+                    }
+                }
+            }
+        }
+    }
+
+    return Ok(f);
+}
+
+pub fn encode_tablemap_func(tablemap_func: &Vec<u32>) -> Result<Vec<u8>> {
+    let mut f: Vec<u8> = vec![];
+
+    let mut fidx = 0;
+    for addr in tablemap_func {
+        io::write_u32(&mut f, fidx)?;
+        io::write_u64(&mut f, *addr as u64)?;
+        fidx += 1;
+    }
+
+    return Ok(f);
+}
+
+// "tablemap_offset format"
+// 関数fについて
+//  - fのローカルの長さ(u32)
+//  - fのローカル (local.len * u8)
+//  - 各コード位置について
+//      - offset  (u32)
+//      - address (u64)
+pub fn encode_tablemap_offset(tablemap_offset: &Vec<Vec<u32>>, funcs: &Vec<Function>) -> Result<Vec<u8>> {
+    let mut f: Vec<u8> = vec![];
+
+    let mut fidx = 0;
+    for function in funcs {
+        match function {
+            Function::ImportFunction(_) => {
+
+            }
+            Function::BytecodeFunction(func) => {
+                let locals = &func.locals;
+                io::write_u32(&mut f, locals.len() as u32)?;
+                io::write_u8s(&mut f, &locals)?;
+                for c in &func.codes {
+                    io::write_u32(&mut f, c.offset)?;
+                    io::write_u64(&mut f, tablemap_offset[fidx][c.offset as usize] as u64)?;
+                }
+                fidx += 1;
+            }
+        }
+    }
+    return Ok(f);
+}
+
+mod io {
+    use std::io::{Write, Error};
+    use byteorder::{LittleEndian, ByteOrder};
+
+    // プリミティブ
+    pub fn write_u8(w: &mut impl Write, n: u8) -> Result<(), Error> {
+        let buf = [n; 1];
+        return w.write_all(&buf);
+    }
+
+    pub fn write_u32(w: &mut impl Write, n: u32) -> Result<(), Error> {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, n);
+        return w.write_all(&buf);
+    }
+
+    pub fn write_u64(w: &mut impl Write, n: u64) -> Result<(), Error> {
+        let mut buf = [0; 8];
+        LittleEndian::write_u64(&mut buf, n);
+        return w.write_all(&buf);
+    }
+
+    // 配列
+    pub fn write_u8s(w: &mut impl Write, v: &[u8]) -> Result<(), Error> {
+        for vi in v {
+            write_u8(w, *vi)?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tablemap_func_has_entry_per_function() -> Result<()> {
+        let buf = wat::parse_str(r#"
+            (module
+              (import "env" "f" (func))
+              (func (result i32) i32.const 1))
+        "#)?;
+        let tables = create_type_tables(&buf)?;
+        // (fidx: u32, addr: u64) が関数ごとに並ぶ
+        assert_eq!(tables.tablemap_func.len(), 2 * (4 + 8));
+        assert_eq!(&tables.tablemap_func[12..16], &1u32.to_le_bytes());
+        assert!(!tables.type_table.is_empty());
+        Ok(())
+    }
+}
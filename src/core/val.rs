@@ -1,9 +1,9 @@
-use wasmparser::ValType;
+use wasmparser::{AbstractHeapType, HeapType, RefType, ValType};
 use strum_macros::EnumString;
 use serde::{Deserialize, Serialize};
 
 #[repr(u8)]
-#[derive(Clone, Copy, EnumString, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumString, Serialize, Deserialize)]
 pub enum WasmType {
     Any = 0,
     U8 = 1,
@@ -12,7 +12,8 @@ pub enum WasmType {
     I64,
     F64,
     V128,
-    Ref,
+    FuncRef,
+    ExternRef,
 }
 
 impl WasmType {
@@ -25,10 +26,27 @@ impl WasmType {
             WasmType::I64 => return "I64",
             WasmType::F64 => return "F64",
             WasmType::V128 => return "V128",
-            WasmType::Ref => return "Ref",
+            WasmType::FuncRef => return "FuncRef",
+            WasmType::ExternRef => return "ExternRef",
         }
     }
     
+    pub fn from_u8(n: u8) -> Option<WasmType> {
+        let t = match n {
+            0 => WasmType::Any,
+            1 => WasmType::U8,
+            2 => WasmType::I32,
+            3 => WasmType::F32,
+            4 => WasmType::I64,
+            5 => WasmType::F64,
+            6 => WasmType::V128,
+            7 => WasmType::FuncRef,
+            8 => WasmType::ExternRef,
+            _ => return None,
+        };
+        return Some(t);
+    }
+
     pub fn size(&self) -> u8 {
         match self {
             WasmType::Any => return 0,
@@ -36,11 +54,19 @@ impl WasmType {
             WasmType::I32 | WasmType::F32 => return 4,
             WasmType::I64 | WasmType::F64 => return 8,
             WasmType::V128 => return 16,
-            WasmType::Ref => return 255,
+            // 参照はWAMRの1セル(4byte)に格納される
+            WasmType::FuncRef | WasmType::ExternRef => return 4,
         }
     }
 }
 
+impl WasmType {
+    /// 値が占める32bitセル (WAMRのスロット) の数
+    pub fn cell_num(&self) -> usize {
+        return (self.size() as usize).div_ceil(4);
+    }
+}
+
 pub fn valtype_to_wasmtype(valtype: &ValType) -> WasmType {
     match valtype {
         ValType::I32 => return WasmType::I32,
@@ -48,7 +74,21 @@ pub fn valtype_to_wasmtype(valtype: &ValType) -> WasmType {
         ValType::F32 => return WasmType::F32,
         ValType::F64 => return WasmType::F64,
         ValType::V128 => return WasmType::V128,
-        ValType::Ref(..) => return WasmType::Ref,
+        ValType::Ref(reftype) => return reftype_to_wasmtype(reftype),
+    }
+}
+
+pub fn reftype_to_wasmtype(reftype: &RefType) -> WasmType {
+    return heaptype_to_wasmtype(&reftype.heap_type());
+}
+
+pub fn heaptype_to_wasmtype(heaptype: &HeapType) -> WasmType {
+    match heaptype {
+        // 型付き関数参照もfuncrefとして扱う
+        HeapType::Concrete(_) => return WasmType::FuncRef,
+        HeapType::Abstract { ty: AbstractHeapType::Func | AbstractHeapType::NoFunc, .. } => return WasmType::FuncRef,
+        // それ以外の参照はホスト側の不透明な値としてexternrefと同じ扱いにする
+        HeapType::Abstract { .. } => return WasmType::ExternRef,
     }
 }
 
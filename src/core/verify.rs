@@ -0,0 +1,274 @@
+use std::fmt;
+
+use anyhow::Result;
+use wasmparser::{FuncValidator, FuncValidatorAllocations, FunctionBody, Parser, ValType, ValidPayload, Validator, ValidatorResources, WasmFeatures};
+
+use crate::core::function::{self as function_v1, valtype_to_size};
+use crate::core::function_v2::{self, is_call_site};
+use crate::core::module;
+use crate::core::val::valtype_to_wasmtype;
+
+pub enum MismatchKind {
+    /// The operand stack heights differ.
+    Height { expected: usize, actual: usize },
+    /// The value at `slot` (counted from the bottom of the stack) has a different type.
+    Type { slot: usize, expected: String, actual: String },
+}
+
+/// A code position where the stack table disagrees with the validator.
+pub struct Mismatch {
+    pub fidx: u32,
+    pub offset: u32,
+    pub op: String,
+    /// Whether the call-site entry (recorded at `offset + 1`) was checked
+    /// rather than the state after the operator.
+    pub call_site: bool,
+    pub kind: MismatchKind,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let at = if self.call_site { " (call site)" } else { "" };
+        write!(f, "fidx {}, offset {}{}, {}: ", self.fidx, self.offset, at, self.op)?;
+        match &self.kind {
+            MismatchKind::Height { expected, actual } => {
+                write!(f, "stack height is {}, expected {}", actual, expected)
+            }
+            MismatchKind::Type { slot, expected, actual } => {
+                write!(f, "stack[{}] is {}, expected {}", slot, actual, expected)
+            }
+        }
+    }
+}
+
+/// Runs the validator in lockstep with `BytecodeFunction::create_stack_table`
+/// and returns every position where the stack tables diverge from the
+/// validator's operand stack.
+pub fn verify_stack_tables(buf: &[u8]) -> Result<Vec<Mismatch>> {
+    let buf = buf.to_vec();
+    let m = module::new_module(&buf)?;
+    let funcs = m.new_function_v2()?;
+
+    let mut mismatches = vec![];
+    for_each_function(&buf, |fidx, body, validator| {
+        let f = match &funcs[fidx as usize] {
+            function_v2::Function::BytecodeFunction(f) => f,
+            function_v2::Function::ImportFunction(_) => anyhow::bail!("Function {} is not a bytecode function", fidx),
+        };
+        let table = f.create_stack_table(false)?;
+        let mut entries = table.iter();
+
+        let mut reader = body.get_binary_reader();
+        validator.read_locals(&mut reader)?;
+        let base_offset = reader.original_position();
+        for (offset, op) in f.operators()? {
+            let pos = base_offset + offset as usize;
+
+            if is_call_site(&op) {
+                // 呼び出し中は引数をpopした状態になっている
+                let entry = entries.next().ok_or_else(|| anyhow::anyhow!("Stack table of function {} ends before offset {}", fidx, offset))?;
+                let params = call_param_count(&m, &op);
+                let mut expected = validator_stack(validator);
+                expected.truncate(expected.len().saturating_sub(params));
+                let actual = entry.stack.inner.iter().map(|(_, t)| t.to_string().to_owned()).collect();
+                compare(&mut mismatches, fidx, offset, &op, true, &expected, actual);
+            }
+
+            validator.op(pos, &op)?;
+
+            let entry = entries.next().ok_or_else(|| anyhow::anyhow!("Stack table of function {} ends before offset {}", fidx, offset))?;
+            let expected = validator_stack(validator);
+            let actual = entry.stack.inner.iter().map(|(_, t)| t.to_string().to_owned()).collect();
+            compare(&mut mismatches, fidx, offset, &op, false, &expected, actual);
+        }
+        validator.finish(body.range().end)?;
+        Ok(())
+    })?;
+
+    Ok(mismatches)
+}
+
+/// Same as `verify_stack_tables`, but checks the v1 type stacks built by
+/// `function::BytecodeFunction::construct`, which only record value sizes.
+pub fn verify_stack_tables_v1(buf: &[u8]) -> Result<Vec<Mismatch>> {
+    let buf = buf.to_vec();
+    let m = module::new_module(&buf)?;
+    let funcs = m.parse()?;
+
+    let mut mismatches = vec![];
+    for_each_function(&buf, |fidx, body, validator| {
+        let f = match &funcs[fidx as usize] {
+            function_v1::Function::BytecodeFunction(f) => f,
+            function_v1::Function::ImportFunction(_) => anyhow::bail!("Function {} is not a bytecode function", fidx),
+        };
+        // codesの先頭はoffset=0用のダミー
+        let mut codes = f.codes.iter().skip(1);
+
+        let mut reader = body.get_binary_reader();
+        validator.read_locals(&mut reader)?;
+        let base_offset = reader.original_position();
+        while !reader.eof() {
+            let pos = reader.original_position();
+            let offset = (pos - base_offset) as u32;
+            let op = reader.read_operator()?;
+            validator.op(pos, &op)?;
+
+            let code = codes.next().ok_or_else(|| anyhow::anyhow!("Type stack of function {} ends before offset {}", fidx, offset))?;
+            let expected = validator_stack(validator)
+                .into_iter()
+                .map(|t| t.map(|t| valtype_to_size(&t).to_string()))
+                .collect::<Vec<_>>();
+            let actual = code.type_stack.iter().map(|size| size.to_string()).collect();
+            compare_strings(&mut mismatches, fidx, offset, &op, false, &expected, actual);
+        }
+        validator.finish(reader.original_position())?;
+        Ok(())
+    })?;
+
+    Ok(mismatches)
+}
+
+/// Validates the whole module and calls `f` with a fresh validator for each function body.
+fn for_each_function<F>(buf: &[u8], mut f: F) -> Result<()>
+where
+    F: FnMut(u32, &FunctionBody, &mut FuncValidator<ValidatorResources>) -> Result<()>,
+{
+    let mut validator = Validator::new_with_features(WasmFeatures::all());
+    let mut allocs = FuncValidatorAllocations::default();
+    for payload in Parser::new(0).parse_all(buf) {
+        if let ValidPayload::Func(func, body) = validator.payload(&payload?)? {
+            let fidx = func.index;
+            let mut func_validator = func.into_validator(allocs);
+            f(fidx, &body, &mut func_validator)?;
+            allocs = func_validator.into_allocations();
+        }
+    }
+    Ok(())
+}
+
+/// Returns the validator's operand stack from bottom to top.
+/// `None` is a value of unknown type (in unreachable code).
+fn validator_stack(validator: &FuncValidator<ValidatorResources>) -> Vec<Option<ValType>> {
+    let height = validator.operand_stack_height() as usize;
+    (0..height)
+        .rev()
+        .map(|depth| validator.get_operand_type(depth).flatten())
+        .collect()
+}
+
+fn call_param_count(m: &module::Module, op: &wasmparser::Operator) -> usize {
+    match op {
+        wasmparser::Operator::Call { function_index } |
+        wasmparser::Operator::ReturnCall { function_index } => m.get_type_by_func(*function_index).params().len(),
+        // 呼び出し先のテーブルインデックスも積まれている
+        wasmparser::Operator::CallIndirect { type_index, .. } |
+        wasmparser::Operator::ReturnCallIndirect { type_index, .. } => m.get_type_by_type(*type_index).params().len() + 1,
+        _ => 0,
+    }
+}
+
+fn compare(
+    mismatches: &mut Vec<Mismatch>,
+    fidx: u32,
+    offset: u32,
+    op: &wasmparser::Operator,
+    call_site: bool,
+    expected: &[Option<ValType>],
+    actual: Vec<String>,
+) {
+    let expected = expected
+        .iter()
+        .map(|t| t.map(|t| valtype_to_wasmtype(&t).to_string().to_string()))
+        .collect::<Vec<_>>();
+    compare_strings(mismatches, fidx, offset, op, call_site, &expected, actual);
+}
+
+fn compare_strings(
+    mismatches: &mut Vec<Mismatch>,
+    fidx: u32,
+    offset: u32,
+    op: &wasmparser::Operator,
+    call_site: bool,
+    expected: &[Option<String>],
+    actual: Vec<String>,
+) {
+    let mut push = |kind| {
+        mismatches.push(Mismatch { fidx, offset, op: format!("{:?}", op), call_site, kind });
+    };
+
+    if expected.len() != actual.len() {
+        push(MismatchKind::Height { expected: expected.len(), actual: actual.len() });
+        return;
+    }
+    for (slot, (e, a)) in expected.iter().zip(actual).enumerate() {
+        // 到達不能コードでは型が決まらない値がある
+        if let Some(e) = e {
+            if *e != a {
+                push(MismatchKind::Type { slot, expected: e.clone(), actual: a });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_matches_validator() -> Result<()> {
+        let buf = wat::parse_str(r#"
+            (module
+              (import "env" "g" (global i64))
+              (memory 1)
+              (func $callee (param i32 f64) (result f32)
+                f32.const 1)
+              (func (param i32) (result i32)
+                (local f32)
+                global.get 0
+                i32.wrap_i64
+                f64.const 2
+                call $callee
+                local.set 1
+                local.get 0
+                i32.load
+                local.get 0
+                i64.const 1
+                f32.convert_i64_s
+                local.get 1
+                f32.lt
+                select
+                block (result i32)
+                  i32.const 1
+                  i32.const 0
+                  br_if 0
+                  drop
+                  i32.const 1
+                end
+                i32.add))
+        "#)?;
+        let mismatches = verify_stack_tables(&buf)?;
+        let reports = mismatches.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+        assert!(reports.is_empty(), "{:#?}", reports);
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_v1_reports_cleared_stack_at_br() -> Result<()> {
+        let buf = wat::parse_str(r#"
+            (module
+              (func (result i64)
+                i64.const 0
+                block
+                  br 0
+                end))
+        "#)?;
+        let mismatches = verify_stack_tables_v1(&buf)?;
+        // br 0 (offset 4) 以降で i64 が失われている
+        let offsets = mismatches.iter().map(|m| m.offset).collect::<Vec<_>>();
+        assert_eq!(offsets, vec![4, 6, 7]);
+        assert!(matches!(mismatches[0].kind, MismatchKind::Height { expected: 1, actual: 0 }));
+
+        assert!(verify_stack_tables(&buf)?.is_empty());
+        Ok(())
+    }
+}
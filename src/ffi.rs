@@ -0,0 +1,410 @@
+//! ランタイム (WAMR) から型スタックテーブルを引くためのC ABI
+//!
+//! ヘッダは `include/wacret.h` (`--features header` のときbuild.rsでcbindgenにより再生成).
+//! 失敗した関数は NULL か負の値を返し、理由は `wacret_last_error` で取得できる.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+use anyhow::Result;
+
+use crate::core::stack_table::{self, StackTables};
+use crate::core::stack_table_bin::{self, StackTableView};
+use crate::core::val::WasmType;
+
+/// 読み込んだスタックテーブル (msgpack形式またはバイナリ形式)
+pub struct WacretStackTable {
+    inner: Loaded,
+}
+
+enum Loaded {
+    Msgpack(stack_table::Header, StackTables),
+    Binary(Vec<u8>),
+}
+
+/// スタックの1要素. `kind` は `WACRET_OP_*`, `value` は kind ごとの即値
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WacretStackEntry {
+    pub kind: u8,
+    /// WasmType
+    pub ty: u8,
+    pub value: u64,
+}
+
+/// ラベルスタックの1要素. アドレスは関数本体の先頭から、スタックポインタと値の数はセル単位
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WacretLabel {
+    pub begin: u32,
+    pub target: u32,
+    pub stack_pointer: u32,
+    pub cell_num: u32,
+}
+
+pub const WACRET_OP_OTHER: u8 = 0;
+pub const WACRET_OP_LOCAL_GET: u8 = 1;
+pub const WACRET_OP_I32_CONST: u8 = 2;
+pub const WACRET_OP_F32_CONST: u8 = 3;
+pub const WACRET_OP_I64_CONST: u8 = 4;
+pub const WACRET_OP_F64_CONST: u8 = 5;
+pub const WACRET_OP_CALL: u8 = 6;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(err: anyhow::Error) {
+    let msg = CString::new(err.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+/// `Result` をC側の戻り値に変換する. 失敗時は `on_error` を返す
+fn ffi_result<T>(result: Result<T>, on_error: T) -> T {
+    result.unwrap_or_else(|err| {
+        set_last_error(err);
+        on_error
+    })
+}
+
+impl WacretStackTable {
+    fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        let inner = if data.starts_with(&stack_table_bin::MAGIC) {
+            // 先にヘッダだけ検査しておく
+            StackTableView::new(&data)?;
+            Loaded::Binary(data)
+        } else {
+            let (header, tables) = StackTables::deserialize(&data)?;
+            Loaded::Msgpack(header, tables)
+        };
+        Ok(Self { inner })
+    }
+
+    fn func_count(&self) -> Result<u32> {
+        match &self.inner {
+            Loaded::Msgpack(_, tables) => Ok(tables.0.len() as u32),
+            Loaded::Binary(data) => Ok(StackTableView::new(data)?.func_count()),
+        }
+    }
+
+    fn check_wasm(&self, wasm: &[u8]) -> Result<()> {
+        match &self.inner {
+            Loaded::Msgpack(header, _) => header.check_wasm(wasm),
+            Loaded::Binary(data) => {
+                let view = StackTableView::new(data)?;
+                let expected = stack_table::Header::new(wasm, view.before_execution());
+                if view.wasm_sha256() != &expected.wasm_sha256[..] {
+                    anyhow::bail!("Stack table was generated from a different wasm module");
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn locals(&self, fidx: u32) -> Result<Vec<WasmType>> {
+        match &self.inner {
+            Loaded::Msgpack(_, tables) => {
+                check_fidx(tables, fidx)?;
+                Ok(tables.get_locals(fidx as usize)?.clone())
+            }
+            Loaded::Binary(data) => StackTableView::new(data)?.get_locals(fidx),
+        }
+    }
+
+    fn stack(&self, fidx: u32, offset: u32) -> Result<Vec<WacretStackEntry>> {
+        let entries = match &self.inner {
+            Loaded::Msgpack(_, tables) => {
+                check_fidx(tables, fidx)?;
+                tables.get_stack(fidx as usize, offset)?.clone()
+            }
+            Loaded::Binary(data) => StackTableView::new(data)?
+                .get_stack(fidx, offset)?
+                .iter()
+                .collect::<Result<Vec<_>>>()?,
+        };
+        Ok(entries
+            .iter()
+            .map(|(op, ty)| {
+                let (kind, value) = stack_table_bin::raw_entry(op);
+                WacretStackEntry { kind, ty: *ty as u8, value }
+            })
+            .collect())
+    }
+
+    fn labels(&self, fidx: u32, offset: u32) -> Result<Vec<WacretLabel>> {
+        let labels = match &self.inner {
+            Loaded::Msgpack(_, tables) => {
+                check_fidx(tables, fidx)?;
+                tables.get_labels(fidx as usize, offset)?.clone()
+            }
+            Loaded::Binary(data) => StackTableView::new(data)?.get_labels(fidx, offset)?,
+        };
+        Ok(labels
+            .iter()
+            .map(|l| WacretLabel { begin: l.begin, target: l.target, stack_pointer: l.stack_pointer, cell_num: l.cell_num })
+            .collect())
+    }
+
+    fn live(&self, fidx: u32, offset: u32) -> Result<Vec<u8>> {
+        let mask = match &self.inner {
+            Loaded::Msgpack(_, tables) => {
+                check_fidx(tables, fidx)?;
+                tables.get_live(fidx as usize, offset)?.clone()
+            }
+            Loaded::Binary(data) => StackTableView::new(data)?.get_live(fidx, offset)?,
+        };
+        Ok(mask.locals.iter().chain(&mask.stack).map(|live| *live as u8).collect())
+    }
+}
+
+fn check_fidx(tables: &StackTables, fidx: u32) -> Result<()> {
+    if fidx as usize >= tables.0.len() {
+        anyhow::bail!("Function {} is out of range ({} functions)", fidx, tables.0.len());
+    }
+    Ok(())
+}
+
+/// `src` を `dst` (容量 `cap`) に書き込み、全体の要素数を返す.
+/// 戻り値が `cap` より大きければ先頭 `cap` 個だけ書き込まれている
+unsafe fn copy_out<T: Copy>(src: &[T], dst: *mut T, cap: usize) -> Result<i64> {
+    if !dst.is_null() {
+        ptr::copy_nonoverlapping(src.as_ptr(), dst, src.len().min(cap));
+    } else if cap != 0 {
+        anyhow::bail!("Output buffer is NULL");
+    }
+    Ok(src.len() as i64)
+}
+
+/// msgpack形式 (`create --v2`) またはバイナリ形式 (`create --v2 --binary`) の
+/// スタックテーブルを読み込む. `data` はコピーされる. 失敗時は NULL
+///
+/// # Safety
+/// `data` は `len` バイト読める領域を指すこと
+#[no_mangle]
+pub unsafe extern "C" fn wacret_stack_table_load(data: *const u8, len: usize) -> *mut WacretStackTable {
+    if data.is_null() {
+        set_last_error(anyhow::anyhow!("data is NULL"));
+        return ptr::null_mut();
+    }
+    let data = std::slice::from_raw_parts(data, len).to_vec();
+    let table = ffi_result(WacretStackTable::from_bytes(data).map(Some), None);
+    table.map_or(ptr::null_mut(), |t| Box::into_raw(Box::new(t)))
+}
+
+/// ファイルからスタックテーブルを読み込む. 失敗時は NULL
+///
+/// # Safety
+/// `path` はNUL終端された文字列であること
+#[no_mangle]
+pub unsafe extern "C" fn wacret_stack_table_open(path: *const c_char) -> *mut WacretStackTable {
+    if path.is_null() {
+        set_last_error(anyhow::anyhow!("path is NULL"));
+        return ptr::null_mut();
+    }
+    let path = CStr::from_ptr(path).to_string_lossy().into_owned();
+    let result = std::fs::read(&path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path, e))
+        .and_then(WacretStackTable::from_bytes);
+    ffi_result(result.map(Some), None).map_or(ptr::null_mut(), |t| Box::into_raw(Box::new(t)))
+}
+
+/// # Safety
+/// `table` は `wacret_stack_table_load/open` が返したポインタか NULL であること
+#[no_mangle]
+pub unsafe extern "C" fn wacret_stack_table_free(table: *mut WacretStackTable) {
+    if !table.is_null() {
+        drop(Box::from_raw(table));
+    }
+}
+
+/// 関数の数 (importを含む). 失敗時は -1
+///
+/// # Safety
+/// `table` は有効なスタックテーブルであること
+#[no_mangle]
+pub unsafe extern "C" fn wacret_stack_table_func_count(table: *const WacretStackTable) -> i64 {
+    let Some(table) = table.as_ref() else {
+        set_last_error(anyhow::anyhow!("table is NULL"));
+        return -1;
+    };
+    ffi_result(table.func_count().map(|n| n as i64), -1)
+}
+
+/// テーブルが `wasm` から生成されたものなら 0, そうでなければ -1
+///
+/// # Safety
+/// `table` は有効なスタックテーブル, `wasm` は `len` バイト読める領域であること
+#[no_mangle]
+pub unsafe extern "C" fn wacret_stack_table_check_wasm(table: *const WacretStackTable, wasm: *const u8, len: usize) -> i32 {
+    let (Some(table), false) = (table.as_ref(), wasm.is_null()) else {
+        set_last_error(anyhow::anyhow!("table or wasm is NULL"));
+        return -1;
+    };
+    let wasm = std::slice::from_raw_parts(wasm, len);
+    ffi_result(table.check_wasm(wasm).map(|_| 0), -1)
+}
+
+/// 関数 `fidx` のローカル (引数を含む) の型を `types` に書き込み、ローカルの数を返す.
+/// 失敗時は -1
+///
+/// # Safety
+/// `table` は有効なスタックテーブル, `types` は `cap` 要素書き込める領域か NULL (cap = 0) であること
+#[no_mangle]
+pub unsafe extern "C" fn wacret_get_locals(table: *const WacretStackTable, fidx: u32, types: *mut u8, cap: usize) -> i64 {
+    let Some(table) = table.as_ref() else {
+        set_last_error(anyhow::anyhow!("table is NULL"));
+        return -1;
+    };
+    let result = table.locals(fidx).and_then(|locals| {
+        let raw = locals.iter().map(|t| *t as u8).collect::<Vec<_>>();
+        copy_out(&raw, types, cap)
+    });
+    ffi_result(result, -1)
+}
+
+/// 関数 `fidx` のコードオフセット `offset` におけるオペランドスタック (底から) を
+/// `entries` に書き込み、要素数を返す. 失敗時は -1
+///
+/// # Safety
+/// `table` は有効なスタックテーブル, `entries` は `cap` 要素書き込める領域か NULL (cap = 0) であること
+#[no_mangle]
+pub unsafe extern "C" fn wacret_get_stack(
+    table: *const WacretStackTable,
+    fidx: u32,
+    offset: u32,
+    entries: *mut WacretStackEntry,
+    cap: usize,
+) -> i64 {
+    let Some(table) = table.as_ref() else {
+        set_last_error(anyhow::anyhow!("table is NULL"));
+        return -1;
+    };
+    let result = table.stack(fidx, offset).and_then(|stack| copy_out(&stack, entries, cap));
+    ffi_result(result, -1)
+}
+
+/// 関数 `fidx` のコードオフセット `offset` におけるラベルスタック (関数フレームから) を
+/// `labels` に書き込み、要素数を返す. 失敗時は -1
+///
+/// # Safety
+/// `table` は有効なスタックテーブル, `labels` は `cap` 要素書き込める領域か NULL (cap = 0) であること
+#[no_mangle]
+pub unsafe extern "C" fn wacret_get_labels(
+    table: *const WacretStackTable,
+    fidx: u32,
+    offset: u32,
+    labels: *mut WacretLabel,
+    cap: usize,
+) -> i64 {
+    let Some(table) = table.as_ref() else {
+        set_last_error(anyhow::anyhow!("table is NULL"));
+        return -1;
+    };
+    let result = table.labels(fidx, offset).and_then(|l| copy_out(&l, labels, cap));
+    ffi_result(result, -1)
+}
+
+/// 関数 `fidx` のコードオフセット `offset` で各ローカル、各スタックの値 (底から) が生きていれば 1,
+/// 死んでいれば 0 を、ローカル、スタックの順に `live` に書き込み、要素数を返す.
+/// 死んでいる値は保存せず、リストア時は0で埋めてよい. 失敗時は -1
+///
+/// # Safety
+/// `table` は有効なスタックテーブル, `live` は `cap` 要素書き込める領域か NULL (cap = 0) であること
+#[no_mangle]
+pub unsafe extern "C" fn wacret_get_live(table: *const WacretStackTable, fidx: u32, offset: u32, live: *mut u8, cap: usize) -> i64 {
+    let Some(table) = table.as_ref() else {
+        set_last_error(anyhow::anyhow!("table is NULL"));
+        return -1;
+    };
+    let result = table.live(fidx, offset).and_then(|mask| copy_out(&mask, live, cap));
+    ffi_result(result, -1)
+}
+
+/// 呼び出したスレッドで最後に起きたエラーのメッセージ. なければ NULL.
+/// 次にエラーが起きるまで有効
+#[no_mangle]
+pub extern "C" fn wacret_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |msg| msg.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(data: &[u8]) -> *mut WacretStackTable {
+        unsafe { wacret_stack_table_load(data.as_ptr(), data.len()) }
+    }
+
+    #[test]
+    fn test_msgpack_and_binary_answer_the_same() -> Result<()> {
+        let wasm = wat::parse_str(r#"
+            (module
+              (func $callee (param i32) (result i64)
+                i64.const -1)
+              (func (param f32) (result i64)
+                (local i32)
+                local.get 0
+                i32.const 5
+                call $callee
+                i64.const 2
+                i64.add
+                drop
+                drop
+                i64.const 0))
+        "#)?;
+        let tables = StackTables::from_wasm(&wasm, false)?;
+        let header = stack_table::Header::new(&wasm, false);
+        let msgpack = load(&tables.serialize(&header));
+        let binary = load(&stack_table_bin::encode(&tables, &header));
+
+        for table in [msgpack, binary] {
+            assert!(!table.is_null());
+            unsafe {
+                assert_eq!(wacret_stack_table_func_count(table), 2);
+                assert_eq!(wacret_stack_table_check_wasm(table, wasm.as_ptr(), wasm.len()), 0);
+
+                let mut types = [0u8; 4];
+                assert_eq!(wacret_get_locals(table, 1, types.as_mut_ptr(), types.len()), 2);
+                assert_eq!(&types[..2], &[WasmType::F32 as u8, WasmType::I32 as u8]);
+
+                // call $callee (offset 4) の呼び出し中
+                let mut entries = [WacretStackEntry::default(); 4];
+                assert_eq!(wacret_get_stack(table, 1, 5, entries.as_mut_ptr(), entries.len()), 1);
+                assert_eq!(entries[0], WacretStackEntry { kind: WACRET_OP_LOCAL_GET, ty: WasmType::F32 as u8, value: 0 });
+
+                // 容量が足りなければ必要な数だけ返す
+                assert_eq!(wacret_get_stack(table, 1, 4, ptr::null_mut(), 0), 2);
+
+                let mut labels = [WacretLabel::default(); 4];
+                assert_eq!(wacret_get_labels(table, 1, 5, labels.as_mut_ptr(), labels.len()), 1);
+                let expected = &tables.get_labels(1, 5)?[0];
+                assert_eq!(labels[0], WacretLabel {
+                    begin: expected.begin,
+                    target: expected.target,
+                    stack_pointer: expected.stack_pointer,
+                    cell_num: expected.cell_num,
+                });
+                assert_eq!(wacret_get_labels(table, 1, 1000, labels.as_mut_ptr(), labels.len()), -1);
+
+                // ローカル2つとスタック1つ
+                let mut live = [0u8; 4];
+                assert_eq!(wacret_get_live(table, 1, 5, live.as_mut_ptr(), live.len()), 3);
+                let expected = tables.get_live(1, 5)?;
+                let expected = expected.locals.iter().chain(&expected.stack).map(|l| *l as u8).collect::<Vec<_>>();
+                assert_eq!(&live[..3], &expected[..]);
+                assert_eq!(wacret_get_live(table, 1, 1000, live.as_mut_ptr(), live.len()), -1);
+
+                assert_eq!(wacret_get_stack(table, 1, 1000, entries.as_mut_ptr(), entries.len()), -1);
+                let err = CStr::from_ptr(wacret_last_error()).to_str()?;
+                assert!(err.contains("offset 1000"), "{}", err);
+                assert_eq!(wacret_get_locals(table, 2, types.as_mut_ptr(), types.len()), -1);
+
+                wacret_stack_table_free(table);
+            }
+        }
+
+        assert!(load(b"not a stack table").is_null());
+        Ok(())
+    }
+}
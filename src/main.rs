@@ -1,9 +1,12 @@
 // mod cli;
-mod core;
+use wacret::core;
 mod command;
 mod compile;
 
-use command::{create_table, create_table_v2, view, insert};
+use command::{create_table, create_table_v2, view, insert, verify, restore_prep, translate, display, stats, remap, instrument, transform, check_snapshot, diff};
+use compile::compile::InterpMode;
+use crate::core::safepoint::SafepointPolicy;
+use command::output::Output;
 
 use env_logger;
 // use log::{debug, error, log_enabled, info, Level};
@@ -27,9 +30,16 @@ struct Cli {
 enum SubCommands {
     /// Create type stack tables for checkpointing a wasm app.
     Create(CreateArgs),
-    /// Display stack tables in human-readable format
+    /// Display each instruction with its operand stacks in human-readable format
     Display {
+        /// Path to input WASM file
         path: Utf8PathBuf,
+        /// Output format
+        #[arg(short, long, value_enum, default_value = "csv")]
+        format: display::Format,
+        /// Output file or directory. Defaults to stdout
+        #[arg(short, long)]
+        output: Option<Utf8PathBuf>,
     },
     /// View protobuf files in JSON format
     View {
@@ -43,6 +53,12 @@ enum SubCommands {
         /// Merge locals and value stack into a single value_stack (for protobuf only)
         #[arg(long)]
         merged_stack: bool,
+        /// Show a summary of an InstanceState file (memory, globals, tables)
+        #[arg(long, requires = "wasm")]
+        instance: bool,
+        /// WASM module the state was taken on, used to name and type globals (with --instance)
+        #[arg(long)]
+        wasm: Option<Utf8PathBuf>,
     },
     /// Insert a NOP instruction at a specific offset within a specific function
     Insert {
@@ -54,9 +70,120 @@ enum SubCommands {
         output: Utf8PathBuf,
         /// Function index (0-based)
         function_index: u32,
-        /// Offset within the function where to insert NOP
+        /// Byte offset of the instruction to insert NOP before, as in the stack tables
         offset: u32,
-    }
+        /// Where to write the old→new offset map. Defaults to `<output>.offsets.json`
+        #[arg(long)]
+        map: Option<Utf8PathBuf>,
+    },
+    /// Check the generated stack tables against the wasm validator's operand stack
+    Verify {
+        /// Path to input WASM file
+        path: Utf8PathBuf,
+        /// Check the v1 type stacks instead
+        #[arg(short = '1', long = "v1")]
+        v1: bool,
+    },
+    /// Check the label stacks of a protobuf snapshot against the stack tables
+    VerifyLabels {
+        /// Path to the wasm module the snapshot was taken from
+        wasm: Utf8PathBuf,
+        /// Path to the stack tables created with `create --v2`
+        table: Utf8PathBuf,
+        /// Path to the CallStack snapshot
+        snapshot: Utf8PathBuf,
+    },
+    /// Check that every frame of a protobuf snapshot fits a wasm module
+    CheckSnapshot {
+        /// Path to the wasm module the snapshot is restored into
+        wasm: Utf8PathBuf,
+        /// Path to the CallStack snapshot
+        snapshot: Utf8PathBuf,
+    },
+    /// Compare two snapshots frame by frame, aligning frames by pc
+    Diff {
+        /// Path to the left snapshot
+        left: Utf8PathBuf,
+        /// Path to the right snapshot
+        right: Utf8PathBuf,
+        /// Read the left side as a v1 file, or a directory of v1 files (one per frame)
+        #[arg(long)]
+        left_v1: bool,
+        /// Read the right side as a v1 file, or a directory of v1 files (one per frame)
+        #[arg(long)]
+        right_v1: bool,
+        /// Output in JSON format
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Report how many bytes per checkpoint dead and recomputable values account for
+    Stats {
+        /// Path to input WASM file
+        path: Utf8PathBuf,
+        /// Output in JSON format
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Fill in the types of a raw protobuf snapshot for restore
+    RestorePrep {
+        /// Path to the wasm module the snapshot was taken from
+        wasm: Utf8PathBuf,
+        /// Path to the stack tables created with `create --v2`
+        table: Utf8PathBuf,
+        /// Path to the CallStack snapshot
+        snapshot: Utf8PathBuf,
+        /// Output file or directory (`-` for stdout). Defaults to the current directory
+        #[arg(short, long)]
+        output: Option<Utf8PathBuf>,
+    },
+    /// Translate a protobuf snapshot between the classic and fast interpreter layouts
+    Translate {
+        /// Path to the wasm module the snapshot was taken from
+        wasm: Utf8PathBuf,
+        /// Path to the CallStack snapshot
+        snapshot: Utf8PathBuf,
+        /// Layout of the input snapshot
+        #[arg(long, value_enum)]
+        from: InterpMode,
+        /// Layout of the output snapshot
+        #[arg(long, value_enum)]
+        to: InterpMode,
+        /// Output file or directory (`-` for stdout). Defaults to the current directory
+        #[arg(short, long)]
+        output: Option<Utf8PathBuf>,
+    },
+    /// Call the imported `wacret.checkpoint_poll(fidx, offset)` at every safepoint of a module
+    Instrument {
+        /// Path to input WASM file
+        path: Utf8PathBuf,
+        /// Where to poll: `loops`, `calls` or `all`
+        #[arg(long, default_value = "all")]
+        safepoints: SafepointPolicy,
+        /// Output directory for the instrumented module, its stack tables and the offset map.
+        /// Defaults to the current directory
+        #[arg(short, long)]
+        output: Option<Utf8PathBuf>,
+    },
+    /// Rewrite a module so it can unwind its call stack into linear memory and rewind it (Asyncify style)
+    Transform {
+        /// Path to input WASM file
+        path: Utf8PathBuf,
+        /// Output file or directory (`-` for stdout). Defaults to the current directory
+        #[arg(short, long)]
+        output: Option<Utf8PathBuf>,
+    },
+    /// Rewrite stack tables or a protobuf snapshot of the original binary for an instrumented one
+    Remap {
+        /// Path to the offset map written by `insert`
+        map: Utf8PathBuf,
+        /// Path to the stack tables or CallStack snapshot taken on the original binary
+        input: Utf8PathBuf,
+        /// Path to the instrumented wasm module
+        wasm: Utf8PathBuf,
+        /// Output file or directory (`-` for stdout). Defaults to the current directory
+        #[arg(short, long)]
+        output: Option<Utf8PathBuf>,
+    },
 }
 
 #[derive(Debug, Parser)]
@@ -71,6 +198,18 @@ struct CreateArgs {
     /// Set offset before execution
     #[arg(long)]
     before_execution: bool,
+
+    /// Write the random-access binary layout instead of msgpack (v2 only)
+    #[arg(long, requires = "v2")]
+    binary: bool,
+
+    /// Only emit entries for safepoints: `loops`, `calls` or `all` (v2 only)
+    #[arg(long, requires = "v2")]
+    safepoints: Option<SafepointPolicy>,
+
+    /// Output file or directory (`-` for stdout). Defaults to the current directory
+    #[arg(short, long)]
+    output: Option<Utf8PathBuf>,
 }
 
 
@@ -81,33 +220,51 @@ fn main() {
     match cli.subcommand {
         SubCommands::Create(args) => {
             let path = args.path;
+            let output = Output::new(args.output);
             if args.v2 {
-                let result = create_table_v2::create_table_v2(path, args.before_execution);
+                let result = create_table_v2::create_table_v2(path, output, args.before_execution, args.binary, args.safepoints);
                 match result {
                     Ok(_) => log::info!("Success to create the type stack tables"),
-                    Err(err) => log::error!("Failed to create the type stack table, {}", err)
+                    Err(err) => {
+                        log::error!("Failed to create the type stack table, {}", err);
+                        std::process::exit(1);
+                    }
                 }
             } else {
-                let result = create_table::create_table(path);
+                let result = create_table::create_table(path, output);
                 match result {
                     Ok(_) => log::info!("Success to create the type stack tables"),
-                    Err(err) => log::error!("Failed to create the type stack table, {}", err)
+                    Err(err) => {
+                        log::error!("Failed to create the type stack table, {}", err);
+                        std::process::exit(1);
+                    }
                 }
             }
         },
-        SubCommands::Display { .. } => {
-            todo!();
+        SubCommands::Display { path, format, output } => {
+            let result = display::display(path, format, Output::new(output.or(Some(Utf8PathBuf::from("-")))));
+            match result {
+                Ok(_) => log::info!("Success to display"),
+                Err(err) => {
+                    log::error!("Failed to display the stack tables: {:#}", err);
+                    std::process::exit(1);
+                }
+            }
         },
-        SubCommands::View { path, v1, json, merged_stack } => {
+        SubCommands::View { path, v1, json, merged_stack, instance, wasm } => {
             let result = if path.len() == 1 {
                 let single_path = path[0].clone();
-                if v1 {
+                if let (true, Some(wasm)) = (instance, wasm) {
+                    view::view_instance(single_path, wasm)
+                } else if v1 {
                     view::view_v1_format(single_path, json)
                 } else {
                     view::view_protobuf(single_path, merged_stack)
                 }
             } else {
-                if v1 {
+                if instance {
+                    Err(anyhow::anyhow!("--instance takes a single InstanceState file"))
+                } else if v1 {
                     view::view_v1_format_multiple(path, json)
                 } else {
                     log::error!("Protobuf viewing does not support multiple files");
@@ -120,19 +277,109 @@ fn main() {
                 Err(err) => log::error!("Failed to view file(s): {}", err)
             }
         },
-        SubCommands::Insert { input, output, function_index, offset } => {
-            let result = insert::insert_nop(input, output, function_index, offset);
+        SubCommands::Insert { input, output, function_index, offset, map } => {
+            let result = insert::insert_nop(input, output, function_index, offset, map);
             match result {
                 Ok(_) => log::info!("Successfully inserted NOP instruction"),
                 Err(err) => log::error!("Failed to insert NOP instruction: {}", err)
             }
         }
-        // SubCommands::Display { path } => {
-            // let result = display::main(path);
-            // match result {
-            //     Ok(_) => log::info!("Success to display"),
-            //     Err(err) => log::error!("Failed to display"),
-            // }
-        // },
+        SubCommands::Verify { path, v1 } => {
+            let result = verify::verify(path, v1);
+            match result {
+                Ok(_) => log::info!("Stack tables match the validator"),
+                Err(err) => {
+                    log::error!("Failed to verify the stack tables: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        SubCommands::VerifyLabels { wasm, table, snapshot } => {
+            let result = verify::verify_labels(wasm, table, snapshot);
+            match result {
+                Ok(_) => log::info!("Label stacks match the stack tables"),
+                Err(err) => {
+                    log::error!("Failed to verify the label stacks: {:#}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        SubCommands::CheckSnapshot { wasm, snapshot } => {
+            let result = check_snapshot::check_snapshot(wasm, snapshot);
+            match result {
+                Ok(_) => log::info!("The snapshot fits the module"),
+                Err(err) => {
+                    log::error!("Failed to check the snapshot: {:#}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        SubCommands::Diff { left, right, left_v1, right_v1, json } => {
+            let result = diff::diff(left, right, left_v1, right_v1, json);
+            match result {
+                Ok(_) => log::info!("Successfully compared the snapshots"),
+                Err(err) => {
+                    log::error!("Failed to compare the snapshots: {:#}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        SubCommands::Stats { path, json } => {
+            let result = stats::stats(path, json);
+            if let Err(err) = result {
+                log::error!("Failed to compute the stats: {:#}", err);
+                std::process::exit(1);
+            }
+        }
+        SubCommands::RestorePrep { wasm, table, snapshot, output } => {
+            let result = restore_prep::restore_prep(wasm, table, snapshot, Output::new(output));
+            match result {
+                Ok(_) => log::info!("Successfully typed the snapshot"),
+                Err(err) => {
+                    log::error!("Failed to type the snapshot: {:#}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        SubCommands::Translate { wasm, snapshot, from, to, output } => {
+            let result = translate::translate(wasm, snapshot, from, to, Output::new(output));
+            match result {
+                Ok(_) => log::info!("Successfully translated the snapshot"),
+                Err(err) => {
+                    log::error!("Failed to translate the snapshot: {:#}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        SubCommands::Instrument { path, safepoints, output } => {
+            let result = instrument::instrument(path, safepoints, Output::new(output));
+            match result {
+                Ok(_) => log::info!("Successfully instrumented the module"),
+                Err(err) => {
+                    log::error!("Failed to instrument the module: {:#}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        SubCommands::Transform { path, output } => {
+            let result = transform::transform(path, Output::new(output));
+            match result {
+                Ok(_) => log::info!("Successfully transformed the module"),
+                Err(err) => {
+                    log::error!("Failed to transform the module: {:#}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        SubCommands::Remap { map, input, wasm, output } => {
+            let result = remap::remap(map, input, wasm, Output::new(output));
+            match result {
+                Ok(_) => log::info!("Successfully remapped the input"),
+                Err(err) => {
+                    log::error!("Failed to remap the input: {:#}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,71 @@
+;; Every threads proposal operator, one expression each.
+(module
+  (memory 1 1 shared)
+  (func (export "atomics") (param $a i32) (param $l i64)
+    (drop (memory.atomic.notify (local.get $a) (local.get $a)))
+    (drop (memory.atomic.wait32 (local.get $a) (local.get $a) (local.get $l)))
+    (drop (memory.atomic.wait64 (local.get $a) (local.get $l) (local.get $l)))
+    (atomic.fence)
+    (drop (i32.atomic.load (local.get $a)))
+    (drop (i64.atomic.load (local.get $a)))
+    (drop (i32.atomic.load8_u (local.get $a)))
+    (drop (i32.atomic.load16_u (local.get $a)))
+    (drop (i64.atomic.load8_u (local.get $a)))
+    (drop (i64.atomic.load16_u (local.get $a)))
+    (drop (i64.atomic.load32_u (local.get $a)))
+    (i32.atomic.store (local.get $a) (local.get $a))
+    (i64.atomic.store (local.get $a) (local.get $l))
+    (i32.atomic.store8 (local.get $a) (local.get $a))
+    (i32.atomic.store16 (local.get $a) (local.get $a))
+    (i64.atomic.store8 (local.get $a) (local.get $l))
+    (i64.atomic.store16 (local.get $a) (local.get $l))
+    (i64.atomic.store32 (local.get $a) (local.get $l))
+    (drop (i32.atomic.rmw.add (local.get $a) (local.get $a)))
+    (drop (i64.atomic.rmw.add (local.get $a) (local.get $l)))
+    (drop (i32.atomic.rmw8.add_u (local.get $a) (local.get $a)))
+    (drop (i32.atomic.rmw16.add_u (local.get $a) (local.get $a)))
+    (drop (i64.atomic.rmw8.add_u (local.get $a) (local.get $l)))
+    (drop (i64.atomic.rmw16.add_u (local.get $a) (local.get $l)))
+    (drop (i64.atomic.rmw32.add_u (local.get $a) (local.get $l)))
+    (drop (i32.atomic.rmw.sub (local.get $a) (local.get $a)))
+    (drop (i64.atomic.rmw.sub (local.get $a) (local.get $l)))
+    (drop (i32.atomic.rmw8.sub_u (local.get $a) (local.get $a)))
+    (drop (i32.atomic.rmw16.sub_u (local.get $a) (local.get $a)))
+    (drop (i64.atomic.rmw8.sub_u (local.get $a) (local.get $l)))
+    (drop (i64.atomic.rmw16.sub_u (local.get $a) (local.get $l)))
+    (drop (i64.atomic.rmw32.sub_u (local.get $a) (local.get $l)))
+    (drop (i32.atomic.rmw.and (local.get $a) (local.get $a)))
+    (drop (i64.atomic.rmw.and (local.get $a) (local.get $l)))
+    (drop (i32.atomic.rmw8.and_u (local.get $a) (local.get $a)))
+    (drop (i32.atomic.rmw16.and_u (local.get $a) (local.get $a)))
+    (drop (i64.atomic.rmw8.and_u (local.get $a) (local.get $l)))
+    (drop (i64.atomic.rmw16.and_u (local.get $a) (local.get $l)))
+    (drop (i64.atomic.rmw32.and_u (local.get $a) (local.get $l)))
+    (drop (i32.atomic.rmw.or (local.get $a) (local.get $a)))
+    (drop (i64.atomic.rmw.or (local.get $a) (local.get $l)))
+    (drop (i32.atomic.rmw8.or_u (local.get $a) (local.get $a)))
+    (drop (i32.atomic.rmw16.or_u (local.get $a) (local.get $a)))
+    (drop (i64.atomic.rmw8.or_u (local.get $a) (local.get $l)))
+    (drop (i64.atomic.rmw16.or_u (local.get $a) (local.get $l)))
+    (drop (i64.atomic.rmw32.or_u (local.get $a) (local.get $l)))
+    (drop (i32.atomic.rmw.xor (local.get $a) (local.get $a)))
+    (drop (i64.atomic.rmw.xor (local.get $a) (local.get $l)))
+    (drop (i32.atomic.rmw8.xor_u (local.get $a) (local.get $a)))
+    (drop (i32.atomic.rmw16.xor_u (local.get $a) (local.get $a)))
+    (drop (i64.atomic.rmw8.xor_u (local.get $a) (local.get $l)))
+    (drop (i64.atomic.rmw16.xor_u (local.get $a) (local.get $l)))
+    (drop (i64.atomic.rmw32.xor_u (local.get $a) (local.get $l)))
+    (drop (i32.atomic.rmw.xchg (local.get $a) (local.get $a)))
+    (drop (i64.atomic.rmw.xchg (local.get $a) (local.get $l)))
+    (drop (i32.atomic.rmw8.xchg_u (local.get $a) (local.get $a)))
+    (drop (i32.atomic.rmw16.xchg_u (local.get $a) (local.get $a)))
+    (drop (i64.atomic.rmw8.xchg_u (local.get $a) (local.get $l)))
+    (drop (i64.atomic.rmw16.xchg_u (local.get $a) (local.get $l)))
+    (drop (i64.atomic.rmw32.xchg_u (local.get $a) (local.get $l)))
+    (drop (i32.atomic.rmw.cmpxchg (local.get $a) (local.get $a) (local.get $a)))
+    (drop (i64.atomic.rmw.cmpxchg (local.get $a) (local.get $l) (local.get $l)))
+    (drop (i32.atomic.rmw8.cmpxchg_u (local.get $a) (local.get $a) (local.get $a)))
+    (drop (i32.atomic.rmw16.cmpxchg_u (local.get $a) (local.get $a) (local.get $a)))
+    (drop (i64.atomic.rmw8.cmpxchg_u (local.get $a) (local.get $l) (local.get $l)))
+    (drop (i64.atomic.rmw16.cmpxchg_u (local.get $a) (local.get $l) (local.get $l)))
+    (drop (i64.atomic.rmw32.cmpxchg_u (local.get $a) (local.get $l) (local.get $l)))))
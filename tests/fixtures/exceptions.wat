@@ -0,0 +1,57 @@
+;; Exception-handling (legacy and try_table) and tail-call operators.
+(module
+  (type $binop (func (param i32 i64) (result i32)))
+  (import "env" "e" (tag $imported (param f64)))
+  (tag $e (param i32 i64))
+  (tag $empty)
+  (table 1 funcref)
+  (func $callee (type $binop)
+    local.get 0)
+  (func $legacy (param i32) (result i32)
+    i64.const 7
+    try (result i32)
+      local.get 0
+      i64.const 1
+      throw $e
+    catch $e
+      drop
+    catch $imported
+      drop
+      i32.const 2
+    catch_all
+      try
+        throw $empty
+      delegate 0
+      rethrow 0
+    end
+    try (result i32)
+      i32.const 3
+    catch_all
+      i32.const 4
+    end
+    i32.add
+    local.get 0
+    i64.const 5
+    return_call $callee)
+  (func $modern (param i32) (result i32)
+    (local exnref)
+    block $h (result i32 i64 exnref)
+      try_table (result i32) (catch_ref $e $h)
+        local.get 0
+        i64.const 1
+        throw $e
+      end
+      return
+    end
+    local.set 1
+    drop
+    drop
+    local.get 1
+    throw_ref)
+  (func $indirect (param i32) (result i32)
+    f32.const 1
+    drop
+    local.get 0
+    i64.const 9
+    i32.const 0
+    return_call_indirect (type $binop)))
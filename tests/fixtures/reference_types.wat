@@ -0,0 +1,33 @@
+;; Reference types and bulk memory/table operators, one expression each.
+(module
+  (type $sig (func))
+  (memory 1)
+  (table $funcs 2 funcref)
+  (table $hosts 2 externref)
+  (data $d "abcd")
+  (elem $e func $f)
+  (elem declare func $g)
+  (func $f)
+  (func $g (param $a i32) (param $x externref) (result funcref)
+    (local $r funcref)
+    (memory.init $d (local.get $a) (local.get $a) (local.get $a))
+    (data.drop $d)
+    (drop (ref.null func))
+    (drop (ref.null extern))
+    (drop (ref.is_null (local.get $x)))
+    (drop (ref.is_null (ref.func $g)))
+    (local.set $r (ref.func $g))
+    (drop (table.get $funcs (local.get $a)))
+    (drop (table.get $hosts (local.get $a)))
+    (table.set $funcs (local.get $a) (local.get $r))
+    (table.set $hosts (local.get $a) (local.get $x))
+    (drop (table.size $funcs))
+    (drop (table.grow $funcs (ref.null func) (local.get $a)))
+    (drop (table.grow $hosts (local.get $x) (local.get $a)))
+    (table.fill $funcs (local.get $a) (local.get $r) (local.get $a))
+    (table.fill $hosts (local.get $a) (local.get $x) (local.get $a))
+    (table.copy $funcs $funcs (local.get $a) (local.get $a) (local.get $a))
+    (table.init $funcs $e (local.get $a) (local.get $a) (local.get $a))
+    (elem.drop $e)
+    (drop (select (result externref) (local.get $x) (ref.null extern) (local.get $a)))
+    (select (result funcref) (table.get $funcs (local.get $a)) (local.get $r) (local.get $a))))
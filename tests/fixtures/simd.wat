@@ -0,0 +1,260 @@
+;; Every fixed-width SIMD and relaxed-SIMD operator, one expression each.
+(module
+  (memory 1)
+  (func (export "simd") (param $a i32) (param $l i64) (param $f f32) (param $d f64) (param $v v128)
+    (drop (v128.load (local.get $a)))
+    (drop (v128.load8x8_s (local.get $a)))
+    (drop (v128.load8x8_u (local.get $a)))
+    (drop (v128.load16x4_s (local.get $a)))
+    (drop (v128.load16x4_u (local.get $a)))
+    (drop (v128.load32x2_s (local.get $a)))
+    (drop (v128.load32x2_u (local.get $a)))
+    (drop (v128.load8_splat (local.get $a)))
+    (drop (v128.load16_splat (local.get $a)))
+    (drop (v128.load32_splat (local.get $a)))
+    (drop (v128.load64_splat (local.get $a)))
+    (drop (v128.load32_zero (local.get $a)))
+    (drop (v128.load64_zero (local.get $a)))
+    (v128.store (local.get $a) (local.get $v))
+    (drop (v128.load8_lane 0 (local.get $a) (local.get $v)))
+    (drop (v128.load16_lane 0 (local.get $a) (local.get $v)))
+    (drop (v128.load32_lane 0 (local.get $a) (local.get $v)))
+    (drop (v128.load64_lane 0 (local.get $a) (local.get $v)))
+    (v128.store8_lane 0 (local.get $a) (local.get $v))
+    (v128.store16_lane 0 (local.get $a) (local.get $v))
+    (v128.store32_lane 0 (local.get $a) (local.get $v))
+    (v128.store64_lane 0 (local.get $a) (local.get $v))
+    (drop (v128.const i32x4 1 2 3 4))
+    (drop (i8x16.shuffle 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 (local.get $v) (local.get $v)))
+    (drop (i8x16.extract_lane_s 1 (local.get $v)))
+    (drop (i8x16.extract_lane_u 1 (local.get $v)))
+    (drop (i8x16.replace_lane 1 (local.get $v) (local.get $a)))
+    (drop (i16x8.extract_lane_s 1 (local.get $v)))
+    (drop (i16x8.extract_lane_u 1 (local.get $v)))
+    (drop (i16x8.replace_lane 1 (local.get $v) (local.get $a)))
+    (drop (i32x4.extract_lane 1 (local.get $v)))
+    (drop (i32x4.replace_lane 1 (local.get $v) (local.get $a)))
+    (drop (i64x2.extract_lane 1 (local.get $v)))
+    (drop (i64x2.replace_lane 1 (local.get $v) (local.get $l)))
+    (drop (f32x4.extract_lane 1 (local.get $v)))
+    (drop (f32x4.replace_lane 1 (local.get $v) (local.get $f)))
+    (drop (f64x2.extract_lane 1 (local.get $v)))
+    (drop (f64x2.replace_lane 1 (local.get $v) (local.get $d)))
+    (drop (i8x16.swizzle (local.get $v) (local.get $v)))
+    (drop (i8x16.splat (local.get $a)))
+    (drop (i16x8.splat (local.get $a)))
+    (drop (i32x4.splat (local.get $a)))
+    (drop (i64x2.splat (local.get $l)))
+    (drop (f32x4.splat (local.get $f)))
+    (drop (f64x2.splat (local.get $d)))
+    (drop (i8x16.eq (local.get $v) (local.get $v)))
+    (drop (i8x16.ne (local.get $v) (local.get $v)))
+    (drop (i8x16.lt_s (local.get $v) (local.get $v)))
+    (drop (i8x16.lt_u (local.get $v) (local.get $v)))
+    (drop (i8x16.gt_s (local.get $v) (local.get $v)))
+    (drop (i8x16.gt_u (local.get $v) (local.get $v)))
+    (drop (i8x16.le_s (local.get $v) (local.get $v)))
+    (drop (i8x16.le_u (local.get $v) (local.get $v)))
+    (drop (i8x16.ge_s (local.get $v) (local.get $v)))
+    (drop (i8x16.ge_u (local.get $v) (local.get $v)))
+    (drop (i16x8.eq (local.get $v) (local.get $v)))
+    (drop (i16x8.ne (local.get $v) (local.get $v)))
+    (drop (i16x8.lt_s (local.get $v) (local.get $v)))
+    (drop (i16x8.lt_u (local.get $v) (local.get $v)))
+    (drop (i16x8.gt_s (local.get $v) (local.get $v)))
+    (drop (i16x8.gt_u (local.get $v) (local.get $v)))
+    (drop (i16x8.le_s (local.get $v) (local.get $v)))
+    (drop (i16x8.le_u (local.get $v) (local.get $v)))
+    (drop (i16x8.ge_s (local.get $v) (local.get $v)))
+    (drop (i16x8.ge_u (local.get $v) (local.get $v)))
+    (drop (i32x4.eq (local.get $v) (local.get $v)))
+    (drop (i32x4.ne (local.get $v) (local.get $v)))
+    (drop (i32x4.lt_s (local.get $v) (local.get $v)))
+    (drop (i32x4.lt_u (local.get $v) (local.get $v)))
+    (drop (i32x4.gt_s (local.get $v) (local.get $v)))
+    (drop (i32x4.gt_u (local.get $v) (local.get $v)))
+    (drop (i32x4.le_s (local.get $v) (local.get $v)))
+    (drop (i32x4.le_u (local.get $v) (local.get $v)))
+    (drop (i32x4.ge_s (local.get $v) (local.get $v)))
+    (drop (i32x4.ge_u (local.get $v) (local.get $v)))
+    (drop (i64x2.eq (local.get $v) (local.get $v)))
+    (drop (i64x2.ne (local.get $v) (local.get $v)))
+    (drop (i64x2.lt_s (local.get $v) (local.get $v)))
+    (drop (i64x2.gt_s (local.get $v) (local.get $v)))
+    (drop (i64x2.le_s (local.get $v) (local.get $v)))
+    (drop (i64x2.ge_s (local.get $v) (local.get $v)))
+    (drop (f32x4.eq (local.get $v) (local.get $v)))
+    (drop (f32x4.ne (local.get $v) (local.get $v)))
+    (drop (f32x4.lt (local.get $v) (local.get $v)))
+    (drop (f32x4.gt (local.get $v) (local.get $v)))
+    (drop (f32x4.le (local.get $v) (local.get $v)))
+    (drop (f32x4.ge (local.get $v) (local.get $v)))
+    (drop (f64x2.eq (local.get $v) (local.get $v)))
+    (drop (f64x2.ne (local.get $v) (local.get $v)))
+    (drop (f64x2.lt (local.get $v) (local.get $v)))
+    (drop (f64x2.gt (local.get $v) (local.get $v)))
+    (drop (f64x2.le (local.get $v) (local.get $v)))
+    (drop (f64x2.ge (local.get $v) (local.get $v)))
+    (drop (v128.not (local.get $v)))
+    (drop (v128.and (local.get $v) (local.get $v)))
+    (drop (v128.andnot (local.get $v) (local.get $v)))
+    (drop (v128.or (local.get $v) (local.get $v)))
+    (drop (v128.xor (local.get $v) (local.get $v)))
+    (drop (v128.bitselect (local.get $v) (local.get $v) (local.get $v)))
+    (drop (v128.any_true (local.get $v)))
+    (drop (i8x16.abs (local.get $v)))
+    (drop (i8x16.neg (local.get $v)))
+    (drop (i8x16.popcnt (local.get $v)))
+    (drop (i8x16.all_true (local.get $v)))
+    (drop (i8x16.bitmask (local.get $v)))
+    (drop (i8x16.narrow_i16x8_s (local.get $v) (local.get $v)))
+    (drop (i8x16.narrow_i16x8_u (local.get $v) (local.get $v)))
+    (drop (i8x16.shl (local.get $v) (local.get $a)))
+    (drop (i8x16.shr_s (local.get $v) (local.get $a)))
+    (drop (i8x16.shr_u (local.get $v) (local.get $a)))
+    (drop (i8x16.add (local.get $v) (local.get $v)))
+    (drop (i8x16.add_sat_s (local.get $v) (local.get $v)))
+    (drop (i8x16.add_sat_u (local.get $v) (local.get $v)))
+    (drop (i8x16.sub (local.get $v) (local.get $v)))
+    (drop (i8x16.sub_sat_s (local.get $v) (local.get $v)))
+    (drop (i8x16.sub_sat_u (local.get $v) (local.get $v)))
+    (drop (i8x16.min_s (local.get $v) (local.get $v)))
+    (drop (i8x16.min_u (local.get $v) (local.get $v)))
+    (drop (i8x16.max_s (local.get $v) (local.get $v)))
+    (drop (i8x16.max_u (local.get $v) (local.get $v)))
+    (drop (i8x16.avgr_u (local.get $v) (local.get $v)))
+    (drop (i16x8.extadd_pairwise_i8x16_s (local.get $v)))
+    (drop (i16x8.extadd_pairwise_i8x16_u (local.get $v)))
+    (drop (i16x8.abs (local.get $v)))
+    (drop (i16x8.neg (local.get $v)))
+    (drop (i16x8.q15mulr_sat_s (local.get $v) (local.get $v)))
+    (drop (i16x8.all_true (local.get $v)))
+    (drop (i16x8.bitmask (local.get $v)))
+    (drop (i16x8.narrow_i32x4_s (local.get $v) (local.get $v)))
+    (drop (i16x8.narrow_i32x4_u (local.get $v) (local.get $v)))
+    (drop (i16x8.extend_low_i8x16_s (local.get $v)))
+    (drop (i16x8.extend_high_i8x16_s (local.get $v)))
+    (drop (i16x8.extend_low_i8x16_u (local.get $v)))
+    (drop (i16x8.extend_high_i8x16_u (local.get $v)))
+    (drop (i16x8.shl (local.get $v) (local.get $a)))
+    (drop (i16x8.shr_s (local.get $v) (local.get $a)))
+    (drop (i16x8.shr_u (local.get $v) (local.get $a)))
+    (drop (i16x8.add (local.get $v) (local.get $v)))
+    (drop (i16x8.add_sat_s (local.get $v) (local.get $v)))
+    (drop (i16x8.add_sat_u (local.get $v) (local.get $v)))
+    (drop (i16x8.sub (local.get $v) (local.get $v)))
+    (drop (i16x8.sub_sat_s (local.get $v) (local.get $v)))
+    (drop (i16x8.sub_sat_u (local.get $v) (local.get $v)))
+    (drop (i16x8.mul (local.get $v) (local.get $v)))
+    (drop (i16x8.min_s (local.get $v) (local.get $v)))
+    (drop (i16x8.min_u (local.get $v) (local.get $v)))
+    (drop (i16x8.max_s (local.get $v) (local.get $v)))
+    (drop (i16x8.max_u (local.get $v) (local.get $v)))
+    (drop (i16x8.avgr_u (local.get $v) (local.get $v)))
+    (drop (i16x8.extmul_low_i8x16_s (local.get $v) (local.get $v)))
+    (drop (i16x8.extmul_high_i8x16_s (local.get $v) (local.get $v)))
+    (drop (i16x8.extmul_low_i8x16_u (local.get $v) (local.get $v)))
+    (drop (i16x8.extmul_high_i8x16_u (local.get $v) (local.get $v)))
+    (drop (i32x4.extadd_pairwise_i16x8_s (local.get $v)))
+    (drop (i32x4.extadd_pairwise_i16x8_u (local.get $v)))
+    (drop (i32x4.abs (local.get $v)))
+    (drop (i32x4.neg (local.get $v)))
+    (drop (i32x4.all_true (local.get $v)))
+    (drop (i32x4.bitmask (local.get $v)))
+    (drop (i32x4.extend_low_i16x8_s (local.get $v)))
+    (drop (i32x4.extend_high_i16x8_s (local.get $v)))
+    (drop (i32x4.extend_low_i16x8_u (local.get $v)))
+    (drop (i32x4.extend_high_i16x8_u (local.get $v)))
+    (drop (i32x4.shl (local.get $v) (local.get $a)))
+    (drop (i32x4.shr_s (local.get $v) (local.get $a)))
+    (drop (i32x4.shr_u (local.get $v) (local.get $a)))
+    (drop (i32x4.add (local.get $v) (local.get $v)))
+    (drop (i32x4.sub (local.get $v) (local.get $v)))
+    (drop (i32x4.mul (local.get $v) (local.get $v)))
+    (drop (i32x4.min_s (local.get $v) (local.get $v)))
+    (drop (i32x4.min_u (local.get $v) (local.get $v)))
+    (drop (i32x4.max_s (local.get $v) (local.get $v)))
+    (drop (i32x4.max_u (local.get $v) (local.get $v)))
+    (drop (i32x4.dot_i16x8_s (local.get $v) (local.get $v)))
+    (drop (i32x4.extmul_low_i16x8_s (local.get $v) (local.get $v)))
+    (drop (i32x4.extmul_high_i16x8_s (local.get $v) (local.get $v)))
+    (drop (i32x4.extmul_low_i16x8_u (local.get $v) (local.get $v)))
+    (drop (i32x4.extmul_high_i16x8_u (local.get $v) (local.get $v)))
+    (drop (i64x2.abs (local.get $v)))
+    (drop (i64x2.neg (local.get $v)))
+    (drop (i64x2.all_true (local.get $v)))
+    (drop (i64x2.bitmask (local.get $v)))
+    (drop (i64x2.extend_low_i32x4_s (local.get $v)))
+    (drop (i64x2.extend_high_i32x4_s (local.get $v)))
+    (drop (i64x2.extend_low_i32x4_u (local.get $v)))
+    (drop (i64x2.extend_high_i32x4_u (local.get $v)))
+    (drop (i64x2.shl (local.get $v) (local.get $a)))
+    (drop (i64x2.shr_s (local.get $v) (local.get $a)))
+    (drop (i64x2.shr_u (local.get $v) (local.get $a)))
+    (drop (i64x2.add (local.get $v) (local.get $v)))
+    (drop (i64x2.sub (local.get $v) (local.get $v)))
+    (drop (i64x2.mul (local.get $v) (local.get $v)))
+    (drop (i64x2.extmul_low_i32x4_s (local.get $v) (local.get $v)))
+    (drop (i64x2.extmul_high_i32x4_s (local.get $v) (local.get $v)))
+    (drop (i64x2.extmul_low_i32x4_u (local.get $v) (local.get $v)))
+    (drop (i64x2.extmul_high_i32x4_u (local.get $v) (local.get $v)))
+    (drop (f32x4.ceil (local.get $v)))
+    (drop (f32x4.floor (local.get $v)))
+    (drop (f32x4.trunc (local.get $v)))
+    (drop (f32x4.nearest (local.get $v)))
+    (drop (f32x4.abs (local.get $v)))
+    (drop (f32x4.neg (local.get $v)))
+    (drop (f32x4.sqrt (local.get $v)))
+    (drop (f32x4.add (local.get $v) (local.get $v)))
+    (drop (f32x4.sub (local.get $v) (local.get $v)))
+    (drop (f32x4.mul (local.get $v) (local.get $v)))
+    (drop (f32x4.div (local.get $v) (local.get $v)))
+    (drop (f32x4.min (local.get $v) (local.get $v)))
+    (drop (f32x4.max (local.get $v) (local.get $v)))
+    (drop (f32x4.pmin (local.get $v) (local.get $v)))
+    (drop (f32x4.pmax (local.get $v) (local.get $v)))
+    (drop (f64x2.ceil (local.get $v)))
+    (drop (f64x2.floor (local.get $v)))
+    (drop (f64x2.trunc (local.get $v)))
+    (drop (f64x2.nearest (local.get $v)))
+    (drop (f64x2.abs (local.get $v)))
+    (drop (f64x2.neg (local.get $v)))
+    (drop (f64x2.sqrt (local.get $v)))
+    (drop (f64x2.add (local.get $v) (local.get $v)))
+    (drop (f64x2.sub (local.get $v) (local.get $v)))
+    (drop (f64x2.mul (local.get $v) (local.get $v)))
+    (drop (f64x2.div (local.get $v) (local.get $v)))
+    (drop (f64x2.min (local.get $v) (local.get $v)))
+    (drop (f64x2.max (local.get $v) (local.get $v)))
+    (drop (f64x2.pmin (local.get $v) (local.get $v)))
+    (drop (f64x2.pmax (local.get $v) (local.get $v)))
+    (drop (i32x4.trunc_sat_f32x4_s (local.get $v)))
+    (drop (i32x4.trunc_sat_f32x4_u (local.get $v)))
+    (drop (f32x4.convert_i32x4_s (local.get $v)))
+    (drop (f32x4.convert_i32x4_u (local.get $v)))
+    (drop (i32x4.trunc_sat_f64x2_s_zero (local.get $v)))
+    (drop (i32x4.trunc_sat_f64x2_u_zero (local.get $v)))
+    (drop (f64x2.convert_low_i32x4_s (local.get $v)))
+    (drop (f64x2.convert_low_i32x4_u (local.get $v)))
+    (drop (f32x4.demote_f64x2_zero (local.get $v)))
+    (drop (f64x2.promote_low_f32x4 (local.get $v)))
+    (drop (i8x16.relaxed_swizzle (local.get $v) (local.get $v)))
+    (drop (i32x4.relaxed_trunc_f32x4_s (local.get $v)))
+    (drop (i32x4.relaxed_trunc_f32x4_u (local.get $v)))
+    (drop (i32x4.relaxed_trunc_f64x2_s_zero (local.get $v)))
+    (drop (i32x4.relaxed_trunc_f64x2_u_zero (local.get $v)))
+    (drop (f32x4.relaxed_madd (local.get $v) (local.get $v) (local.get $v)))
+    (drop (f32x4.relaxed_nmadd (local.get $v) (local.get $v) (local.get $v)))
+    (drop (f64x2.relaxed_madd (local.get $v) (local.get $v) (local.get $v)))
+    (drop (f64x2.relaxed_nmadd (local.get $v) (local.get $v) (local.get $v)))
+    (drop (i8x16.relaxed_laneselect (local.get $v) (local.get $v) (local.get $v)))
+    (drop (i16x8.relaxed_laneselect (local.get $v) (local.get $v) (local.get $v)))
+    (drop (i32x4.relaxed_laneselect (local.get $v) (local.get $v) (local.get $v)))
+    (drop (i64x2.relaxed_laneselect (local.get $v) (local.get $v) (local.get $v)))
+    (drop (f32x4.relaxed_min (local.get $v) (local.get $v)))
+    (drop (f32x4.relaxed_max (local.get $v) (local.get $v)))
+    (drop (f64x2.relaxed_min (local.get $v) (local.get $v)))
+    (drop (f64x2.relaxed_max (local.get $v) (local.get $v)))
+    (drop (i16x8.relaxed_q15mulr_s (local.get $v) (local.get $v)))
+    (drop (i16x8.relaxed_dot_i8x16_i7x16_s (local.get $v) (local.get $v)))
+    (drop (i32x4.relaxed_dot_i8x16_i7x16_add_s (local.get $v) (local.get $v) (local.get $v)))))